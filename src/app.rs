@@ -1,22 +1,45 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    config::{ConfigManager, ProcliConfig},
-    event::{AppEvent, Event, EventHandler},
-    proc::manager::ProcessManager,
+    config::{ConfigManager, ConfigSource, ProcliConfig, diff_configs},
+    event::{AppEvent, Event, EventHandler, IDLE_TICK_FPS, IdleTracker},
+    proc::{
+        command::shell_snippet,
+        container,
+        feed::{self, FeedCommand, FeedEvent},
+        manager::{Health, ProcessManager},
+        process::{Process, ProcessState},
+        terminate::GRACEFUL_STOP,
+    },
     ui::{
         dashboard::DashboardWidget,
-        state::{Focussable, UiState},
+        state::{DEFAULT_ABSENT_SYMBOL, FocusMove, Focussable, Mode, UiState},
+        theme::Theme,
+        title::{TerminalTitle, title},
     },
+    update,
 };
 use color_eyre::eyre::Result;
 use log::*;
 use ratatui::{
     DefaultTerminal,
-    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    crossterm::{
+        event::{DisableBracketedPaste, EnableBracketedPaste, KeyCode, KeyEvent, KeyModifiers},
+        execute,
+        terminal::{EnterAlternateScreen, enable_raw_mode},
+    },
     prelude::*,
 };
 use tui_logger::TuiWidgetEvent;
+use uuid::Uuid;
+
+/// Where the log panel's lines, process output included, are also written.
+pub const LOG_FILE: &str = "procli.log";
 
 pub struct App {
     pub running: bool,
@@ -24,36 +47,95 @@ pub struct App {
     pub config: ConfigManager,
     pub proc: ProcessManager,
     pub ui_state: UiState,
+    /// Only run these processes (and their dependencies), if not empty.
+    pub only: Vec<String>,
+    pub title: TerminalTitle,
+    idle: IdleTracker,
+    /// Whether power save has slowed the refresh rates.
+    slowed: bool,
+    tick_fps: f64,
+    /// Opened on first copy and kept, as on some platforms the copied text
+    /// only stays available while the clipboard is open.
+    clipboard: Option<arboard::Clipboard>,
+    /// A program to hand the terminal to before the next draw.
+    external: Option<Vec<OsString>>,
+    /// Write the screen to a file after the next draw.
+    dump_screen: bool,
 }
 
 impl App {
-    pub fn new(config_path: PathBuf) -> Result<Self> {
-        let events = EventHandler::new();
+    pub fn new(
+        config: ConfigSource,
+        tick_fps: f64,
+        only: Vec<String>,
+        watch: bool,
+    ) -> Result<Self> {
+        Self::with_events(EventHandler::new(tick_fps), config, tick_fps, only, watch)
+    }
+
+    /// An app that runs without the TUI and quits when signalled.
+    pub fn headless(
+        config: ConfigSource,
+        tick_fps: f64,
+        only: Vec<String>,
+        watch: bool,
+    ) -> Result<Self> {
+        let events = EventHandler::headless(tick_fps);
+        Self::with_events(events, config, tick_fps, only, watch)
+    }
+
+    fn with_events(
+        events: EventHandler,
+        source: ConfigSource,
+        tick_fps: f64,
+        only: Vec<String>,
+        watch: bool,
+    ) -> Result<Self> {
         let sender1 = events.clone_sender();
         let sender2 = events.clone_sender();
+        let config = ConfigManager::new(source, sender1, watch)?;
+        config.current().only(&only)?;
         Ok(Self {
             running: true,
             events,
-            config: ConfigManager::new(config_path, sender1)?,
+            config,
             proc: ProcessManager::new(sender2),
-            ui_state: UiState::default(),
+            ui_state: UiState::with_tick_fps(tick_fps),
+            only,
+            title: TerminalTitle::default(),
+            idle: IdleTracker::new(Instant::now()),
+            slowed: false,
+            tick_fps,
+            clipboard: None,
+            external: None,
+            dump_screen: false,
         })
     }
 
     /// Run the application's main loop.
     pub async fn run(&mut self, mut terminal: DefaultTerminal) -> Result<()> {
-        if let Some(err) = self.start(&self.config.current()).err() {
+        if let Some(err) = self.start_up().await.err() {
             error!(target: "App", "Failed to start: {}", err);
         }
+        set_bracketed_paste(true);
         while self.running {
-            terminal.draw(|frame| {
+            let visible = self.ui_state.visible(&self.proc.processes).len();
+            self.ui_state.update_procs(visible);
+            let frame = terminal.draw(|frame| {
                 DashboardWidget {
                     ui: &self.ui_state,
                     processes: &self.proc.processes,
+                    self_stats: self.proc.self_stats,
+                    totals: &self.proc.totals,
+                    totals_max: self.proc.totals_max,
                     config: &self.config.current(),
                 }
                 .render(frame.area(), frame.buffer_mut())
             })?;
+            if std::mem::take(&mut self.dump_screen) {
+                write_screen(frame.buffer);
+            }
+            self.update_title();
 
             match self.events.next().await? {
                 Event::Tick => self.tick(),
@@ -61,31 +143,238 @@ impl App {
                     crossterm::event::Event::Key(key_event)
                         if key_event.kind == crossterm::event::KeyEventKind::Press =>
                     {
+                        self.idle.poke(Instant::now());
                         self.handle_key_events(key_event)?
                     }
+                    // The grid keeps its columns and rows, so there is
+                    // nothing to clamp; the next draw lays out the new size.
+                    crossterm::event::Event::Resize(..) => {}
+                    crossterm::event::Event::Paste(text) => {
+                        self.idle.poke(Instant::now());
+                        self.handle_paste(text)
+                    }
                     _ => {}
                 },
-                Event::App(app_event) => match app_event {
-                    AppEvent::Reload => self.reload_config(),
-                    AppEvent::Quit => self.quit(),
-                    AppEvent::ProcessDied(id, status) => self.proc.process_died(id, status),
-                    AppEvent::StatsRefresh => self.proc.tick(),
-                },
+                Event::App(app_event) => self.handle_app_event(app_event),
             }
+            self.proc.publish_state_changes();
+            self.update_pace();
+            if let Some(command) = self.external.take() {
+                self.run_external(&mut terminal, command).await?;
+            }
+        }
+        set_bracketed_paste(false);
+        Ok(())
+    }
+
+    /// Draw and log in colour or not, as `--color` decided.
+    pub fn set_color(&mut self, color: bool) {
+        self.proc.color = color;
+        if !color {
+            self.ui_state.theme = Theme::monochrome();
+        }
+    }
+
+    /// Leave the TUI, run `command` in the terminal until it exits, then come
+    /// back. Processes carry on meanwhile; their events wait in the queue.
+    async fn run_external(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        command: Vec<OsString>,
+    ) -> Result<()> {
+        let Some((program, args)) = command.split_first() else {
+            return Ok(());
+        };
+        self.events.set_read_terminal(false).await;
+        set_bracketed_paste(false);
+        ratatui::restore();
+        let status = tokio::process::Command::new(program)
+            .args(args)
+            .status()
+            .await;
+        enable_raw_mode()?;
+        execute!(std::io::stdout(), EnterAlternateScreen)?;
+        set_bracketed_paste(true);
+        terminal.clear()?;
+        self.events.set_read_terminal(true).await;
+        let program = program.to_string_lossy();
+        match status {
+            Ok(status) if !status.success() => {
+                warn!(target: "App", "{} exited with {}", program, status)
+            }
+            Ok(_) => {}
+            Err(err) => warn!(target: "App", "Can't run {}: {}", program, err),
         }
         Ok(())
     }
 
+    /// Supervise everything without the TUI until asked to quit, then stop
+    /// every process.
+    pub async fn run_headless(&mut self) -> Result<()> {
+        self.start_up().await?;
+        while self.running {
+            if let Event::App(app_event) = self.events.next().await? {
+                self.handle_app_event(app_event);
+            }
+            self.proc.publish_state_changes();
+        }
+        self.shutdown().await
+    }
+
+    /// Start everything without the TUI and wait until every process is
+    /// running, one fails, or the timeout elapses. Returns whether all of
+    /// them became healthy.
+    pub async fn wait_healthy(&mut self, timeout: Duration) -> Result<bool> {
+        self.start_up().await?;
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        let healthy = loop {
+            match self.proc.health() {
+                Health::Healthy => break true,
+                Health::Unhealthy => break false,
+                Health::Pending => {}
+            }
+            if !self.running {
+                break false;
+            }
+            tokio::select! {
+                _ = &mut deadline => {
+                    warn!(target: "App", "Timed out waiting for processes to become healthy");
+                    break false;
+                }
+                event = self.events.next() => {
+                    if let Event::App(app_event) = event? {
+                        self.handle_app_event(app_event);
+                    }
+                }
+            }
+        };
+        Ok(healthy)
+    }
+
+    /// One line per process describing its current state.
+    pub fn status_summary(&self) -> Vec<String> {
+        self.proc
+            .processes
+            .iter()
+            .map(|p| {
+                let state = match &p.state {
+                    ProcessState::Starting => "starting".to_string(),
+                    ProcessState::Running => "running".to_string(),
+                    ProcessState::Killing(_) => "stopping".to_string(),
+                    ProcessState::Stopped(_, status) => format!("stopped ({status})"),
+                    ProcessState::Completed(status) => format!("completed ({status})"),
+                    ProcessState::Failed(reason) => format!("failed to start: {reason}"),
+                };
+                format!("{}: {}", p.name, state)
+            })
+            .collect()
+    }
+
+    /// Stop every process and wait a while for them to exit.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.proc.stop_all();
+        let deadline = tokio::time::sleep(GRACEFUL_STOP + Duration::from_secs(1));
+        tokio::pin!(deadline);
+        while self.proc.processes.iter().any(|p| p.is_alive()) {
+            tokio::select! {
+                _ = &mut deadline => break,
+                event = self.events.next() => {
+                    if let Event::App(AppEvent::ProcessDied(id, status)) = event? {
+                        self.proc.process_died(id, status);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_app_event(&mut self, app_event: AppEvent) {
+        match app_event {
+            AppEvent::Reload => self.reload_config(),
+            AppEvent::Quit => self.quit(),
+            AppEvent::ProcessDied(id, status) => self.process_died(id, status),
+            AppEvent::StatsRefresh => self.proc.tick(),
+            AppEvent::StopAll => self.proc.stop_all(),
+            AppEvent::RestartAll => self.proc.restart_all(),
+            AppEvent::StartStopped => {
+                self.proc.start_stopped();
+            }
+            AppEvent::HealthChanged(id, status) => self.proc.health_changed(id, status),
+            AppEvent::ContainerChosen(id, launch) => self.proc.container_chosen(id, launch),
+            AppEvent::ResetAndRestart(name) => {
+                if let Err(err) = self.proc.reset_and_restart(&name) {
+                    warn!(target: &name, "Can't reset restarts: {}", err);
+                }
+            }
+            AppEvent::Control(command, reply) => {
+                let name = command.name().to_string();
+                let result = match &command {
+                    FeedCommand::Stop { name } => self.proc.stop(name),
+                    FeedCommand::Start { name } => self.proc.start(name),
+                };
+                let (state, error) = match result {
+                    Ok(state) => (Some(state), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+                // The client may have gone already.
+                let _ = reply.send(FeedEvent::Reply { name, state, error });
+            }
+        }
+    }
+
     /// Handles the key events and updates the state of [`App`].
     pub fn handle_key_events(&mut self, key_event: KeyEvent) -> Result<()> {
+        if self.ui_state.searching {
+            self.handle_search_key(key_event);
+            return Ok(());
+        }
+        if self.ui_state.attached {
+            self.handle_input_key(key_event);
+            return Ok(());
+        }
         match key_event.code {
             KeyCode::Char('q') => self.events.send(AppEvent::Quit),
             KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
                 self.events.send(AppEvent::Quit)
             }
             KeyCode::Char('r') => self.events.send(AppEvent::Reload),
+            KeyCode::Char('R') => self.events.send(AppEvent::RestartAll),
+            KeyCode::Char('S') => self.events.send(AppEvent::StopAll),
+            KeyCode::Char('s') => self.events.send(AppEvent::StartStopped),
             KeyCode::Char('d') => self.ui_state.toggle_debug(),
+            KeyCode::Char('i') => self.ui_state.toggle_interpolate(),
+            KeyCode::Char('t') => self.ui_state.toggle_totals(),
+            KeyCode::Char('L') => self.ui_state.toggle_list(),
+            KeyCode::Char('/') => self.ui_state.start_search(),
+            KeyCode::Char('a') => self.ui_state.attach(),
+            KeyCode::Char('c') => self.copy_command(),
+            KeyCode::Char('e') => self.open_directory(),
+            KeyCode::Char('p') => self.open_log(),
+            KeyCode::Char('D') => self.dump_screen = true,
+            KeyCode::Char('v') => self.ui_state.cycle_kind_filter(),
+            KeyCode::Char('x') => {
+                if let Some(name) = self.focused().map(|p| p.name.clone()) {
+                    self.events.send(AppEvent::ResetAndRestart(name));
+                }
+            }
+            KeyCode::Delete | KeyCode::Backspace => {
+                if let Some(name) = self.focused().map(|p| p.name.clone())
+                    && let Err(err) = self.proc.dismiss(&name)
+                {
+                    warn!(target: &name, "Can't dismiss: {}", err);
+                }
+            }
             KeyCode::Enter | KeyCode::Char(' ') => self.ui_state.toggle_spotlight(),
+            KeyCode::Char(c @ '1'..='9') => self.ui_state.focus_process(c as usize - '1' as usize),
+            KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down
+                if key_event.modifiers == KeyModifiers::SHIFT
+                    && matches!(self.ui_state.focus, Some(Focussable::Process(_))) =>
+            {
+                if let Some(dir) = direction(key_event.code) {
+                    self.ui_state.move_focused(&self.proc.processes, dir);
+                }
+            }
             KeyCode::Tab => {
                 if key_event.modifiers == KeyModifiers::SHIFT {
                     self.ui_state.focus_prev();
@@ -97,9 +386,18 @@ impl App {
             _ => match self.ui_state.focus {
                 Some(Focussable::Logs) => {
                     self.ui_state.logger_state.transition(match key_event.code {
-                        KeyCode::Esc => TuiWidgetEvent::EscapeKey,
-                        KeyCode::PageUp => TuiWidgetEvent::PrevPageKey,
-                        KeyCode::PageDown => TuiWidgetEvent::NextPageKey,
+                        KeyCode::Esc | KeyCode::End => {
+                            self.ui_state.log_follow_latest();
+                            return Ok(());
+                        }
+                        KeyCode::PageUp => {
+                            self.ui_state.log_page_up();
+                            return Ok(());
+                        }
+                        KeyCode::PageDown => {
+                            self.ui_state.log_page_down();
+                            return Ok(());
+                        }
                         KeyCode::Left => TuiWidgetEvent::LeftKey,
                         KeyCode::Right => TuiWidgetEvent::RightKey,
                         KeyCode::Up => TuiWidgetEvent::UpKey,
@@ -109,17 +407,191 @@ impl App {
                         KeyCode::Char('f') => TuiWidgetEvent::FocusKey,
                         KeyCode::Char('+') => TuiWidgetEvent::PlusKey,
                         KeyCode::Char('-') => TuiWidgetEvent::MinusKey,
-                        _ => return Ok(()),
+                        _ => {
+                            self.move_focus(key_event.code);
+                            return Ok(());
+                        }
                     });
                 }
-                Some(Focussable::Process(_)) => {}
-                Some(Focussable::Debug) => {}
-                None => {}
+                Some(Focussable::Totals) => match key_event.code {
+                    KeyCode::Char('+') => self.ui_state.widen_totals_window(),
+                    KeyCode::Char('-') => self.ui_state.narrow_totals_window(),
+                    _ => self.move_focus(key_event.code),
+                },
+                Some(Focussable::Process(_)) | Some(Focussable::Debug) | None => {
+                    self.move_focus(key_event.code)
+                }
             },
         }
         Ok(())
     }
 
+    /// Hand a death to the manager, then turn to the process if it crashed,
+    /// rather than being stopped or completing.
+    fn process_died(&mut self, id: Uuid, status: ExitStatus) {
+        let name = self
+            .proc
+            .processes
+            .iter()
+            .find(|p| p.uuid == id && !matches!(p.state, ProcessState::Killing(_)))
+            .map(|p| p.name.clone());
+        self.proc.process_died(id, status);
+        let crashed = name.filter(|name| {
+            self.proc
+                .processes
+                .iter()
+                .any(|p| p.name == *name && matches!(p.state, ProcessState::Stopped(_, _)))
+        });
+        if let Some(name) = crashed {
+            self.ui_state.focus_crashed(&self.proc.processes, &name);
+        }
+    }
+
+    /// The focused process, in the grid or the spotlight.
+    fn focused(&self) -> Option<&Process> {
+        match self.ui_state.focus {
+            Some(Focussable::Process(i)) => {
+                self.ui_state.visible(&self.proc.processes).get(i).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// The process shown in the spotlight, if any.
+    fn spotlit(&self) -> Option<&Process> {
+        match (&self.ui_state.focus, &self.ui_state.mode) {
+            (Some(Focussable::Process(i)), Mode::Spotlight) => {
+                self.ui_state.visible(&self.proc.processes).get(*i).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// Copy the spotlit process's command, as a shell snippet, so it can be
+    /// run by hand.
+    fn copy_command(&mut self) {
+        let Some(proc) = self.spotlit() else {
+            return;
+        };
+        let name = proc.name.clone();
+        let snippet = shell_snippet(&proc.cmd);
+        if self.clipboard.is_none() {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(err) => {
+                    warn!(target: "App", "No clipboard available: {}", err);
+                    return;
+                }
+            }
+        }
+        let copied = self.clipboard.as_mut().map(|c| c.set_text(snippet));
+        match copied {
+            Some(Ok(())) => info!(target: &name, "Copied command to the clipboard"),
+            Some(Err(err)) => warn!(target: "App", "Can't copy to the clipboard: {}", err),
+            None => {}
+        }
+    }
+
+    /// Open the spotlit process's working directory in `$EDITOR`.
+    fn open_directory(&mut self) {
+        let Some(proc) = self.spotlit() else {
+            return;
+        };
+        let directory = proc.directory.clone().unwrap_or_else(|| PathBuf::from("."));
+        self.external = external_command("EDITOR", "vi", &directory);
+    }
+
+    /// Open the log file, which has the spotlit process's output among
+    /// everything else, in `$PAGER`.
+    fn open_log(&mut self) {
+        if self.spotlit().is_some() {
+            self.external = external_command("PAGER", "less", Path::new(LOG_FILE));
+        }
+    }
+
+    /// Keys typed while attached to the spotlit process's stdin. `Esc`
+    /// detaches and `Ctrl-D` closes stdin.
+    fn handle_input_key(&mut self, key_event: KeyEvent) {
+        let name = self.spotlit().map(|p| p.name.clone());
+        let Some(proc) =
+            name.and_then(|name| self.proc.processes.iter_mut().find(|p| p.name == name))
+        else {
+            self.ui_state.detach();
+            return;
+        };
+        let ctrl = key_event.modifiers == KeyModifiers::CONTROL;
+        let bytes = match key_event.code {
+            KeyCode::Esc => {
+                self.ui_state.detach();
+                return;
+            }
+            KeyCode::Char('d' | 'D') if ctrl => {
+                info!(target: &proc.name, "Closing stdin");
+                proc.close_input();
+                self.ui_state.detach();
+                return;
+            }
+            KeyCode::Char('c' | 'C') if ctrl => {
+                self.events.send(AppEvent::Quit);
+                return;
+            }
+            KeyCode::Enter => b"\n".to_vec(),
+            KeyCode::Tab => b"\t".to_vec(),
+            KeyCode::Backspace => b"\x7f".to_vec(),
+            KeyCode::Char(c) => c.to_string().into_bytes(),
+            _ => return,
+        };
+        if !proc.send_input(bytes) {
+            warn!(target: &proc.name, "Stdin is closed, detaching");
+            self.ui_state.detach();
+        }
+    }
+
+    /// Pasted text goes to the attached process's stdin, with line endings
+    /// as `Enter` sends them, or into the search box. Otherwise it is
+    /// ignored.
+    fn handle_paste(&mut self, text: String) {
+        if self.ui_state.attached {
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            let sent = self
+                .spotlit()
+                .map(|p| (p.name.clone(), p.send_input(text.into_bytes())));
+            match sent {
+                Some((_, true)) => {}
+                Some((name, false)) => {
+                    warn!(target: &name, "Stdin is closed, detaching");
+                    self.ui_state.detach();
+                }
+                None => self.ui_state.detach(),
+            }
+        } else if self.ui_state.searching {
+            for c in text.chars().filter(|c| !c.is_control()) {
+                self.ui_state.search_push(c);
+            }
+        }
+    }
+
+    /// Keys typed into the search box.
+    fn handle_search_key(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Esc => self.ui_state.clear_search(),
+            KeyCode::Enter => self.ui_state.finish_search(),
+            KeyCode::Backspace => self.ui_state.search_pop(),
+            KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.events.send(AppEvent::Quit)
+            }
+            KeyCode::Char(c) => self.ui_state.search_push(c),
+            _ => {}
+        }
+    }
+
+    /// Move focus around the grid with vim keys or arrows.
+    fn move_focus(&mut self, code: KeyCode) {
+        if let Some(dir) = direction(code) {
+            self.ui_state.focus_move(dir);
+        }
+    }
+
     /// Handles the tick event of the terminal.
     ///
     /// The tick event is where you can update the state of your application with any logic that
@@ -128,6 +600,36 @@ impl App {
         self.ui_state.tick();
     }
 
+    /// In power save mode, slow the tick and stats rates while nothing is
+    /// happening and speed them back up as soon as something does.
+    fn update_pace(&mut self) {
+        let busy = self.proc.processes.iter().any(|p| p.is_changing());
+        let output = self.proc.processes.iter().map(|p| p.output.total()).sum();
+        let idle = self.idle.is_idle(Instant::now(), busy, output);
+        let slow = idle && self.config.current().power_save;
+        if slow == self.slowed {
+            return;
+        }
+        self.slowed = slow;
+        debug!(target: "App", "Power save {}", if slow { "on" } else { "off" });
+        let fps = if slow { IDLE_TICK_FPS } else { self.tick_fps };
+        self.events.set_tick_fps(fps);
+        self.ui_state.tick_fps = fps;
+        self.proc.set_idle(slow);
+    }
+
+    /// Reflect the process states in the terminal title, if enabled.
+    fn update_title(&mut self) {
+        let result = if self.config.current().set_terminal_title {
+            self.title.update(title(self.proc.current()))
+        } else {
+            self.title.reset()
+        };
+        if let Err(err) = result {
+            debug!(target: "App", "Can't set the terminal title: {}", err);
+        }
+    }
+
     /// Set running to false to quit the application.
     fn quit(&mut self) {
         self.running = false;
@@ -135,8 +637,16 @@ impl App {
 
     fn reload_config(&mut self) {
         debug!(target:"App", "Reload!");
+        let previous = self.config.current();
         match self.config.reload() {
             Ok(config) => {
+                let diff = diff_configs(&previous, &config);
+                if diff.is_empty() {
+                    info!(target: "Config", "Reloaded, no changes");
+                }
+                for line in diff.describe() {
+                    info!(target: "Config", "Reloaded, {}", line);
+                }
                 if let Some(e) = self.start(&config).err() {
                     error!(target: "App", "{}", e);
                 }
@@ -145,15 +655,70 @@ impl App {
         }
     }
 
+    /// Start everything in the current config, first removing containers an
+    /// earlier run left behind if the config asks for it.
+    async fn start_up(&mut self) -> Result<()> {
+        let config = self.config.current();
+        if config.check_updates {
+            let url = config.release_url.as_deref();
+            update::spawn(url.unwrap_or(update::DEFAULT_RELEASE_URL).to_string());
+        }
+        if config.cleanup_orphans {
+            let selected = config.only(&self.only)?;
+            // Reused containers are kept on purpose.
+            let services = selected
+                .services
+                .iter()
+                .filter(|s| s.image.is_some() && !s.reuse);
+            let stubs = selected
+                .stubs
+                .iter()
+                .filter(|s| s.image.is_some() && !s.reuse);
+            let names: Vec<String> = services
+                .map(|s| s.name.clone())
+                .chain(stubs.map(|s| s.name.clone()))
+                .collect();
+            if !names.is_empty()
+                && let Err(err) = container::remove_orphans(&names).await
+            {
+                warn!(target: "App", "Can't clean up orphaned containers: {}", err);
+            }
+        }
+        self.start(&config)
+    }
+
     /// Start services, stubs, and agents from the given configuration.
     /// Changes to the service lineup use the names as unique keys but
     /// let the process manager decide whether to restart or not.
+    ///
+    /// Everything else about the view is left alone, and focus stays on the
+    /// same process even if the reload moves it.
     fn start(&mut self, config: &ProcliConfig) -> Result<()> {
+        let config = &config.only(&self.only)?;
+        let focused = self.focused().map(|p| p.name.clone());
+        self.proc.set_statsd(config.statsd.as_deref());
+        self.proc.set_feed(feed::Sockets {
+            events: config.event_socket.clone(),
+            control: config.control_socket.clone(),
+        });
+        self.ui_state.chart_style = config.chart_style;
+        self.ui_state.absent_symbol = config
+            .absent_symbol
+            .clone()
+            .unwrap_or(DEFAULT_ABSENT_SYMBOL.to_string());
+        self.ui_state.absent_as_zero = config.absent_as_zero;
+        self.ui_state.card_height = config.card_height;
+        self.ui_state.focus_on_crash = config.focus_on_crash;
+        self.proc.cpu_mode = config.cpu_mode;
+        self.proc.min_restart_interval = Duration::from_millis(config.min_restart_interval_ms);
+        self.ui_state.cpu_mode = config.cpu_mode;
+        self.ui_state.cores = self.proc.cores;
+        self.proc.on_remove = config.on_remove;
         let removals: Vec<String> = self
             .proc
             .processes
             .iter()
-            .filter(|proc| config.contains(&proc.name))
+            .filter(|proc| !config.contains(&proc.name) && !proc.removed)
             .map(|proc| proc.name.clone())
             .collect();
         for name in removals {
@@ -162,17 +727,282 @@ impl App {
         }
         for stub in config.stubs.iter() {
             debug!("Start stub {}", stub.name);
-            self.proc.upsert(stub)?;
+            if let Err(err) = self.proc.upsert(stub) {
+                error!(target: &stub.name, "Failed to start: {}", err);
+            }
         }
         for svc in config.services.iter() {
             debug!("Start service {}", svc.name);
-            self.proc.upsert(svc)?;
+            if let Err(err) = self.proc.upsert(svc) {
+                error!(target: &svc.name, "Failed to start: {}", err);
+            }
         }
         for agent in config.agents.iter() {
             debug!("Start agent {}", agent.name);
         }
 
-        self.ui_state.update_procs(self.proc.processes.len());
+        let visible = self.ui_state.visible(&self.proc.processes);
+        let refocus = focused.and_then(|name| visible.iter().position(|p| p.name == name));
+        let count = visible.len();
+        self.ui_state.update_procs(count);
+        if let Some(i) = refocus {
+            self.ui_state.focus = Some(Focussable::Process(i));
+        }
         Ok(())
     }
 }
+
+/// Have pastes arrive whole, as one event, rather than as keys. Terminals
+/// without it just send the keys as before.
+pub fn set_bracketed_paste(on: bool) {
+    let _ = if on {
+        execute!(std::io::stdout(), EnableBracketedPaste)
+    } else {
+        execute!(std::io::stdout(), DisableBracketedPaste)
+    };
+}
+
+/// The screen as plain text, one line per row with trailing blanks trimmed.
+fn screen_text(buf: &Buffer) -> String {
+    let mut text = String::new();
+    for y in buf.area.top()..buf.area.bottom() {
+        let mut line = String::new();
+        let mut skip = 0;
+        for x in buf.area.left()..buf.area.right() {
+            // The cells a wide character spills into hold nothing of their own.
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let symbol = buf[(x, y)].symbol();
+            skip = Span::raw(symbol).width().saturating_sub(1);
+            line.push_str(symbol);
+        }
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// Write the screen to a file named for the time, for bug reports about
+/// what's drawn.
+fn write_screen(buf: &Buffer) {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = format!("procli-screen-{millis}.txt");
+    match std::fs::write(&path, screen_text(buf)) {
+        Ok(()) => info!(target: "App", "Screen written to {}", path),
+        Err(err) => warn!(target: "App", "Can't write {}: {}", path, err),
+    }
+}
+
+/// The way a vim key or arrow points.
+fn direction(code: KeyCode) -> Option<FocusMove> {
+    match code {
+        KeyCode::Char('h') | KeyCode::Left => Some(FocusMove::Left),
+        KeyCode::Char('l') | KeyCode::Right => Some(FocusMove::Right),
+        KeyCode::Char('k') | KeyCode::Up => Some(FocusMove::Up),
+        KeyCode::Char('j') | KeyCode::Down => Some(FocusMove::Down),
+        _ => None,
+    }
+}
+
+/// The program named by the environment variable `var`, which may carry its
+/// own arguments, or else `fallback`, followed by `path`.
+fn external_command(var: &str, fallback: &str, path: &Path) -> Option<Vec<OsString>> {
+    let value = std::env::var(var)
+        .ok()
+        .filter(|value| !value.trim().is_empty());
+    program_with_path(value.as_deref().unwrap_or(fallback), path).or_else(|| {
+        warn!(target: "App", "Can't parse ${}", var);
+        None
+    })
+}
+
+fn program_with_path(program: &str, path: &Path) -> Option<Vec<OsString>> {
+    let mut command: Vec<OsString> = shlex::split(program)?
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+    if command.is_empty() {
+        return None;
+    }
+    command.push(path.as_os_str().to_os_string());
+    Some(command)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigFormat, proc::process::ProcessKind};
+    use futures::StreamExt;
+
+    #[test]
+    fn screen_text_trims_rows_and_skips_wide_spill() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 8, 2));
+        buf.set_string(0, 0, "ab 中文", Style::default());
+        buf.set_string(2, 1, "╭─╮", Style::default());
+        assert_eq!(screen_text(&buf), "ab 中文\n  ╭─╮\n");
+    }
+
+    #[tokio::test]
+    async fn signal_stops_children_before_returning() {
+        let config = "[[services]]\nname = \"sleepy\"\ncommand = \"sleep 30\"\n";
+        let source = ConfigSource::Stdin(config.to_string(), ConfigFormat::Toml);
+        let (signal, signals) = futures::channel::mpsc::unbounded();
+        let events = EventHandler::headless_with_signals(30.0, signals.boxed());
+        let mut app = App::with_events(events, source, 30.0, Vec::new(), false).unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            signal.unbounded_send(())
+        });
+        tokio::time::timeout(Duration::from_secs(10), app.run_headless())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(app.proc.processes.len(), 1);
+        assert!(!app.proc.processes[0].is_alive());
+    }
+
+    #[tokio::test]
+    async fn reload_removes_only_the_processes_dropped_from_the_config() {
+        let load = |toml: &str| {
+            let source = ConfigSource::Stdin(toml.to_string(), ConfigFormat::Toml);
+            ConfigManager::load(&source).unwrap()
+        };
+        let service =
+            |name, command| format!("[[services]]\nname = \"{name}\"\ncommand = \"{command}\"\n");
+        let source = ConfigSource::Stdin(String::new(), ConfigFormat::Toml);
+        let mut app = App::headless(source, 30.0, Vec::new(), false).unwrap();
+        let before = [
+            service("kept", "sleep 30"),
+            service("running", "sleep 30"),
+            service("done", "true"),
+        ];
+        app.start(&load(&before.concat())).unwrap();
+        let done_dies = async {
+            while app
+                .proc
+                .processes
+                .iter()
+                .any(|p| p.name == "done" && p.is_alive())
+            {
+                if let Event::App(app_event) = app.events.next().await.unwrap() {
+                    app.handle_app_event(app_event);
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), done_dies)
+            .await
+            .unwrap();
+
+        app.start(&load(&service("kept", "sleep 30"))).unwrap();
+        let state = |name| {
+            app.proc
+                .processes
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| &p.state)
+        };
+        assert!(matches!(
+            state("kept"),
+            Some(ProcessState::Starting | ProcessState::Running)
+        ));
+        assert!(matches!(state("running"), Some(ProcessState::Killing(_))));
+        assert!(matches!(state("done"), Some(ProcessState::Completed(_))));
+        let removed: Vec<_> = app.proc.processes.iter().filter(|p| p.removed).collect();
+        assert_eq!(removed.len(), 2);
+        assert!(removed.iter().all(|p| p.name != "kept"));
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_the_view_and_the_focused_process() {
+        let load = |toml: &str| {
+            let source = ConfigSource::Stdin(toml.to_string(), ConfigFormat::Toml);
+            ConfigManager::load(&source).unwrap()
+        };
+        let service = |name| format!("[[services]]\nname = \"{name}\"\ncommand = \"sleep 30\"\n");
+        let before = load(&[service("a"), service("b"), service("c")].concat());
+        let source = ConfigSource::Stdin(String::new(), ConfigFormat::Toml);
+        let mut app = App::headless(source, 30.0, Vec::new(), false).unwrap();
+        app.start(&before).unwrap();
+        app.ui_state.cycle_kind_filter();
+        app.ui_state.focus_process(2);
+        app.ui_state.toggle_list();
+        app.ui_state.toggle_spotlight();
+
+        // `a` becomes a stub, so the services filter hides it and `c` moves up.
+        let after = load(
+            &[
+                "[[stubs]]\nname = \"a\"\ncommand = \"sleep 30\"\n".to_string(),
+                service("b"),
+                service("c"),
+                service("d"),
+            ]
+            .concat(),
+        );
+        app.start(&after).unwrap();
+        assert_eq!(app.focused().map(|p| p.name.as_str()), Some("c"));
+        assert_eq!(app.ui_state.focus, Some(Focussable::Process(1)));
+        assert_eq!(app.ui_state.mode, Mode::Spotlight);
+        assert_eq!(app.ui_state.kind_filter, Some(ProcessKind::Service));
+        app.ui_state.toggle_spotlight();
+        assert_eq!(app.ui_state.mode, Mode::List);
+        app.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn paste_goes_to_the_attached_process_or_the_search() {
+        let toml = "[[services]]\nname = \"echo\"\ncommand = \"cat\"\n";
+        let source = ConfigSource::Stdin(toml.to_string(), ConfigFormat::Toml);
+        let config = ConfigManager::load(&source).unwrap();
+        let mut app = App::headless(source, 30.0, Vec::new(), false).unwrap();
+        app.start(&config).unwrap();
+
+        app.handle_paste("ignored".to_string());
+        app.ui_state.start_search();
+        app.handle_paste("ec\nho".to_string());
+        assert_eq!(app.ui_state.filter, "echo");
+        app.ui_state.clear_search();
+
+        app.ui_state.focus_process(0);
+        app.ui_state.toggle_spotlight();
+        app.ui_state.attach();
+        app.handle_paste("one\r\ntwo\r".to_string());
+        let output = &app.proc.processes[0].output;
+        for _ in 0..100 {
+            if output.lines().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(output.lines(), ["one", "two"]);
+        app.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn external_programs_keep_their_arguments() {
+        let path = Path::new("procli.log");
+        let command = |program| {
+            program_with_path(program, path).map(|words| {
+                words
+                    .into_iter()
+                    .map(|w| w.into_string().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            command("less"),
+            Some(vec!["less".into(), "procli.log".into()])
+        );
+        assert_eq!(
+            command("code --wait"),
+            Some(vec!["code".into(), "--wait".into(), "procli.log".into()])
+        );
+        assert_eq!(command("  "), None);
+        assert_eq!(command("vim '"), None);
+    }
+}
@@ -1,60 +1,332 @@
-//! Provides a ConfigManager to read and refresh config from files.
+//! Provides a ConfigManager to read and refresh config from files, or once
+//! from stdin.
 //!
 
-use color_eyre::Result;
+use color_eyre::{Result, eyre::eyre};
 use config;
 use log::*;
 use notify::{RecommendedWatcher, Watcher};
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 use tokio::sync::mpsc::UnboundedSender;
 
-use crate::event::{AppEvent, Event};
+use crate::{
+    event::{AppEvent, Event},
+    log_levels::LogLevels,
+};
 
 pub const DEFAULT_FILE: &str = "procli.toml";
+/// Config path that means "read the config from stdin".
+pub const STDIN_PATH: &str = "-";
+/// Where an image based process gets its `directory` mounted by default.
+pub const DEFAULT_MOUNT_TARGET: &str = "/opt/mounted";
 
-#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RestartPolicy {
+    /// Restart the process when it dies. Off means it is never restarted.
     pub enabled: bool,
     pub cooloff: u64,
+    /// Restarts allowed before giving up; 0 for no limit.
     pub max_restarts: u32,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+impl RestartPolicy {
+    /// Whether there is no limit on restarts.
+    pub fn unlimited(&self) -> bool {
+        self.max_restarts == 0
+    }
+
+    /// Whether a process restarted `restarts` times may be restarted again.
+    pub fn allows(&self, restarts: u32) -> bool {
+        self.enabled && (self.unlimited() || restarts < self.max_restarts)
+    }
+
+    /// The most restarts allowed, as shown after the count used.
+    pub fn limit(&self) -> String {
+        match self.unlimited() {
+            true => "∞".to_string(),
+            false => self.max_restarts.to_string(),
+        }
+    }
+}
+
+/// A command run now and then to check a running service is healthy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthCheck {
+    /// Shell command, run like `post_start`, that exits 0 when healthy.
+    pub command: String,
+    /// Seconds between checks, give or take a tenth.
+    #[serde(default = "default_health_interval_secs")]
+    pub interval_secs: u64,
+    /// Seconds a check may take before it counts as failed. No more than
+    /// `interval_secs`.
+    #[serde(default = "default_health_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Failures in a row before the service is unhealthy.
+    #[serde(default = "default_health_retries")]
+    pub retries: u32,
+    /// Seconds after starting during which failures don't count.
+    #[serde(default)]
+    pub start_period_secs: u64,
+    /// Stop the service once it is unhealthy, leaving its restart policy to
+    /// start it again.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.max(1))
+    }
+
+    pub fn start_period(&self) -> Duration {
+        Duration::from_secs(self.start_period_secs)
+    }
+}
+
+fn default_health_interval_secs() -> u64 {
+    30
+}
+
+fn default_health_timeout_secs() -> u64 {
+    5
+}
+
+fn default_health_retries() -> u32 {
+    3
+}
+
+/// How CPU and RAM history is drawn.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChartStyle {
+    /// Block character bars.
+    #[default]
+    Bars,
+    /// A smoother braille line, for terminals and fonts that draw it well.
+    Braille,
+    /// Plain ASCII bars, for limited terminals.
+    Ascii,
+}
+
+/// How CPU use is counted.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CpuMode {
+    /// Summed across cores, so a busy multi-threaded process can go over 100%.
+    #[default]
+    Total,
+    /// Divided by the number of cores, so 100% means every core is busy.
+    Normalized,
+}
+
+/// What happens to a process's card once a reload removes it from the config.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnRemove {
+    /// Keep it, showing how the process ended, until dismissed.
+    #[default]
+    Keep,
+    /// Drop it as soon as the process has stopped.
+    Drop,
+}
+
+/// Whether the dashboard turns to a process that crashes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusOnCrash {
+    /// Leave the focus where it is.
+    #[default]
+    Off,
+    /// Move the focus to it.
+    Focus,
+    /// Move the focus to it and open the spotlight.
+    Spotlight,
+}
+
+/// What a relative `directory` is resolved against.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryBase {
+    /// The directory procli was started in.
+    #[default]
+    WorkingDirectory,
+    /// The directory of the config file the service or stub is defined in.
+    ConfigFile,
+}
+
+/// When reloading the config restarts a process that is still in it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChangeRestart {
+    /// Only when its command, environment or directory changed.
+    #[default]
+    IfChanged,
+    /// On every reload, for processes that read the config themselves.
+    Always,
+    /// Never; changes apply the next time it starts.
+    Never,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Service {
     pub name: String,
     pub display: Option<String>,
+    /// Shown before the display name in titles instead of `SVC`. Empty for
+    /// none.
+    pub label: Option<String>,
+    /// A short description, e.g. "internal only, talks to redis", shown in
+    /// the spotlight and at the bottom of the card.
+    pub note: Option<String>,
     pub image: Option<String>,
     pub command: Option<String>,
+    /// Arguments passed after those in `command` exactly as written, with no
+    /// splitting or quoting. Not for `shell` commands.
+    #[serde(default)]
+    pub args: Vec<String>,
     pub directory: Option<String>,
+    /// Path inside the container that `directory` is mounted at and used as
+    /// the working directory. Defaults to `/opt/mounted`.
+    pub mount_target: Option<String>,
+    /// Bind-mount `directory` into the container for image based processes.
+    /// Defaults to true; turn off to leave the container's filesystem alone.
+    pub mount_directory: Option<bool>,
+    /// Name for the container instead of `procli-<name>`.
+    pub container_name: Option<String>,
+    /// Keep the container between runs and start it again rather than
+    /// running a fresh one, so it can be inspected with `docker exec`.
+    #[serde(default)]
+    pub reuse: bool,
+    /// Whether reloading the config restarts the process.
+    #[serde(default)]
+    pub restart_on_config_change: ConfigChangeRestart,
     #[serde(default)]
     pub environment: HashMap<String, String>,
     #[serde(default)]
     pub dependencies: Vec<String>,
     #[serde(default)]
     pub restart: Option<RestartPolicy>,
+    /// Exit codes, besides 0, that count as completing successfully rather
+    /// than crashing.
+    #[serde(default)]
+    pub success_codes: Vec<i32>,
+    /// CPU % above which the process is shown as a warning.
+    pub cpu_warn: Option<f32>,
+    /// CPU % above which the process is shown as critical.
+    pub cpu_crit: Option<f32>,
+    /// RAM in MB above which the process is shown as a warning.
+    pub mem_warn: Option<f32>,
+    /// RAM in MB above which the process is shown as critical.
+    pub mem_crit: Option<f32>,
+    /// Override the image's entrypoint. `command` is then passed to it as
+    /// arguments, as with `docker run --entrypoint`.
+    pub entrypoint: Option<String>,
+    /// Run a local `command` with `sh -c` (`cmd /C` on Windows) instead of
+    /// splitting it into arguments, so pipes and redirects work. The shell is
+    /// then the direct child, so it is the shell that gets signalled on stop.
+    #[serde(default)]
+    pub shell: bool,
+    /// Stop the process once it has been running this long, then restart it
+    /// according to `restart` as if it had crashed.
+    pub max_lifetime_secs: Option<u64>,
+    /// Stop the process if it isn't running, and healthy if it has a health
+    /// check, this long after starting, then restart it according to
+    /// `restart` as if it had crashed.
+    pub start_timeout_secs: Option<u64>,
+    /// Warn, and mark the card, when the running process has written no
+    /// output for this long. The process is left running.
+    pub idle_timeout_secs: Option<u64>,
+    /// A shell command run on the host, in `directory` and with
+    /// `environment`, each time the process starts running. Its output is
+    /// logged and a failure only warned about.
+    pub post_start: Option<String>,
+    /// A command run now and then while the process is alive to tell whether
+    /// it is healthy.
+    pub health_check: Option<HealthCheck>,
+    /// A regular expression that marks the service healthy the first time
+    /// a line of its output matches, such as `Listening on port \d+`.
+    pub ready_log_pattern: Option<String>,
+    /// Strip ANSI colour and other escape codes from the output so it reads
+    /// cleanly in the logs.
+    #[serde(default)]
+    pub ansi: bool,
+    /// Read stdout and stderr through one pipe, so lines are logged in the
+    /// order they were written. Unix only.
+    #[serde(default)]
+    pub merge_output: bool,
+    /// Log at most this many output lines a second, dropping the rest and
+    /// saying how many were dropped.
+    pub log_rate_limit: Option<u32>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Stub {
     pub name: String,
     pub display: Option<String>,
+    /// As for services; defaults to `STUB`.
+    pub label: Option<String>,
+    /// As for services.
+    pub note: Option<String>,
     pub image: Option<String>,
     pub command: Option<String>,
+    /// Arguments passed after those in `command` exactly as written, with no
+    /// splitting or quoting. Not for `shell` commands.
+    #[serde(default)]
+    pub args: Vec<String>,
     pub directory: Option<String>,
+    /// Path inside the container that `directory` is mounted at and used as
+    /// the working directory. Defaults to `/opt/mounted`.
+    pub mount_target: Option<String>,
+    /// Bind-mount `directory` into the container for image based processes.
+    /// Defaults to true; turn off to leave the container's filesystem alone.
+    pub mount_directory: Option<bool>,
+    /// As for services.
+    pub container_name: Option<String>,
+    #[serde(default)]
+    pub reuse: bool,
+    /// As for services.
+    #[serde(default)]
+    pub restart_on_config_change: ConfigChangeRestart,
     #[serde(default)]
     pub environment: HashMap<String, String>,
     pub restart: Option<RestartPolicy>,
+    /// As for services.
+    #[serde(default)]
+    pub success_codes: Vec<i32>,
+    pub cpu_warn: Option<f32>,
+    pub cpu_crit: Option<f32>,
+    pub mem_warn: Option<f32>,
+    pub mem_crit: Option<f32>,
+    #[serde(default)]
+    pub ansi: bool,
+    /// As for services.
+    #[serde(default)]
+    pub merge_output: bool,
+    /// As for services.
+    pub log_rate_limit: Option<u32>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Agent {
     pub name: String,
     pub display: Option<String>,
     pub scenario: String,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProcliConfig {
     #[serde(default)]
     pub services: Vec<Service>,
@@ -64,6 +336,66 @@ pub struct ProcliConfig {
     pub agents: Vec<Agent>,
     #[serde(default = "default_log_buffer_size")]
     pub log_buffer_size: usize,
+    /// The least time between starts of a process that keeps dying, however
+    /// short its `cooloff`.
+    #[serde(default = "default_min_restart_interval_ms")]
+    pub min_restart_interval_ms: u64,
+    /// Show running and failed counts in the terminal title.
+    #[serde(default = "default_set_terminal_title")]
+    pub set_terminal_title: bool,
+    /// Remove containers left over from an earlier run that would clash
+    /// with the ones about to start.
+    #[serde(default)]
+    pub cleanup_orphans: bool,
+    /// Refresh stats and animate less often once nothing has happened for a
+    /// while, to save battery.
+    #[serde(default)]
+    pub power_save: bool,
+    /// Push metrics to a StatsD agent at this `host:port` on each refresh.
+    pub statsd: Option<String>,
+    /// Stream process events as JSON lines to clients connecting to this
+    /// `host:port`.
+    pub event_socket: Option<String>,
+    /// Take `stop` and `start` commands, as well as streaming events, on a
+    /// Unix domain socket at this path. Unix only.
+    pub control_socket: Option<PathBuf>,
+    /// How the sparklines draw CPU and RAM history.
+    #[serde(default)]
+    pub chart_style: ChartStyle,
+    /// Character drawn in the sparklines where there is no sample. `_` if
+    /// not set.
+    pub absent_symbol: Option<String>,
+    /// Draw sparkline bins without a sample as zero rather than absent.
+    #[serde(default)]
+    pub absent_as_zero: bool,
+    /// Lines each process card takes, borders included. Just enough for its
+    /// stats and note if not set; taller cards draw taller sparklines.
+    pub card_height: Option<u16>,
+    /// Whether CPU use is summed across cores or divided between them.
+    #[serde(default)]
+    pub cpu_mode: CpuMode,
+    /// Whether a card stays once a reload takes its process out of the config.
+    #[serde(default)]
+    pub on_remove: OnRemove,
+    /// Whether the dashboard turns to a process that crashes.
+    #[serde(default)]
+    pub focus_on_crash: FocusOnCrash,
+    /// What a relative `directory` of a service or stub is resolved against.
+    #[serde(default)]
+    pub directories_relative_to: DirectoryBase,
+    /// Environment shared by every service and stub, overridden by their own
+    /// `environment`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// How much to log, such as `info` or `info,App=debug,Config=warn`. Read
+    /// at startup; `--log-level` overrides it.
+    pub log_level: Option<String>,
+    /// Look for a newer procli release at startup and mention it in the log.
+    #[serde(default)]
+    pub check_updates: bool,
+    /// Where to look for the latest release. A JSON object with a `tag_name`
+    /// or `version`, or just the version as text.
+    pub release_url: Option<String>,
 }
 
 impl ProcliConfig {
@@ -81,47 +413,1142 @@ impl ProcliConfig {
             || self.get_stub(name).is_some()
             || self.get_agent(name).is_some()
     }
+
+    /// Give every service and stub the shared `env`, then expand `${VAR}` in
+    /// their commands, args, directories and environment values.
+    ///
+    /// Variables come from the process's own `environment` first, then the
+    /// shared `env`, then procli's environment. Environment values are only
+    /// expanded from the latter two.
+    pub fn interpolate(&mut self) -> Result<()> {
+        let shared = &self.env;
+        let services = self.services.iter_mut().map(|s| {
+            (
+                &s.name,
+                &mut s.command,
+                &mut s.args,
+                &mut s.directory,
+                &mut s.environment,
+            )
+        });
+        let stubs = self.stubs.iter_mut().map(|s| {
+            (
+                &s.name,
+                &mut s.command,
+                &mut s.args,
+                &mut s.directory,
+                &mut s.environment,
+            )
+        });
+        for (name, command, args, directory, environment) in services.chain(stubs) {
+            let from_shared = |var: &str| shared.get(var).cloned().or(std::env::var(var).ok());
+            let mut merged = shared.clone();
+            for (key, value) in environment.iter() {
+                let value = expand(value, from_shared)
+                    .map_err(|err| eyre!("{} in the environment of `{}`", err, name))?;
+                merged.insert(key.clone(), value);
+            }
+            *environment = merged;
+            let lookup = |var: &str| environment.get(var).cloned().or(from_shared(var));
+            let fields = [command, directory].into_iter().flat_map(|f| f.as_mut());
+            for text in fields.chain(args.iter_mut()) {
+                *text = expand(text, lookup).map_err(|err| eyre!("{} in `{}`", err, name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Join each relative service and stub `directory` onto the directory
+    /// `base` gives for that name, if any.
+    pub fn resolve_directories(&mut self, base: impl Fn(&str) -> Option<PathBuf>) {
+        let services = self
+            .services
+            .iter_mut()
+            .map(|s| (&s.name, &mut s.directory));
+        let stubs = self.stubs.iter_mut().map(|s| (&s.name, &mut s.directory));
+        for (name, directory) in services.chain(stubs) {
+            let Some(dir) = directory.as_mut() else {
+                continue;
+            };
+            if Path::new(dir).is_relative()
+                && let Some(base) = base(name)
+            {
+                *dir = base.join(&dir).to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    /// Check the config for problems serde can't catch on its own.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(cycle) = self.dependency_cycle() {
+            return Err(eyre!("dependency cycle: {}", cycle.join(" -> ")));
+        }
+        if let Some(levels) = &self.log_level {
+            levels
+                .parse::<LogLevels>()
+                .map_err(|err| eyre!("log_level: {}", err))?;
+        }
+        for svc in &self.services {
+            if let Some(pattern) = &svc.ready_log_pattern {
+                Regex::new(pattern)
+                    .map_err(|err| eyre!("{}: bad ready_log_pattern: {}", svc.name, err))?;
+            }
+        }
+        if let Some(symbol) = &self.absent_symbol
+            && symbol.chars().count() != 1
+        {
+            return Err(eyre!(
+                "absent_symbol must be a single character, not {:?}",
+                symbol
+            ));
+        }
+        Ok(())
+    }
+
+    /// Find a cycle in the service dependencies, returned as the path around
+    /// it with the first service repeated at the end.
+    pub fn dependency_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            config: &'a ProcliConfig,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|n| *n == name) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(name.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(name) {
+                return None;
+            }
+            let svc = config.get_service(name)?;
+            path.push(name);
+            for dep in svc.dependencies.iter() {
+                if let Some(cycle) = visit(config, dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
+        }
+
+        let mut done = HashSet::new();
+        self.services
+            .iter()
+            .find_map(|svc| visit(self, &svc.name, &mut Vec::new(), &mut done))
+    }
+
+    /// Restrict the config to the named processes and everything they depend
+    /// on, directly or indirectly. An empty selection keeps everything.
+    pub fn only(&self, names: &[String]) -> Result<ProcliConfig> {
+        if names.is_empty() {
+            return Ok(self.clone());
+        }
+        if let Some(unknown) = names.iter().find(|n| !self.contains(n)) {
+            return Err(eyre!("Unknown service `{}`", unknown));
+        }
+        let mut selected: HashSet<&str> = HashSet::new();
+        let mut pending: Vec<&str> = names.iter().map(String::as_str).collect();
+        while let Some(name) = pending.pop() {
+            if selected.insert(name)
+                && let Some(svc) = self.get_service(name)
+            {
+                pending.extend(svc.dependencies.iter().map(String::as_str));
+            }
+        }
+        Ok(ProcliConfig {
+            services: self
+                .services
+                .iter()
+                .filter(|s| selected.contains(s.name.as_str()))
+                .cloned()
+                .collect(),
+            stubs: self
+                .stubs
+                .iter()
+                .filter(|s| selected.contains(s.name.as_str()))
+                .cloned()
+                .collect(),
+            agents: self
+                .agents
+                .iter()
+                .filter(|a| selected.contains(a.name.as_str()))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        })
+    }
+}
+
+/// What changed between two configs, keyed by service, stub and agent name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Names with the fields that differ.
+    pub changed: Vec<(String, Vec<String>)>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One line per added, removed or changed entry.
+    pub fn describe(&self) -> Vec<String> {
+        let added = self.added.iter().map(|name| format!("added {name}"));
+        let removed = self.removed.iter().map(|name| format!("removed {name}"));
+        let changed = self
+            .changed
+            .iter()
+            .map(|(name, fields)| format!("changed {name}: {}", fields.join(", ")));
+        added.chain(removed).chain(changed).collect()
+    }
+}
+
+/// Compare the services, stubs and agents of two configs.
+pub fn diff_configs(old: &ProcliConfig, new: &ProcliConfig) -> ConfigDiff {
+    fn entries(config: &ProcliConfig) -> Vec<(String, serde_json::Value)> {
+        fn entry<T: Serialize>(kind: &str, name: &str, item: &T) -> (String, serde_json::Value) {
+            let mut value = serde_json::to_value(item).unwrap_or_default();
+            value["kind"] = kind.into();
+            (name.to_string(), value)
+        }
+        let services = config.services.iter().map(|s| entry("service", &s.name, s));
+        let stubs = config.stubs.iter().map(|s| entry("stub", &s.name, s));
+        let agents = config.agents.iter().map(|a| entry("agent", &a.name, a));
+        services.chain(stubs).chain(agents).collect()
+    }
+
+    let old = entries(old);
+    let new = entries(new);
+    let find = |entries: &[(String, serde_json::Value)], name: &str| {
+        entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.clone())
+    };
+    let mut diff = ConfigDiff::default();
+    for (name, value) in new.iter() {
+        match find(&old, name) {
+            None => diff.added.push(name.clone()),
+            Some(previous) if previous != *value => {
+                let empty = serde_json::Map::new();
+                let before = previous.as_object().unwrap_or(&empty);
+                let after = value.as_object().unwrap_or(&empty);
+                let mut fields: Vec<String> = before
+                    .keys()
+                    .chain(after.keys())
+                    .filter(|k| before.get(*k) != after.get(*k))
+                    .cloned()
+                    .collect();
+                fields.sort();
+                fields.dedup();
+                diff.changed.push((name.clone(), fields));
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, _) in old.iter() {
+        if find(&new, name).is_none() {
+            diff.removed.push(name.clone());
+        }
+    }
+    diff
+}
+
+/// JSON Schema describing the config file, for editor completion and validation.
+pub fn schema() -> schemars::Schema {
+    schemars::schema_for!(ProcliConfig)
 }
 
 fn default_log_buffer_size() -> usize {
     10_000
 }
 
+fn default_min_restart_interval_ms() -> u64 {
+    1000
+}
+
+fn default_set_terminal_title() -> bool {
+    true
+}
+
+/// Format of the config, for when there is no file extension to go by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    fn file_format(self) -> config::FileFormat {
+        match self {
+            ConfigFormat::Toml => config::FileFormat::Toml,
+            ConfigFormat::Yaml => config::FileFormat::Yaml,
+            ConfigFormat::Json => config::FileFormat::Json,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!("unknown format `{s}`, expected toml, yaml or json")),
+        }
+    }
+}
+
+/// Where the config is read from.
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// One or more files, in the given format or else the one each extension
+    /// suggests. Later files are layered over earlier ones.
+    Files(Vec<PathBuf>, Option<ConfigFormat>),
+    /// Text read from stdin up front, since it can only be read once. There is
+    /// nothing to watch and reloading parses the same text again.
+    Stdin(String, ConfigFormat),
+}
+
+impl ConfigSource {
+    /// The files at `paths`, or stdin if the only path is `-`, in which case
+    /// the format defaults to TOML. With no paths, the nearest `procli.toml`
+    /// from the current directory up.
+    pub fn open(mut paths: Vec<PathBuf>, format: Option<ConfigFormat>) -> Result<Self> {
+        if paths.is_empty() {
+            let found = find_upwards(&std::env::current_dir()?, DEFAULT_FILE);
+            paths.push(found.unwrap_or_else(|| PathBuf::from(DEFAULT_FILE)));
+        }
+        if !paths.iter().any(|path| path.as_os_str() == STDIN_PATH) {
+            return Ok(ConfigSource::Files(paths, format));
+        }
+        if paths.len() > 1 {
+            return Err(eyre!(
+                "`--config -` can't be combined with other config files"
+            ));
+        }
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        Ok(ConfigSource::Stdin(
+            text,
+            format.unwrap_or(ConfigFormat::Toml),
+        ))
+    }
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Files(paths, _) => {
+                let paths: Vec<_> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(f, "{}", paths.join(", "))
+            }
+            ConfigSource::Stdin(..) => write!(f, "stdin"),
+        }
+    }
+}
+
+/// The first `file_name` in `start` or any directory above it.
+pub fn find_upwards(start: &Path, file_name: &str) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(file_name))
+        .find(|path| path.is_file())
+}
+
 #[derive(Debug)]
 pub struct ConfigManager {
-    pub file_path: PathBuf,
+    pub source: ConfigSource,
     config: ProcliConfig,
-    _watcher: RecommendedWatcher,
+    /// Absent when hot-reload is disabled.
+    _watcher: Option<RecommendedWatcher>,
 }
 
 impl ConfigManager {
-    pub fn new(file_path: PathBuf, sender: UnboundedSender<Event>) -> Result<ConfigManager> {
-        let mut watcher = notify::recommended_watcher(move |_| {
-            let _ = sender.send(Event::App(AppEvent::Reload));
-        })?;
-        info!(target: "Config", "Watching file {:?}", file_path);
-        watcher.watch(&file_path, notify::RecursiveMode::NonRecursive)?;
+    /// Load the config and, if `watch` is set and it is read from files, watch
+    /// them all for changes.
+    ///
+    /// The parent directory is watched rather than the file itself so that
+    /// editors which save by renaming a temporary file over the original don't
+    /// break the watch.
+    pub fn new(
+        source: ConfigSource,
+        sender: UnboundedSender<Event>,
+        watch: bool,
+    ) -> Result<ConfigManager> {
+        let watcher = match &source {
+            ConfigSource::Files(paths, _) if watch => Some(Self::watch(paths, sender)?),
+            _ => {
+                info!(target: "Config", "Not watching {}", source);
+                None
+            }
+        };
         Ok(ConfigManager {
-            file_path: file_path.clone(),
-            config: Self::load_from_file(file_path.clone())?,
+            config: Self::load(&source)?,
+            source,
             _watcher: watcher,
         })
     }
 
+    fn watch(file_paths: &[PathBuf], sender: UnboundedSender<Event>) -> Result<RecommendedWatcher> {
+        // Events name the file under the directory as watched, so watch the
+        // canonical directory and compare whole paths: a file of the same
+        // name next to another config file is none of ours.
+        let mut watched = Vec::new();
+        let mut dirs = Vec::new();
+        for file_path in file_paths {
+            let file_name = file_path
+                .file_name()
+                .ok_or_else(|| eyre!("Config path {:?} is not a file", file_path))?;
+            let dir = match file_path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = dir.canonicalize()?;
+            watched.push(dir.join(file_name));
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        let mut watcher = notify::recommended_watcher(move |event| {
+            if let Ok(event) = event
+                && watched.iter().any(|path| is_change_to(&event, path))
+            {
+                let _ = sender.send(Event::App(AppEvent::Reload));
+            }
+        })?;
+        for file_path in file_paths {
+            info!(target: "Config", "Watching file {:?}", file_path);
+        }
+        for dir in &dirs {
+            watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        }
+        Ok(watcher)
+    }
+
     pub fn current(&self) -> ProcliConfig {
         self.config.clone()
     }
 
     pub fn reload(&mut self) -> Result<ProcliConfig> {
-        self.config = Self::load_from_file(self.file_path.clone())?;
+        self.config = Self::load(&self.source)?;
         Ok(self.current())
     }
 
-    fn load_from_file(file_path: PathBuf) -> Result<ProcliConfig> {
-        let raw = config::Config::builder()
-            .add_source(config::File::from(file_path))
+    /// Load and validate the config, without watching it.
+    ///
+    /// Unknown keys are logged as warnings.
+    pub fn load(source: &ConfigSource) -> Result<ProcliConfig> {
+        let (config, warnings) = Self::load_with_warnings(source)?;
+        for warning in warnings {
+            warn!(target: "Config", "{}", warning);
+        }
+        Ok(config)
+    }
+
+    /// Load and validate the config, returning any unknown keys as warnings
+    /// rather than logging them.
+    ///
+    /// Files are layered in order: tables such as `[env]` are merged key by
+    /// key, while a service, stub or agent in a later file replaces the one
+    /// of the same name entirely, `environment` included. New names are added
+    /// after the existing ones.
+    pub fn load_with_warnings(source: &ConfigSource) -> Result<(ProcliConfig, Vec<String>)> {
+        let (mut config, warnings, origins) = Self::layered(source)?;
+        config.interpolate()?;
+        if let ConfigSource::Files(paths, _) = source
+            && config.directories_relative_to == DirectoryBase::ConfigFile
+        {
+            let dirs = paths
+                .iter()
+                .map(|path| Ok(std::path::absolute(path)?.parent().map(Path::to_path_buf)))
+                .collect::<Result<Vec<_>>>()?;
+            config.resolve_directories(|name| dirs[*origins.get(name)?].clone());
+        }
+        config.validate()?;
+        Ok((config, warnings))
+    }
+
+    /// Load and validate the config as written: layered and overridden, but
+    /// with no `${VAR}` expanded and no `[env]` merged in, so nothing taken
+    /// from the environment shows up when it is printed.
+    pub fn load_raw(source: &ConfigSource) -> Result<ProcliConfig> {
+        let (config, _, _) = Self::layered(source)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The config files layered and deserialized, with any unknown keys and
+    /// the index of the layer each name was last defined in.
+    fn layered(
+        source: &ConfigSource,
+    ) -> Result<(ProcliConfig, Vec<String>, HashMap<String, usize>)> {
+        let layers = match source {
+            ConfigSource::Files(paths, format) => paths
+                .iter()
+                .map(|path| {
+                    let file = config::File::from(path.as_path());
+                    let file = match format {
+                        Some(format) => file.format(format.file_format()),
+                        None => file,
+                    };
+                    config::Config::builder().add_source(file).build()
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            ConfigSource::Stdin(text, format) => vec![
+                config::Config::builder()
+                    .add_source(config::File::from_str(text, format.file_format()))
+                    .build()?,
+            ],
+        };
+        let mut builder = config::Config::builder();
+        // The layer each service, stub and agent was last defined in.
+        let mut origins = HashMap::new();
+        for list in NAMED_LISTS {
+            let merged = merge_by_name(&layers, list)?;
+            let mut entries = Vec::new();
+            for (entry, layer) in merged {
+                if let Some(name) = entry_name(&entry) {
+                    origins.insert(name, layer);
+                }
+                entries.push(entry);
+            }
+            if !entries.is_empty() {
+                builder = builder.set_override(list, entries)?;
+            }
+        }
+        for layer in layers {
+            builder = builder.add_source(layer);
+        }
+        let raw = builder
             .add_source(config::Environment::with_prefix("PROCLI_"))
             .build()?;
-        Ok(raw.try_deserialize()?)
+        let (config, warnings) = deserialize(raw)?;
+        Ok((config, warnings, origins))
+    }
+}
+
+/// The lists whose entries are matched up by `name` across config files.
+const NAMED_LISTS: [&str; 3] = ["services", "stubs", "agents"];
+
+/// The entries of `list` from every layer, where an entry replaces an earlier
+/// one with the same name, each with the index of the layer it came from.
+fn merge_by_name(layers: &[config::Config], list: &str) -> Result<Vec<(config::Value, usize)>> {
+    let mut merged: Vec<(config::Value, usize)> = Vec::new();
+    for (index, layer) in layers.iter().enumerate() {
+        let entries = match layer.get_array(list) {
+            Ok(entries) => entries,
+            Err(config::ConfigError::NotFound(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let name = entry_name(&entry);
+            match merged
+                .iter_mut()
+                .find(|(existing, _)| name.is_some() && entry_name(existing) == name)
+            {
+                Some(existing) => *existing = (entry, index),
+                None => merged.push((entry, index)),
+            }
+        }
+    }
+    Ok(merged)
+}
+
+fn entry_name(entry: &config::Value) -> Option<String> {
+    entry
+        .clone()
+        .into_table()
+        .ok()?
+        .remove("name")?
+        .into_string()
+        .ok()
+}
+
+/// Replace each `${VAR}` in `text` with its value, and each `$${` with a
+/// literal `${`. A `$` not followed by `{` is left alone.
+fn expand(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = after;
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let end = after
+            .find('}')
+            .ok_or_else(|| eyre!("unclosed `${{` in `{}`", text))?;
+        let var = &after[..end];
+        let value = lookup(var).ok_or_else(|| {
+            eyre!(
+                "unknown variable `{}` (write `$${{` for a literal `${{`)",
+                var
+            )
+        })?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Whether a watcher event on the config's directory changed the config file
+/// at `file_path`, in its canonical directory.
+fn is_change_to(event: &notify::Event, file_path: &Path) -> bool {
+    matches!(
+        event.kind,
+        notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+    ) && event.paths.iter().any(|path| path == file_path)
+}
+
+/// Deserialize the config, describing any keys that serde would otherwise
+/// silently drop.
+fn deserialize(raw: config::Config) -> Result<(ProcliConfig, Vec<String>)> {
+    let mut unknown: Vec<Vec<String>> = Vec::new();
+    let config: ProcliConfig =
+        serde_ignored::deserialize(raw, |path| unknown.push(path_segments(&path)))?;
+    let mut warnings: Vec<String> = unknown
+        .iter()
+        .map(|path| describe_unknown(&config, path))
+        .collect();
+    warnings.sort();
+    Ok((config, warnings))
+}
+
+fn path_segments(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut segments = path_segments(parent);
+            segments.push(index.to_string());
+            segments
+        }
+        Path::Map { parent, key } => {
+            let mut segments = path_segments(parent);
+            segments.push(key.clone());
+            segments
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => path_segments(parent),
+    }
+}
+
+/// Describe an unknown key relative to the service, stub or agent it is in.
+fn describe_unknown(config: &ProcliConfig, path: &[String]) -> String {
+    if let [list, index, field @ ..] = path
+        && !field.is_empty()
+        && let Ok(index) = index.parse::<usize>()
+    {
+        let owner = match list.as_str() {
+            "services" => config.services.get(index).map(|s| ("service", &s.name)),
+            "stubs" => config.stubs.get(index).map(|s| ("stub", &s.name)),
+            "agents" => config.agents.get(index).map(|a| ("agent", &a.name)),
+            _ => None,
+        };
+        if let Some((kind, name)) = owner {
+            return format!("unknown field `{}` in {} `{}`", field.join("."), kind, name);
+        }
+    }
+    format!("unknown field `{}`", path.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, dependencies: &[&str]) -> Service {
+        Service {
+            name: name.to_string(),
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn stack() -> ProcliConfig {
+        ProcliConfig {
+            services: vec![
+                service("web", &["api"]),
+                service("api", &["db", "payments"]),
+                service("db", &[]),
+                service("worker", &["db"]),
+            ],
+            stubs: vec![Stub {
+                name: "payments".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn names(config: &ProcliConfig) -> Vec<&str> {
+        let services = config.services.iter().map(|s| s.name.as_str());
+        let stubs = config.stubs.iter().map(|s| s.name.as_str());
+        services.chain(stubs).collect()
+    }
+
+    fn validate_err(services: Vec<Service>) -> Option<String> {
+        let config = ProcliConfig {
+            services,
+            ..Default::default()
+        };
+        config.validate().err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn validate_accepts_shared_dependencies() {
+        assert_eq!(validate_err(stack().services), None);
+    }
+
+    #[test]
+    fn validate_reports_self_dependency() {
+        assert_eq!(
+            validate_err(vec![service("a", &["a"])]).as_deref(),
+            Some("dependency cycle: a -> a")
+        );
+    }
+
+    #[test]
+    fn validate_reports_two_service_cycle() {
+        assert_eq!(
+            validate_err(vec![service("a", &["b"]), service("b", &["a"])]).as_deref(),
+            Some("dependency cycle: a -> b -> a")
+        );
+    }
+
+    #[test]
+    fn validate_reports_cycle_down_a_chain() {
+        let services = vec![
+            service("web", &["api"]),
+            service("api", &["db", "cache"]),
+            service("db", &[]),
+            service("cache", &["queue"]),
+            service("queue", &["api"]),
+        ];
+        assert_eq!(
+            validate_err(services).as_deref(),
+            Some("dependency cycle: api -> cache -> queue -> api")
+        );
+    }
+
+    #[test]
+    fn validate_reports_bad_ready_log_pattern() {
+        let web = Service {
+            ready_log_pattern: Some("(".to_string()),
+            ..service("web", &[])
+        };
+        let err = validate_err(vec![web]).unwrap();
+        assert!(err.starts_with("web: bad ready_log_pattern"), "{err}");
+    }
+
+    #[test]
+    fn absent_symbol_must_be_one_character() {
+        assert!(load_toml(r#"absent_symbol = "·""#).is_ok());
+        for symbol in ["", "--"] {
+            let err = load_toml(&format!("absent_symbol = {symbol:?}")).unwrap_err();
+            assert!(err.to_string().contains("single character"), "{err}");
+        }
+    }
+
+    #[test]
+    fn schema_describes_the_config() {
+        let schema = serde_json::to_value(schema()).unwrap();
+        let defs = &schema["$defs"];
+        assert!(schema["properties"]["services"].is_object());
+        assert!(schema["properties"]["log_buffer_size"].is_object());
+        assert!(defs["Service"]["properties"]["command"].is_object());
+        assert!(defs["RestartPolicy"]["properties"]["cooloff"].is_object());
+        assert_eq!(defs["Service"]["required"], serde_json::json!(["name"]));
+        assert_eq!(
+            defs["Agent"]["required"],
+            serde_json::json!(["name", "scenario"])
+        );
+    }
+
+    fn warnings(toml: &str) -> Vec<String> {
+        let raw = config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap();
+        deserialize(raw).unwrap().1
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_context() {
+        let toml = r#"
+            log_bufer_size = 10
+
+            [[services]]
+            name = "api"
+            comand = "run"
+            restart = { enabled = true, cooloff = 1, max_restarts = 2, coolof = 1 }
+
+            [[stubs]]
+            name = "mock"
+            imag = "wiremock"
+        "#;
+        assert_eq!(
+            warnings(toml),
+            [
+                "unknown field `comand` in service `api`",
+                "unknown field `imag` in stub `mock`",
+                "unknown field `log_bufer_size`",
+                "unknown field `restart.coolof` in service `api`",
+            ]
+        );
+    }
+
+    #[test]
+    fn known_keys_are_not_reported() {
+        let toml = r#"
+            [[services]]
+            name = "api"
+            command = "run"
+            environment = { PORT = "8080" }
+            restart = { enabled = true, cooloff = 1, max_restarts = 2 }
+        "#;
+        assert!(warnings(toml).is_empty());
+    }
+
+    #[test]
+    fn watcher_only_reacts_to_changes_to_the_config_file() {
+        use notify::event::{AccessKind, CreateKind, ModifyKind, RenameMode};
+        use notify::{Event, EventKind};
+
+        let file_path = Path::new("/work/a/procli.toml");
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        let renamed = EventKind::Modify(ModifyKind::Name(RenameMode::To));
+
+        assert!(is_change_to(
+            &event(renamed, "/work/a/procli.toml"),
+            file_path
+        ));
+        assert!(is_change_to(
+            &event(EventKind::Create(CreateKind::File), "/work/a/procli.toml"),
+            file_path
+        ));
+        assert!(!is_change_to(
+            &event(renamed, "/work/a/.procli.toml.swp"),
+            file_path
+        ));
+        assert!(!is_change_to(
+            &event(renamed, "/work/b/procli.toml"),
+            file_path
+        ));
+        assert!(!is_change_to(
+            &event(EventKind::Access(AccessKind::Any), "/work/a/procli.toml"),
+            file_path
+        ));
+    }
+
+    #[test]
+    fn diff_of_identical_configs_is_empty() {
+        assert!(diff_configs(&stack(), &stack()).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let old = stack();
+        let mut new = stack();
+        new.services.retain(|s| s.name != "worker");
+        new.services.push(service("cron", &["db"]));
+        let api = new.services.iter_mut().find(|s| s.name == "api").unwrap();
+        api.command = Some("api --fast".to_string());
+        api.environment
+            .insert("PORT".to_string(), "8080".to_string());
+        api.restart = Some(RestartPolicy {
+            enabled: true,
+            cooloff: 1,
+            max_restarts: 3,
+        });
+
+        let diff = diff_configs(&old, &new);
+        assert_eq!(
+            diff,
+            ConfigDiff {
+                added: vec!["cron".to_string()],
+                removed: vec!["worker".to_string()],
+                changed: vec![(
+                    "api".to_string(),
+                    vec![
+                        "command".to_string(),
+                        "environment".to_string(),
+                        "restart".to_string()
+                    ]
+                )],
+            }
+        );
+        assert_eq!(
+            diff.describe(),
+            [
+                "added cron",
+                "removed worker",
+                "changed api: command, environment, restart"
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_reports_a_change_of_kind() {
+        let old = stack();
+        let mut new = stack();
+        new.stubs.clear();
+        new.services.push(service("payments", &[]));
+        let diff = diff_configs(&old, &new);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0, "payments");
+        assert!(diff.changed[0].1.contains(&"kind".to_string()));
+    }
+
+    #[test]
+    fn only_includes_transitive_dependencies() {
+        let config = stack().only(&["web".to_string()]).unwrap();
+        assert_eq!(names(&config), ["web", "api", "db", "payments"]);
+    }
+
+    #[test]
+    fn only_with_no_names_keeps_everything() {
+        let config = stack().only(&[]).unwrap();
+        assert_eq!(names(&config), ["web", "api", "db", "worker", "payments"]);
+    }
+
+    #[test]
+    fn only_rejects_unknown_names() {
+        let err = stack().only(&["wbe".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown service `wbe`");
+    }
+
+    fn load_toml(toml: &str) -> Result<ProcliConfig> {
+        let source = ConfigSource::Stdin(toml.to_string(), ConfigFormat::Toml);
+        ConfigManager::load(&source)
+    }
+
+    #[test]
+    fn variables_resolve_from_each_layer_in_order() {
+        let path = std::env::var("PATH").unwrap();
+        let config = load_toml(
+            r#"
+            [env]
+            BASE_URL = "http://localhost"
+            PORT = "80"
+            ROOT = "srv"
+
+            [[services]]
+            name = "api"
+            command = "./api --url ${BASE_URL}:${PORT} --path ${PATH}"
+            args = ["--root", "${ROOT} and $${ROOT}"]
+            directory = "${ROOT}/api"
+            environment = { PORT = "8080", SEARCH = "${PATH}" }
+            "#,
+        )
+        .unwrap();
+        let api = &config.services[0];
+        assert_eq!(
+            api.command.as_deref(),
+            Some(format!("./api --url http://localhost:8080 --path {path}").as_str())
+        );
+        assert_eq!(api.args, ["--root", "srv and ${ROOT}"]);
+        assert_eq!(api.directory.as_deref(), Some("srv/api"));
+        assert_eq!(api.environment["BASE_URL"], "http://localhost");
+        assert_eq!(api.environment["PORT"], "8080");
+        assert_eq!(api.environment["SEARCH"], path);
+    }
+
+    #[test]
+    fn unknown_variables_are_errors() {
+        let err = load_toml(
+            r#"
+            [[stubs]]
+            name = "mock"
+            command = "./mock ${PROCLI_NO_SUCH_VARIABLE}"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown variable `PROCLI_NO_SUCH_VARIABLE` (write `$${` for a literal `${`) in `mock`"
+        );
+        assert_eq!(
+            expand("$HOME costs $5", |_| None).unwrap(),
+            "$HOME costs $5"
+        );
+    }
+
+    #[test]
+    fn doubled_dollar_escapes_interpolation() {
+        let lookup = |var: &str| (var == "PORT").then(|| "8080".to_string());
+        assert_eq!(
+            expand("echo $${NAME:-web} ${PORT} $${#x}", lookup).unwrap(),
+            "echo ${NAME:-web} 8080 ${#x}"
+        );
+    }
+
+    #[test]
+    fn formats_parse_case_insensitively() {
+        assert_eq!("YAML".parse(), Ok(ConfigFormat::Yaml));
+        assert_eq!("yml".parse(), Ok(ConfigFormat::Yaml));
+        assert!("ini".parse::<ConfigFormat>().is_err());
+    }
+
+    #[test]
+    fn stdin_text_loads_in_the_given_format() {
+        let source = ConfigSource::Stdin(
+            "services:\n  - name: api\n    command: ./api\n".to_string(),
+            ConfigFormat::Yaml,
+        );
+        let (config, warnings) = ConfigManager::load_with_warnings(&source).unwrap();
+        assert_eq!(config.services[0].name, "api");
+        assert!(warnings.is_empty());
+        assert_eq!(source.to_string(), "stdin");
+    }
+
+    #[test]
+    fn later_files_replace_services_by_name_and_merge_tables() {
+        let dir = std::env::temp_dir().join(format!("procli-layers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.toml");
+        let overrides = dir.join("overrides.yaml");
+        std::fs::write(
+            &base,
+            r#"
+            [env]
+            HOST = "localhost"
+            PORT = "80"
+
+            [[services]]
+            name = "api"
+            command = "./api"
+            environment = { LOG = "debug", MODE = "dev" }
+
+            [[services]]
+            name = "web"
+            command = "./web"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            &overrides,
+            "env:\n  PORT: \"8080\"\nservices:\n  - name: api\n    command: ./api --fast\n    environment:\n      MODE: prod\n  - name: db\n    command: ./db\n",
+        )
+        .unwrap();
+        let source = ConfigSource::open(vec![base.clone(), overrides.clone()], None).unwrap();
+        let config = ConfigManager::load(&source);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(names(&config), ["api", "web", "db"]);
+        let api = &config.services[0];
+        assert_eq!(api.command.as_deref(), Some("./api --fast"));
+        assert_eq!(api.environment.get("LOG"), None);
+        assert_eq!(api.environment["MODE"], "prod");
+        assert_eq!(api.environment["HOST"], "localhost");
+        assert_eq!(api.environment["PORT"], "8080");
+        assert_eq!(
+            source.to_string(),
+            format!("{}, {}", base.display(), overrides.display())
+        );
+    }
+
+    #[test]
+    fn directories_can_be_relative_to_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("procli-relative-{}", std::process::id()));
+        let nested = dir.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        let base = dir.join("procli.toml");
+        let overrides = nested.join("overrides.toml");
+        std::fs::write(
+            &base,
+            r#"
+            directories_relative_to = "config_file"
+
+            [[services]]
+            name = "api"
+            command = "./api"
+            directory = "api"
+
+            [[services]]
+            name = "web"
+            command = "./web"
+            directory = "/srv/web"
+
+            [[stubs]]
+            name = "db"
+            directory = "db"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            &overrides,
+            "[[stubs]]\nname = \"db\"\ndirectory = \"../data\"\n",
+        )
+        .unwrap();
+        let source = ConfigSource::open(vec![base, overrides], None).unwrap();
+        let config = ConfigManager::load(&source);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+
+        let api = dir.join("api").to_string_lossy().into_owned();
+        let db = nested.join("../data").to_string_lossy().into_owned();
+        assert_eq!(config.services[0].directory, Some(api));
+        assert_eq!(config.services[1].directory.as_deref(), Some("/srv/web"));
+        assert_eq!(config.stubs[0].directory, Some(db));
+    }
+
+    #[test]
+    fn directories_are_relative_to_the_working_directory_by_default() {
+        let config = load_toml(
+            r#"
+            [[services]]
+            name = "api"
+            command = "./api"
+            directory = "api"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.services[0].directory.as_deref(), Some("api"));
+    }
+
+    #[test]
+    fn config_is_found_in_the_nearest_directory_up() {
+        let root = std::env::temp_dir().join(format!("procli-upwards-{}", std::process::id()));
+        let project = root.join("project");
+        let deep = project.join("src/bin");
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(root.join(DEFAULT_FILE), "").unwrap();
+        std::fs::write(project.join(DEFAULT_FILE), "").unwrap();
+        // A directory of the same name doesn't count.
+        std::fs::create_dir_all(project.join("src").join(DEFAULT_FILE)).unwrap();
+
+        let from_deep = find_upwards(&deep, DEFAULT_FILE);
+        let from_project = find_upwards(&project, DEFAULT_FILE);
+        let from_root = find_upwards(&root, DEFAULT_FILE);
+        let missing = find_upwards(&deep, "nothing-here.toml");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(from_deep, Some(project.join(DEFAULT_FILE)));
+        assert_eq!(from_project, Some(project.join(DEFAULT_FILE)));
+        assert_eq!(from_root, Some(root.join(DEFAULT_FILE)));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn stdin_cannot_be_layered() {
+        let paths = vec![PathBuf::from("procli.toml"), PathBuf::from(STDIN_PATH)];
+        assert!(ConfigSource::open(paths, None).is_err());
     }
 }
@@ -1,12 +1,36 @@
 use color_eyre::eyre::OptionExt;
-use futures::{FutureExt, StreamExt};
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use log::*;
+#[cfg(feature = "tui")]
 use ratatui::crossterm::event::Event as CrosstermEvent;
-use std::{process::ExitStatus, time::Duration};
-use tokio::sync::mpsc;
+use std::{
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal,
+    sync::{
+        mpsc::{self, UnboundedSender},
+        watch,
+    },
+};
 use uuid::Uuid;
 
-/// The frequency at which tick events are emitted.
+use crate::proc::{
+    container::Launch,
+    feed::{FeedCommand, FeedEvent},
+    health::HealthStatus,
+};
+
+/// The default frequency at which tick events are emitted.
 pub const TICK_FPS: f64 = 30.0;
+/// Tick rate while idle in power save mode.
+pub const IDLE_TICK_FPS: f64 = 2.0;
+/// How long without activity before power save slows things down.
+pub const IDLE_AFTER: Duration = Duration::from_secs(10);
 
 /// Representation of all possible events.
 #[derive(Clone, Debug)]
@@ -20,6 +44,7 @@ pub enum Event {
     /// Crossterm events.
     ///
     /// These events are emitted by the terminal.
+    #[cfg(feature = "tui")]
     Crossterm(CrosstermEvent),
     /// Application events.
     ///
@@ -36,6 +61,22 @@ pub enum AppEvent {
     Reload,
     StatsRefresh,
     ProcessDied(Uuid, ExitStatus),
+    /// Stop every process.
+    StopAll,
+    /// Restart every process.
+    RestartAll,
+    /// Start every process that has stopped and won't restart by itself.
+    StartStopped,
+    /// Reset a given-up process's restarts and start it again.
+    ResetAndRestart(String),
+    /// A process's health check found it in a new state.
+    HealthChanged(Uuid, HealthStatus),
+    /// Docker has said how a reused container should be launched, or why it
+    /// can't be.
+    ContainerChosen(Uuid, Result<Launch, String>),
+    /// A command from a client of the event socket, and where to send the
+    /// reply.
+    Control(FeedCommand, UnboundedSender<FeedEvent>),
     /// Quit the application.
     Quit,
 }
@@ -47,15 +88,72 @@ pub struct EventHandler {
     sender: mpsc::UnboundedSender<Event>,
     /// Event receiver channel.
     receiver: mpsc::UnboundedReceiver<Event>,
+    /// Current tick rate, shared with the event task.
+    tick_fps: watch::Sender<f64>,
+    /// Whether the event task should read terminal events.
+    read_terminal: watch::Sender<bool>,
+    /// Whether it is reading them, once it has caught up.
+    reading_terminal: watch::Receiver<bool>,
 }
 
 impl EventHandler {
     /// Constructs a new instance of [`EventTask`] and spawns a new thread to handle events.
-    pub fn new() -> Self {
+    ///
+    /// Tick events are emitted `tick_fps` times a second.
+    pub fn new(tick_fps: f64) -> Self {
+        Self::spawn(tick_fps, true, termination_signals())
+    }
+
+    /// Like [`EventHandler::new`] but without reading terminal events, for
+    /// running without a TUI.
+    pub fn headless(tick_fps: f64) -> Self {
+        Self::spawn(tick_fps, false, termination_signals())
+    }
+
+    /// Like [`EventHandler::headless`] but quitting on `signals` rather than
+    /// the process's own, so a test can ask it to stop.
+    #[cfg(all(test, unix, feature = "tui"))]
+    pub(crate) fn headless_with_signals(tick_fps: f64, signals: BoxStream<'static, ()>) -> Self {
+        Self::spawn(tick_fps, false, signals)
+    }
+
+    /// Either way, each of `signals` sends [`AppEvent::Quit`].
+    fn spawn(tick_fps: f64, read_terminal: bool, signals: BoxStream<'static, ()>) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel();
-        let actor = EventTask::new(sender.clone());
+        let (tick_fps, fps) = watch::channel(tick_fps);
+        let (read_terminal, read) = watch::channel(read_terminal);
+        let (reading, reading_terminal) = watch::channel(false);
+        let actor = EventTask::new(sender.clone(), fps, read, reading);
         tokio::spawn(async { actor.run().await });
-        Self { sender, receiver }
+        quit_on_signal(sender.clone(), signals);
+        Self {
+            sender,
+            receiver,
+            tick_fps,
+            read_terminal,
+            reading_terminal,
+        }
+    }
+
+    /// Stop or resume reading terminal events, waiting until the event task
+    /// has done so. Stopped while another program has the terminal, so it
+    /// gets every key press.
+    pub async fn set_read_terminal(&mut self, read: bool) {
+        self.read_terminal.send_replace(read);
+        // Only fails if the event task has gone, when nothing is read anyway.
+        let _ = self
+            .reading_terminal
+            .wait_for(|reading| *reading == read)
+            .await;
+    }
+
+    /// Change how often tick events are emitted.
+    pub fn set_tick_fps(&self, tick_fps: f64) {
+        self.tick_fps.send_if_modified(|current| {
+            let changed = *current != tick_fps;
+            *current = tick_fps;
+            changed
+        });
     }
 
     /// Receives an event from the sender.
@@ -92,7 +190,7 @@ impl EventHandler {
 
 impl Default for EventHandler {
     fn default() -> Self {
-        Self::new()
+        Self::new(TICK_FPS)
     }
 }
 
@@ -100,24 +198,41 @@ impl Default for EventHandler {
 struct EventTask {
     /// Event sender channel.
     sender: mpsc::UnboundedSender<Event>,
+    /// Tick events per second.
+    tick_fps: watch::Receiver<f64>,
+    /// Whether to read crossterm events.
+    read_terminal: watch::Receiver<bool>,
+    /// Set once the terminal is read, or no longer read, as asked.
+    reading: watch::Sender<bool>,
 }
 
 impl EventTask {
     /// Constructs a new instance of [`EventTask`].
-    fn new(sender: mpsc::UnboundedSender<Event>) -> Self {
-        Self { sender }
+    fn new(
+        sender: mpsc::UnboundedSender<Event>,
+        tick_fps: watch::Receiver<f64>,
+        read_terminal: watch::Receiver<bool>,
+        reading: watch::Sender<bool>,
+    ) -> Self {
+        Self {
+            sender,
+            tick_fps,
+            read_terminal,
+            reading,
+        }
     }
 
     /// Runs the event thread.
     ///
     /// This function emits tick events at a fixed rate and polls for crossterm events in between.
-    async fn run(self) -> color_eyre::Result<()> {
-        let tick_rate = Duration::from_secs_f64(1.0 / TICK_FPS);
-        let mut reader = crossterm::event::EventStream::new();
-        let mut tick = tokio::time::interval(tick_rate);
+    async fn run(mut self) -> color_eyre::Result<()> {
+        let tick_rate = |fps: f64| Duration::from_secs_f64(1.0 / fps);
+        let read = *self.read_terminal.borrow_and_update();
+        let mut terminal = terminal_events(read);
+        self.reading.send_replace(read);
+        let mut tick = tokio::time::interval(tick_rate(*self.tick_fps.borrow_and_update()));
         loop {
             let tick_delay = tick.tick();
-            let crossterm_event = reader.next().fuse();
             tokio::select! {
               _ = self.sender.closed() => {
                 break;
@@ -125,8 +240,18 @@ impl EventTask {
               _ = tick_delay => {
                 self.send(Event::Tick);
               }
-              Some(Ok(evt)) = crossterm_event => {
-                self.send(Event::Crossterm(evt));
+              Ok(()) = self.tick_fps.changed() => {
+                tick = tokio::time::interval(tick_rate(*self.tick_fps.borrow_and_update()));
+              }
+              Ok(()) = self.read_terminal.changed() => {
+                let read = *self.read_terminal.borrow_and_update();
+                // Dropping the old stream stops its reader before anyone is
+                // told.
+                terminal = terminal_events(read);
+                self.reading.send_replace(read);
+              }
+              Some(evt) = terminal.next() => {
+                self.send(evt);
               }
             };
         }
@@ -140,3 +265,118 @@ impl EventTask {
         let _ = self.sender.send(event);
     }
 }
+
+/// Ctrl-C (as a signal rather than a key) and, on Unix, SIGTERM: the ways
+/// the process is asked to stop.
+///
+/// SIGTERM is listened for before this returns, so it no longer kills us.
+fn termination_signals() -> BoxStream<'static, ()> {
+    #[cfg(unix)]
+    let terminated = match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(mut terminate) => stream::poll_fn(move |cx| terminate.poll_recv(cx)).boxed(),
+        Err(err) => {
+            warn!(target: "App", "Can't listen for SIGTERM: {}", err);
+            stream::pending().boxed()
+        }
+    };
+    #[cfg(not(unix))]
+    let terminated = stream::pending();
+    let interrupted = stream::unfold((), |()| async {
+        signal::ctrl_c().await.ok().map(|()| ((), ()))
+    });
+    stream::select(interrupted, terminated).boxed()
+}
+
+/// Send [`AppEvent::Quit`] on each of `signals`, so the app gets to stop its
+/// children rather than leaving them orphaned.
+fn quit_on_signal(sender: mpsc::UnboundedSender<Event>, mut signals: BoxStream<'static, ()>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = sender.closed() => break,
+                signalled = signals.next() => {
+                    if signalled.is_none() {
+                        break;
+                    }
+                }
+            }
+            info!(target: "App", "Asked to stop, quitting");
+            if sender.send(Event::App(AppEvent::Quit)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Terminal events, or none at all when not reading the terminal.
+#[cfg(feature = "tui")]
+fn terminal_events(read_terminal: bool) -> BoxStream<'static, Event> {
+    if !read_terminal {
+        return stream::pending().boxed();
+    }
+    crossterm::event::EventStream::new()
+        .filter_map(|evt| std::future::ready(evt.ok().map(Event::Crossterm)))
+        .boxed()
+}
+
+/// Without the TUI there is no terminal to read.
+#[cfg(not(feature = "tui"))]
+fn terminal_events(_read_terminal: bool) -> BoxStream<'static, Event> {
+    stream::pending().boxed()
+}
+
+/// Notices when nothing has happened for a while, so refresh rates can drop.
+#[derive(Debug)]
+pub struct IdleTracker {
+    last_activity: Instant,
+    output_seen: u64,
+}
+
+impl IdleTracker {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last_activity: now,
+            output_seen: 0,
+        }
+    }
+
+    /// Something happened, such as a key press.
+    pub fn poke(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// Whether things have been quiet for [`IDLE_AFTER`], given whether any
+    /// process is busy changing state and the running total of output lines.
+    pub fn is_idle(&mut self, now: Instant, busy: bool, output_lines: u64) -> bool {
+        if busy || output_lines != self.output_seen {
+            self.output_seen = output_lines;
+            self.last_activity = now;
+        }
+        now.duration_since(self.last_activity) >= IDLE_AFTER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_after_a_quiet_spell() {
+        let start = Instant::now();
+        let mut idle = IdleTracker::new(start);
+        assert!(!idle.is_idle(start + Duration::from_secs(1), false, 0));
+        assert!(idle.is_idle(start + IDLE_AFTER, false, 0));
+    }
+
+    #[test]
+    fn activity_resets_the_quiet_spell() {
+        let start = Instant::now();
+        let later = start + IDLE_AFTER;
+        let mut idle = IdleTracker::new(start);
+        assert!(!idle.is_idle(later, false, 5), "new output");
+        assert!(!idle.is_idle(later + IDLE_AFTER, true, 5), "busy process");
+        idle.poke(later + IDLE_AFTER * 2);
+        assert!(!idle.is_idle(later + IDLE_AFTER * 2, false, 5), "key press");
+        assert!(idle.is_idle(later + IDLE_AFTER * 3, false, 5));
+    }
+}
@@ -0,0 +1,47 @@
+//! Process supervision for development stacks, as used by the `procli` binary.
+//!
+//! The terminal UI ([`app`] and [`ui`]) is behind the default `tui` feature.
+//! Without it, processes can still be supervised from your own tool: events
+//! from the processes arrive on the channel given to the [`ProcessManager`],
+//! and stats refreshes and restarts happen when you call
+//! [`ProcessManager::tick`] on [`AppEvent::StatsRefresh`].
+//!
+//! ```no_run
+//! use procli::{AppEvent, Event, ProcessManager, Service};
+//!
+//! # async fn run() -> color_eyre::Result<()> {
+//! let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+//! let mut manager = ProcessManager::new(sender);
+//! manager.upsert(&Service {
+//!     name: "api".to_string(),
+//!     command: Some("./api --port 8080".to_string()),
+//!     ..Default::default()
+//! })?;
+//! while let Some(event) = events.recv().await {
+//!     match event {
+//!         Event::App(AppEvent::StatsRefresh) => manager.tick(),
+//!         Event::App(AppEvent::ProcessDied(id, status)) => manager.process_died(id, status),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "tui")]
+pub mod app;
+pub mod config;
+pub mod event;
+pub mod log_levels;
+pub mod proc;
+pub mod resample;
+#[cfg(feature = "tui")]
+pub mod ui;
+pub mod update;
+
+pub use config::{Agent, ConfigManager, ConfigSource, ProcliConfig, RestartPolicy, Service, Stub};
+pub use event::{AppEvent, Event, EventHandler};
+pub use proc::{
+    manager::{Health, ProcessManager},
+    process::{Process, ProcessKind, ProcessRestart, ProcessState},
+};
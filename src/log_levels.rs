@@ -0,0 +1,120 @@
+//! Log levels as given to `--log-level` or `log_level` in the config: a
+//! default level and levels for particular targets, such as
+//! `info,App=debug,Config=warn`.
+
+use std::{fmt, str::FromStr};
+
+use log::LevelFilter;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevels {
+    /// The level for any target not listed.
+    pub default: LevelFilter,
+    /// Levels for particular targets, such as `App` or a process name.
+    pub targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl LogLevels {
+    /// The most verbose level any target is logged at.
+    pub fn max(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.parse().map_err(|_| {
+        format!("unknown log level `{s}`, expected off, error, warn, info, debug or trace")
+    })
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut default = None;
+        let mut targets = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(format!("missing target before `={}`", level.trim()));
+                    }
+                    targets.push((target.to_string(), parse_level(level.trim())?));
+                }
+                None if default.is_some() => {
+                    return Err(format!("more than one default level in `{s}`"));
+                }
+                None => default = Some(parse_level(part)?),
+            }
+        }
+        Ok(Self {
+            default: default.unwrap_or(LevelFilter::Info),
+            targets,
+        })
+    }
+}
+
+impl fmt::Display for LogLevels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (target, level) in &self.targets {
+            write!(f, ",{}={}", target, level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_default_and_per_target_levels() {
+        let levels: LogLevels = "warn, App=debug,Config=ERROR".parse().unwrap();
+        assert_eq!(levels.default, LevelFilter::Warn);
+        assert_eq!(
+            levels.targets,
+            [
+                ("App".to_string(), LevelFilter::Debug),
+                ("Config".to_string(), LevelFilter::Error)
+            ]
+        );
+        assert_eq!(levels.max(), LevelFilter::Debug);
+        assert_eq!(levels.to_string(), "warn,App=debug,Config=error");
+    }
+
+    #[test]
+    fn default_is_info() {
+        assert_eq!("".parse::<LogLevels>().unwrap(), LogLevels::default());
+        let levels: LogLevels = "api=trace".parse().unwrap();
+        assert_eq!(levels.default, LevelFilter::Info);
+        assert_eq!(levels.max(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn reports_bad_specs() {
+        let err = |s: &str| s.parse::<LogLevels>().unwrap_err();
+        assert_eq!(
+            err("App=loud"),
+            "unknown log level `loud`, expected off, error, warn, info, debug or trace"
+        );
+        assert_eq!(err("=debug"), "missing target before `=debug`");
+        assert_eq!(
+            err("info,warn"),
+            "more than one default level in `info,warn`"
+        );
+    }
+}
@@ -1,24 +1,51 @@
 use clap::{Parser, Subcommand};
+use color_eyre::eyre::eyre;
 use log::info;
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 use tui_logger::{
-    TuiLoggerFile, TuiLoggerLevelOutput, init_logger, set_default_level, set_log_file,
+    TuiLoggerFile, TuiLoggerLevelOutput, init_logger, set_default_level, set_level_for_target,
+    set_log_file,
 };
 
-use crate::app::App;
-
-pub mod app;
-pub mod config;
-pub mod event;
-pub mod proc;
-pub mod resample;
-pub mod ui;
+use procli::{
+    app::{self, App},
+    config, event,
+    log_levels::LogLevels,
+    proc::{
+        ansi::ColorMode,
+        command::{check_command, command_line},
+        feed::FeedCommand,
+        process::Process,
+    },
+};
 
 #[derive(Parser, Debug)]
 #[command(about)]
 struct Cli {
-    #[arg(short, long, value_name = "FILE", default_value = config::DEFAULT_FILE)]
-    config: PathBuf,
+    /// Configuration file to load. Repeat to layer files, later ones
+    /// overriding earlier ones. Defaults to the nearest `procli.toml` in this
+    /// directory or one above it
+    #[arg(short, long, value_name = "FILE")]
+    config: Vec<PathBuf>,
+    /// Print which configuration file would be used, then exit
+    #[arg(long)]
+    print_config_path: bool,
+    /// Format of the configuration: toml, yaml or json. Needed when reading
+    /// it from stdin with `--config -`, which is otherwise taken as TOML
+    #[arg(long, value_name = "FORMAT")]
+    config_format: Option<config::ConfigFormat>,
+    /// When to use colour: auto, always or never. `auto` colours a terminal
+    /// unless `NO_COLOR` is set
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: ColorMode,
+    /// How much to log: a level, optionally followed by levels for
+    /// particular targets, as in `info,App=debug,Config=warn`. Overrides
+    /// `log_level` in the config; `info` if neither is set
+    #[arg(long, value_name = "LEVELS")]
+    log_level: Option<LogLevels>,
+    /// UI refresh and animation rate in frames per second
+    #[arg(long, value_name = "FPS", default_value_t = event::TICK_FPS, value_parser = parse_fps)]
+    fps: f64,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -26,30 +53,287 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Run all processes and monitor
-    Run,
+    Run {
+        /// Only run these processes and the ones they depend on
+        #[arg(long, value_name = "NAME", value_delimiter = ',')]
+        only: Vec<String>,
+        /// Don't reload the configuration when the file changes
+        #[arg(long)]
+        no_watch: bool,
+        /// Run without the TUI until every process is running, then exit.
+        /// Exits non-zero if any fails or the timeout elapses
+        #[arg(long)]
+        wait_healthy: bool,
+        /// Print the command each process would run, without starting anything
+        #[arg(long, conflicts_with = "wait_healthy")]
+        dry_run: bool,
+        /// Supervise without the TUI, logging to stdout, until stopped by
+        /// SIGTERM or Ctrl-C. For systemd, containers and other places
+        /// without a terminal
+        #[arg(long, conflicts_with_all = ["wait_healthy", "dry_run"])]
+        headless: bool,
+        /// Seconds to wait for processes to become healthy
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60,
+            requires = "wait_healthy"
+        )]
+        timeout: u64,
+    },
     /// Validate the configuration file
-    Validate,
+    Validate {
+        /// Also build each process's command, without running it, to check
+        /// that its program and directory can be found
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Stop a process in the procli already running with this config, via
+    /// its `control_socket`
+    Stop {
+        /// The process to stop
+        name: String,
+    },
+    /// Start a stopped process in the procli already running with this
+    /// config, via its `control_socket`
+    Start {
+        /// The process to start
+        name: String,
+    },
+    /// Print a JSON Schema for the configuration file
+    Schema,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the effective configuration, after defaults and overrides, as TOML
+    Dump,
+}
+
+fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Print what each stub and service would run.
+fn dry_run(config: &config::ProcliConfig) -> color_eyre::Result<()> {
+    let stubs = config.stubs.iter().map(Process::new);
+    let services = config.services.iter().map(Process::new);
+    for process in stubs.chain(services) {
+        let process = process?;
+        let cmd = process.cmd.as_std();
+        println!("{}", process.name);
+        println!("  command: {}", command_line(&process.cmd));
+        if let Some(dir) = &process.directory {
+            println!("  directory: {}", dir.display());
+        }
+        let mut envs: Vec<String> = cmd
+            .get_envs()
+            .map(|(k, v)| {
+                let v = v.unwrap_or_default();
+                format!("{}={}", k.display(), v.display())
+            })
+            .collect();
+        envs.sort();
+        for env in envs {
+            println!("  env: {env}");
+        }
+    }
+    Ok(())
+}
+
+/// Build every stub's and service's command, reporting each that fails.
+fn check_commands(config: &config::ProcliConfig) -> color_eyre::Result<()> {
+    let stubs = config.stubs.iter().map(|s| (&s.name, check_command(s)));
+    let services = config.services.iter().map(|s| (&s.name, check_command(s)));
+    let mut failed = 0;
+    for (name, result) in stubs.chain(services) {
+        if let Err(err) = result {
+            eprintln!("error: {name}: {err}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} process(es) would fail to start", failed));
+    }
+    Ok(())
+}
+
+/// Send a command to the running procli and print the process's state.
+async fn control(source: &config::ConfigSource, command: FeedCommand) -> color_eyre::Result<()> {
+    let config = config::ConfigManager::load(source)?;
+    let path = config
+        .control_socket
+        .ok_or_else(|| eyre!("{} has no control_socket to reach a running procli", source))?;
+    #[cfg(unix)]
+    {
+        let state = procli::proc::feed::request(&path, &command).await?;
+        println!("{}: {}", command.name(), state);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    Err(eyre!(
+        "Can't reach {} for {}: control sockets are Unix only",
+        path.display(),
+        command.name()
+    ))
+}
+
+/// The `log_level` from the config, if it loads and sets one. Anything
+/// wrong with the config is reported when the app loads it properly.
+fn configured_log_levels(source: &config::ConfigSource) -> Option<LogLevels> {
+    let (config, _) = config::ConfigManager::load_with_warnings(source).ok()?;
+    config.log_level?.parse().ok()
+}
+
+/// Capture logs for the log panel and mirror them to `procli.log`.
+fn init_logging(levels: &LogLevels) -> color_eyre::Result<()> {
+    init_logger(levels.max())?;
+    set_default_level(levels.default);
+    for (target, level) in &levels.targets {
+        set_level_for_target(target, *level);
+    }
+    let file_options = TuiLoggerFile::new(app::LOG_FILE)
+        .output_level(Some(TuiLoggerLevelOutput::Abbreviated))
+        .output_file(false)
+        .output_separator(':');
+    set_log_file(file_options);
+    info!("Logging started");
+    Ok(())
+}
+
+/// Log to stdout at the levels given by `--log-level`, else `RUST_LOG`, else
+/// the config.
+fn init_stdout_logging(color: bool, cli: Option<&LogLevels>, configured: Option<LogLevels>) {
+    let filters = match (cli, std::env::var("RUST_LOG")) {
+        (Some(levels), _) => levels.to_string(),
+        (None, Ok(filters)) => filters,
+        (None, Err(_)) => configured.unwrap_or_default().to_string(),
+    };
+    env_logger::Builder::new()
+        .parse_filters(&filters)
+        .target(env_logger::Target::Stdout)
+        .write_style(if color {
+            env_logger::WriteStyle::Always
+        } else {
+            env_logger::WriteStyle::Never
+        })
+        .init();
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
-    color_eyre::install()?;
     let cli = Cli::parse();
-    match &cli.command {
-        Some(Commands::Validate) => Ok(()),
-        Some(Commands::Run) | None => {
-            init_logger(tui_logger::LevelFilter::Debug)?;
-            let file_options = TuiLoggerFile::new("procli.log")
-                .output_level(Some(TuiLoggerLevelOutput::Abbreviated))
-                .output_file(false)
-                .output_separator(':');
-            set_log_file(file_options);
-            info!("Logging started");
-            let mut app = App::new(cli.config)?;
-            set_default_level(tui_logger::LevelFilter::Debug);
+    let color = cli.color.enabled_for_stdout();
+    if color {
+        color_eyre::install()?;
+    } else {
+        color_eyre::config::HookBuilder::blank().install()?;
+    }
+    let command = cli.command.unwrap_or(Commands::Run {
+        only: Vec::new(),
+        no_watch: false,
+        wait_healthy: false,
+        dry_run: false,
+        headless: false,
+        timeout: 60,
+    });
+    let source = config::ConfigSource::open(cli.config, cli.config_format)?;
+    if cli.print_config_path {
+        println!("{}", source);
+        return Ok(());
+    }
+    let log_levels = |source: &config::ConfigSource| {
+        cli.log_level
+            .clone()
+            .or_else(|| configured_log_levels(source))
+            .unwrap_or_default()
+    };
+    match command {
+        Commands::Validate { strict } => {
+            let (config, warnings) = config::ConfigManager::load_with_warnings(&source)?;
+            for warning in warnings {
+                eprintln!("warning: {warning}");
+            }
+            if strict {
+                check_commands(&config)?;
+            }
+            println!("{} is valid", source);
+            Ok(())
+        }
+        Commands::Stop { name } => control(&source, FeedCommand::Stop { name }).await,
+        Commands::Start { name } => control(&source, FeedCommand::Start { name }).await,
+        Commands::Schema => {
+            println!("{}", serde_json::to_string_pretty(&config::schema())?);
+            Ok(())
+        }
+        Commands::Config {
+            command: ConfigCommands::Dump,
+        } => {
+            let config = config::ConfigManager::load_raw(&source)?;
+            print!("{}", toml::to_string_pretty(&config)?);
+            Ok(())
+        }
+        Commands::Run {
+            only,
+            dry_run: true,
+            ..
+        } => {
+            let config = config::ConfigManager::load(&source)?.only(&only)?;
+            dry_run(&config)
+        }
+        Commands::Run {
+            only,
+            wait_healthy: true,
+            timeout,
+            ..
+        } => {
+            init_logging(&log_levels(&source))?;
+            let mut app = App::headless(source, cli.fps, only, false)?;
+            app.set_color(color);
+            let healthy = app.wait_healthy(Duration::from_secs(timeout)).await?;
+            for line in app.status_summary() {
+                println!("{line}");
+            }
+            app.shutdown().await?;
+            if healthy {
+                Ok(())
+            } else {
+                Err(eyre!("Not every process became healthy"))
+            }
+        }
+        Commands::Run {
+            only,
+            no_watch,
+            headless: true,
+            ..
+        } => {
+            init_stdout_logging(
+                color,
+                cli.log_level.as_ref(),
+                configured_log_levels(&source),
+            );
+            let mut app = App::headless(source, cli.fps, only, !no_watch)?;
+            app.set_color(color);
+            app.run_headless().await
+        }
+        Commands::Run { only, no_watch, .. } => {
+            init_logging(&log_levels(&source))?;
+            let mut app = App::new(source, cli.fps, only, !no_watch)?;
+            app.set_color(color);
             let terminal = ratatui::init();
             let result = app.run(terminal).await;
+            app::set_bracketed_paste(false);
             ratatui::restore();
+            app.shutdown().await?;
             result
         }
     }
@@ -1,4 +1,13 @@
-mod command;
+pub mod ansi;
+pub mod command;
+pub mod container;
+pub mod feed;
+pub mod health;
 pub mod manager;
 pub mod process;
+pub mod rate_limit;
 pub mod stats;
+pub mod statsd;
+pub mod terminate;
+#[cfg(all(test, unix))]
+pub(crate) mod testing;
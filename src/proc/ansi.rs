@@ -0,0 +1,132 @@
+//! Escape sequences in process output, and whether to use colour at all.
+//!
+//! The log widget styles lines by level only, so colours and other escapes
+//! are stripped to leave readable text.
+
+use std::{ffi::OsStr, io::IsTerminal, str::FromStr};
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// Remove ANSI escape sequences: CSI (colours, cursor movement), OSC (titles,
+/// hyperlinks) and other two character escapes.
+pub fn strip(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Parameters and intermediates, up to a final byte in @ to ~.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Ends with BEL or ST (ESC \).
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// When to use colour, as chosen with `--color`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Colour when writing to a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether to use colour, given whether output goes to a terminal and the
+    /// value of `NO_COLOR`, which counts only when it isn't empty.
+    pub fn enabled(self, terminal: bool, no_color: Option<&OsStr>) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => terminal && no_color.is_none_or(|v| v.is_empty()),
+        }
+    }
+
+    /// Whether to use colour on stdout, in this environment.
+    pub fn enabled_for_stdout(self) -> bool {
+        self.enabled(
+            std::io::stdout().is_terminal(),
+            std::env::var_os("NO_COLOR").as_deref(),
+        )
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!(
+                "unknown colour mode `{s}`, expected auto, always or never"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colour_follows_the_flag_then_no_color_then_the_terminal() {
+        let set = Some(OsStr::new("1"));
+        let empty = Some(OsStr::new(""));
+        assert!(ColorMode::Auto.enabled(true, None));
+        assert!(ColorMode::Auto.enabled(true, empty));
+        assert!(!ColorMode::Auto.enabled(true, set));
+        assert!(!ColorMode::Auto.enabled(false, None));
+        assert!(ColorMode::Always.enabled(false, set));
+        assert!(!ColorMode::Never.enabled(true, None));
+        assert_eq!("NEVER".parse(), Ok(ColorMode::Never));
+        assert!("sometimes".parse::<ColorMode>().is_err());
+    }
+
+    #[test]
+    fn colours_are_stripped() {
+        assert_eq!(
+            strip("\x1b[1m\x1b[32m   Compiling\x1b[0m procli v0.1.1"),
+            "   Compiling procli v0.1.1"
+        );
+        assert_eq!(strip("\x1b[38;5;208morange\x1b[39m"), "orange");
+    }
+
+    #[test]
+    fn osc_and_short_escapes_are_stripped() {
+        assert_eq!(
+            strip("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\ \x1b]0;title\x07done\x1b="),
+            "link done"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_unchanged() {
+        assert_eq!(strip("plain [text] ~"), "plain [text] ~");
+    }
+}
@@ -1,51 +1,85 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
 use color_eyre::eyre::eyre;
 use tokio::process::Command;
 
-use crate::proc::process::ProcessConfig;
+use crate::{
+    config::HealthCheck,
+    proc::{
+        container::{FINGERPRINT_LABEL, Reuse, fingerprint},
+        process::{Named, ProcessConfig},
+    },
+};
 
 pub fn build_command<T>(from: &T) -> color_eyre::Result<Command>
 where
-    T: ProcessConfig,
+    T: Named + ProcessConfig,
 {
     let cmd = match from.image() {
         Some(image) => {
             // Docker based:
-            //  `docker run --rm -e K=V -w /opt/mounted -v <dir>:/opt/mounted <image> <command>`
+            //  `docker run --rm --interactive --name procli-<name> -e K=V -w <target> -v <dir>:<target> [--entrypoint <e>] <image> <command>`
+            // Reused containers are kept (no `--rm`) and labelled with a
+            // fingerprint of the options after the name.
+            let options = docker_run_options(from, image)?;
             let mut c = Command::new("docker");
-            c.args(["run", "--rm"]);
-            // env vars
-            for (k, v) in from.environment() {
-                c.arg("-e").arg(format!("{}={}", k, v));
-            }
-            // optional directory mount
-            if let Some(d) = from.directory()? {
-                let mut mount = d;
-                mount.push("");
-                c.args(["-w", "/opt/mounted", "-v"]).arg(mount);
+            c.arg("run");
+            if !from.reuse() {
+                c.arg("--rm");
             }
-            c.arg(image);
-            // optional command
-            if let Some(c2) = from.command() {
-                let strings = shlex::split(&c2).ok_or(eyre!("Bad command string"))?;
-                c.args(strings);
+            c.args(["--interactive", "--name"])
+                .arg(from.container_name());
+            if from.reuse() {
+                let fingerprint = fingerprint(options.iter().map(OsString::as_os_str));
+                c.arg("--label")
+                    .arg(format!("{FINGERPRINT_LABEL}={fingerprint}"));
             }
+            c.args(options);
             c
         }
         None => {
             // Local command:
+            let mut inline = Vec::new();
             let command = from
                 .command()
                 .ok_or(eyre!("Must specify command if no image"))?;
-            let strings = shlex::split(&command).ok_or(eyre!("Bad command string"))?;
-            let program = strings
-                .first()
-                .ok_or(eyre!("Must specify command if no image"))?;
-            let mut c = Command::new(program);
-            c.args(strings.iter().skip(1));
-            // Env vars
+            let mut c = if from.shell() {
+                if !from.args().is_empty() {
+                    return Err(eyre!(
+                        "`args` can't be used with `shell`; put them in `command`"
+                    ));
+                }
+                shell_command(&command)
+            } else {
+                let strings = shlex::split(&command).ok_or(eyre!("Bad command string"))?;
+                let (assignments, words) = split_assignments(&strings);
+                let program = words.first().ok_or_else(|| {
+                    if assignments.is_empty() {
+                        eyre!("Must specify command if no image")
+                    } else {
+                        eyre!("No program after the environment assignments")
+                    }
+                })?;
+                // Arguments split from the command come first, then `args`
+                // untouched.
+                let mut c = Command::new(program);
+                c.args(words.iter().skip(1)).args(from.args());
+                inline = assignments;
+                c
+            };
+            // Env vars, with any given before the program winning as they
+            // would in a shell
             for (k, v) in &from.environment() {
                 c.env(k, v);
             }
+            for (k, v) in inline {
+                c.env(k, v);
+            }
             // Optional dir
             if let Some(d) = from.directory()? {
                 c.current_dir(d);
@@ -55,3 +89,604 @@ where
     };
     Ok(cmd)
 }
+
+/// Take shell style `KEY=VALUE` assignments off the front of a split command,
+/// returning them and the words that follow.
+fn split_assignments(words: &[String]) -> (Vec<(String, String)>, &[String]) {
+    let assignment = |word: &String| {
+        let (key, value) = word.split_once('=')?;
+        let mut chars = key.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        valid.then(|| (key.to_string(), value.to_string()))
+    };
+    let assignments: Vec<_> = words.iter().map_while(assignment).collect();
+    let rest = &words[assignments.len()..];
+    (assignments, rest)
+}
+
+/// The `docker run` options, image and command for an image based process.
+fn docker_run_options<T>(from: &T, image: String) -> color_eyre::Result<Vec<OsString>>
+where
+    T: ProcessConfig,
+{
+    let mut options: Vec<OsString> = Vec::new();
+    // env vars, sorted so the same config always gives the same options
+    let mut environment: Vec<_> = from.environment().into_iter().collect();
+    environment.sort();
+    for (k, v) in environment {
+        options.push("-e".into());
+        options.push(format!("{}={}", k, v).into());
+    }
+    // optional directory mount
+    if let Some(d) = from.directory()?
+        && from.mount_directory()
+    {
+        let target = from.mount_target();
+        let mut mount = d;
+        mount.push(":");
+        mount.push(&target);
+        options.extend(["-w".into(), target.into(), "-v".into(), mount]);
+    }
+    if let Some(entrypoint) = from.entrypoint() {
+        options.extend(["--entrypoint".into(), entrypoint.into()]);
+    }
+    options.push(image.into());
+    // optional command
+    if let Some(c2) = from.command() {
+        let strings = shlex::split(&c2).ok_or(eyre!("Bad command string"))?;
+        options.extend(strings.into_iter().map(OsString::from));
+    }
+    options.extend(from.args().into_iter().map(OsString::from));
+    Ok(options)
+}
+
+/// How to start a reused container again, for image based processes with
+/// `reuse` set.
+pub fn build_reuse<T>(from: &T) -> color_eyre::Result<Option<Reuse>>
+where
+    T: Named + ProcessConfig,
+{
+    let Some(image) = from.image().filter(|_| from.reuse()) else {
+        return Ok(None);
+    };
+    let options = docker_run_options(from, image)?;
+    let container = from.container_name();
+    let mut start = Command::new("docker");
+    start
+        .args(["start", "--attach", "--interactive"])
+        .arg(&container);
+    Ok(Some(Reuse {
+        container,
+        fingerprint: fingerprint(options.iter().map(OsString::as_os_str)),
+        start,
+    }))
+}
+
+/// A shell command run on the host alongside a process, in its directory and
+/// with its environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    command: String,
+    environment: HashMap<String, String>,
+    directory: Option<OsString>,
+}
+
+impl Hook {
+    pub fn command(&self) -> Command {
+        let mut c = shell_command(&self.command);
+        c.envs(&self.environment);
+        if let Some(d) = &self.directory {
+            c.current_dir(d);
+        }
+        c
+    }
+}
+
+/// The hook to run once a process is running, if it has one.
+pub fn build_post_start<T>(from: &T) -> color_eyre::Result<Option<Hook>>
+where
+    T: ProcessConfig,
+{
+    from.post_start()
+        .map(|command| build_hook(from, command))
+        .transpose()
+}
+
+/// The health check and how to run its command, if there is one.
+pub fn build_health_check<T>(from: &T) -> color_eyre::Result<Option<(HealthCheck, Hook)>>
+where
+    T: ProcessConfig,
+{
+    let Some(check) = from.health_check() else {
+        return Ok(None);
+    };
+    let hook = build_hook(from, check.command.clone())?;
+    Ok(Some((check, hook)))
+}
+
+fn build_hook<T>(from: &T, command: String) -> color_eyre::Result<Hook>
+where
+    T: ProcessConfig,
+{
+    Ok(Hook {
+        command,
+        environment: from.environment(),
+        directory: from.directory()?,
+    })
+}
+
+/// Build the command for a process without running it, and check that its
+/// program can be found and its directory exists.
+pub fn check_command<T>(from: &T) -> color_eyre::Result<()>
+where
+    T: Named + ProcessConfig,
+{
+    let cmd = build_command(from)?;
+    let cmd = cmd.as_std();
+    if let Some(dir) = from.directory()?
+        && !Path::new(&dir).is_dir()
+    {
+        return Err(eyre!("Directory {} does not exist", dir.display()));
+    }
+    let program = cmd.get_program();
+    let found = if Path::new(program).components().count() > 1 {
+        // A path, which the child resolves from its own directory.
+        cmd.get_current_dir()
+            .map_or_else(|| PathBuf::from(program), |dir| dir.join(program))
+            .is_file()
+    } else {
+        find_on_path(program)
+    };
+    if !found {
+        return Err(eyre!("Program {} not found", program.display()));
+    }
+    Ok(())
+}
+
+/// Whether a program name can be found in one of the `PATH` directories.
+fn find_on_path(program: &OsStr) -> bool {
+    let Some(path) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(program);
+        candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+    })
+}
+
+/// The program and arguments of a command as one line.
+pub fn command_line(cmd: &Command) -> String {
+    let cmd = cmd.as_std();
+    let args = cmd.get_args().collect::<Vec<_>>().join(OsStr::new(" "));
+    format!("{} {}", cmd.get_program().display(), args.display())
+}
+
+/// A shell snippet that runs a command by hand as procli would: changing to
+/// its directory, then running it with its environment, quoted for `sh`.
+pub fn shell_snippet(cmd: &Command) -> String {
+    let cmd = cmd.as_std();
+    let quote = |s: &OsStr| {
+        let s = s.to_string_lossy();
+        shlex::try_quote(&s).map_or_else(|_| s.to_string(), |q| q.to_string())
+    };
+    let mut envs: Vec<String> = cmd
+        .get_envs()
+        .filter_map(|(k, v)| v.map(|v| format!("{}={}", k.display(), quote(v))))
+        .collect();
+    envs.sort();
+    let words = envs
+        .into_iter()
+        .chain(std::iter::once(quote(cmd.get_program())))
+        .chain(cmd.get_args().map(quote))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match cmd.get_current_dir() {
+        Some(dir) => format!("cd {} && {}", quote(dir.as_os_str()), words),
+        None => words,
+    }
+}
+
+/// Run a command line through the platform shell.
+fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+    let mut c = Command::new(shell);
+    c.arg(flag).arg(command);
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Service;
+
+    fn argv(svc: &Service) -> Vec<String> {
+        let cmd = build_command(svc).unwrap();
+        let cmd = cmd.as_std();
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn env(svc: &Service) -> Vec<(String, String)> {
+        let cmd = build_command(svc).unwrap();
+        let mut envs: Vec<_> = cmd
+            .as_std()
+            .get_envs()
+            .filter_map(|(k, v)| Some((k.to_str()?.to_string(), v?.to_str()?.to_string())))
+            .collect();
+        envs.sort();
+        envs
+    }
+
+    fn local(command: &str) -> Service {
+        Service {
+            command: Some(command.to_string()),
+            environment: HashMap::from([("MODE".to_string(), "dev".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn command_without_assignments_runs_as_is() {
+        let svc = local("./run --opt=1 PORT=80");
+        assert_eq!(argv(&svc), ["./run", "--opt=1", "PORT=80"]);
+        assert_eq!(env(&svc), [("MODE".to_string(), "dev".to_string())]);
+    }
+
+    #[test]
+    fn leading_assignment_sets_the_environment() {
+        let svc = local("PORT=8080 ./run --fast");
+        assert_eq!(argv(&svc), ["./run", "--fast"]);
+        assert_eq!(
+            env(&svc),
+            [
+                ("MODE".to_string(), "dev".to_string()),
+                ("PORT".to_string(), "8080".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn several_leading_assignments_override_the_environment() {
+        let svc = local("MODE=prod _A1='two words' EMPTY= ./run");
+        assert_eq!(argv(&svc), ["./run"]);
+        assert_eq!(
+            env(&svc),
+            [
+                ("EMPTY".to_string(), String::new()),
+                ("MODE".to_string(), "prod".to_string()),
+                ("_A1".to_string(), "two words".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_need_a_program_after_them() {
+        let err = build_command(&local("PORT=8080")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No program after the environment assignments"
+        );
+        // Not a variable name, so it is the program.
+        assert_eq!(argv(&local("1X=2 ./run")), ["1X=2", "./run"]);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn check_finds_programs_on_the_path() {
+        let svc = Service {
+            command: Some("sh -c true".to_string()),
+            ..Default::default()
+        };
+        assert!(check_command(&svc).is_ok());
+    }
+
+    #[test]
+    fn check_reports_missing_programs_and_directories() {
+        let svc = Service {
+            command: Some("procli-no-such-program --flag".to_string()),
+            ..Default::default()
+        };
+        let err = check_command(&svc).unwrap_err();
+        assert_eq!(err.to_string(), "Program procli-no-such-program not found");
+        let svc = Service {
+            command: Some("./run".to_string()),
+            directory: Some("no/such/dir".to_string()),
+            ..Default::default()
+        };
+        let err = check_command(&svc).unwrap_err();
+        assert!(err.to_string().starts_with("Directory "));
+    }
+
+    #[test]
+    fn check_reports_bad_command_strings() {
+        let svc = Service {
+            command: Some("echo 'unterminated".to_string()),
+            ..Default::default()
+        };
+        let err = check_command(&svc).unwrap_err();
+        assert_eq!(err.to_string(), "Bad command string");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn snippet_quotes_directory_environment_and_arguments() {
+        let svc = Service {
+            command: Some("echo 'hello world'".to_string()),
+            directory: Some("/srv/my app".to_string()),
+            environment: [("GREETING".to_string(), "hi there".to_string())].into(),
+            ..Default::default()
+        };
+        let cmd = build_command(&svc).unwrap();
+        assert_eq!(
+            shell_snippet(&cmd),
+            "cd '/srv/my app' && GREETING='hi there' echo 'hello world'"
+        );
+    }
+
+    #[test]
+    fn local_command_is_split_into_arguments() {
+        let svc = Service {
+            command: Some("echo 'hello world' | wc".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(argv(&svc), ["echo", "hello world", "|", "wc"]);
+    }
+
+    #[test]
+    fn image_mounts_directory_at_the_default_target() {
+        let svc = Service {
+            name: "web".to_string(),
+            image: Some("nginx".to_string()),
+            directory: Some("/srv/site".to_string()),
+            ..Default::default()
+        };
+        let dir = std::path::absolute("/srv/site").unwrap();
+        let mount = format!("{}:/opt/mounted", dir.display());
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--interactive",
+                "--name",
+                "procli-web",
+                "-w",
+                "/opt/mounted",
+                "-v",
+                &mount,
+                "nginx"
+            ]
+        );
+    }
+
+    #[test]
+    fn image_mounts_directory_at_a_custom_target() {
+        let svc = Service {
+            name: "app".to_string(),
+            image: Some("node".to_string()),
+            command: Some("npm start".to_string()),
+            directory: Some("/srv/app".to_string()),
+            mount_target: Some("/app".to_string()),
+            ..Default::default()
+        };
+        let dir = std::path::absolute("/srv/app").unwrap();
+        let mount = format!("{}:/app", dir.display());
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--interactive",
+                "--name",
+                "procli-app",
+                "-w",
+                "/app",
+                "-v",
+                &mount,
+                "node",
+                "npm",
+                "start"
+            ]
+        );
+    }
+
+    #[test]
+    fn image_can_leave_the_directory_unmounted() {
+        let svc = Service {
+            name: "web".to_string(),
+            image: Some("nginx".to_string()),
+            directory: Some("/srv/site".to_string()),
+            mount_directory: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--interactive",
+                "--name",
+                "procli-web",
+                "nginx"
+            ]
+        );
+    }
+
+    #[test]
+    fn reused_containers_are_kept_and_labelled() {
+        let svc = Service {
+            name: "db".to_string(),
+            image: Some("postgres".to_string()),
+            container_name: Some("dev-db".to_string()),
+            reuse: true,
+            ..Default::default()
+        };
+        let reuse = build_reuse(&svc).unwrap().unwrap();
+        let label = format!("{FINGERPRINT_LABEL}={}", reuse.fingerprint);
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--interactive",
+                "--name",
+                "dev-db",
+                "--label",
+                &label,
+                "postgres"
+            ]
+        );
+        let start = reuse.start.as_std();
+        assert_eq!(
+            start.get_args().collect::<Vec<_>>(),
+            ["start", "--attach", "--interactive", "dev-db"]
+        );
+    }
+
+    #[test]
+    fn fingerprint_ignores_the_order_of_the_environment() {
+        let svc = || Service {
+            name: "db".to_string(),
+            image: Some("postgres".to_string()),
+            reuse: true,
+            environment: HashMap::from(
+                ["PGUSER", "PGPASSWORD", "PGDATABASE", "PGPORT", "TZ", "LANG"]
+                    .map(|key| (key.to_string(), key.to_lowercase())),
+            ),
+            ..Default::default()
+        };
+        let fingerprints: Vec<_> = (0..4)
+            .map(|_| build_reuse(&svc()).unwrap().unwrap().fingerprint)
+            .collect();
+        assert!(fingerprints.iter().all(|f| *f == fingerprints[0]));
+        let args = argv(&svc());
+        let env: Vec<_> = args
+            .windows(2)
+            .filter(|w| w[0] == "-e")
+            .map(|w| &w[1])
+            .collect();
+        assert_eq!(env.len(), 6);
+        assert!(env.is_sorted(), "{env:?}");
+    }
+
+    #[test]
+    fn entrypoint_comes_before_the_image() {
+        let svc = Service {
+            name: "debug".to_string(),
+            image: Some("busybox".to_string()),
+            entrypoint: Some("sh".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--interactive",
+                "--name",
+                "procli-debug",
+                "--entrypoint",
+                "sh",
+                "busybox"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_is_passed_to_the_entrypoint() {
+        let svc = Service {
+            name: "debug".to_string(),
+            image: Some("busybox".to_string()),
+            entrypoint: Some("sh".to_string()),
+            command: Some("-c 'ls /'".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            argv(&svc),
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--interactive",
+                "--name",
+                "procli-debug",
+                "--entrypoint",
+                "sh",
+                "busybox",
+                "-c",
+                "ls /"
+            ]
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn shell_command_is_passed_whole() {
+        let svc = Service {
+            command: Some("echo 'hello world' | wc".to_string()),
+            shell: true,
+            ..Default::default()
+        };
+        assert_eq!(argv(&svc), ["sh", "-c", "echo 'hello world' | wc"]);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn args_follow_the_command_unsplit() {
+        let args = vec!["--greeting".to_string(), "hello 'world'".to_string()];
+        let svc = Service {
+            args: args.clone(),
+            ..local("PORT=80 ./run --verbose")
+        };
+        assert_eq!(
+            argv(&svc),
+            ["./run", "--verbose", "--greeting", "hello 'world'"]
+        );
+        assert!(env(&svc).contains(&("PORT".to_string(), "80".to_string())));
+
+        let bare = Service {
+            args: args.clone(),
+            ..local("./run")
+        };
+        assert_eq!(argv(&bare), ["./run", "--greeting", "hello 'world'"]);
+
+        let image = Service {
+            image: Some("alpine".to_string()),
+            command: Some("echo".to_string()),
+            args,
+            ..Default::default()
+        };
+        let image = argv(&image);
+        assert_eq!(
+            image[image.len() - 4..],
+            ["alpine", "echo", "--greeting", "hello 'world'"]
+        );
+    }
+
+    #[test]
+    fn args_are_refused_for_shell_commands() {
+        let svc = Service {
+            command: Some("./run | tee out".to_string()),
+            args: vec!["x".to_string()],
+            shell: true,
+            ..Default::default()
+        };
+        let err = build_command(&svc).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`args` can't be used with `shell`; put them in `command`"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Docker containers started for image based processes.
+
+use std::ffi::OsStr;
+
+use color_eyre::eyre::{Result, eyre};
+use log::*;
+use tokio::process::Command;
+
+/// Prefix of the names given to procli's containers.
+pub const NAME_PREFIX: &str = "procli-";
+/// Label on reused containers recording the `docker run` options they were
+/// created with.
+pub const FINGERPRINT_LABEL: &str = "procli.fingerprint";
+
+/// A container kept between runs, and how to start it again.
+#[derive(Debug)]
+pub struct Reuse {
+    pub container: String,
+    pub fingerprint: String,
+    /// `docker start` for the existing container.
+    pub start: Command,
+}
+
+/// Which command the next spawn of a reused container runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Launch {
+    /// `docker run`, creating the container.
+    Run,
+    /// `docker start` for the kept container.
+    Start,
+}
+
+/// What docker has under a reused container's name.
+#[derive(Debug, PartialEq, Eq)]
+enum Existing {
+    Missing,
+    /// Created from the current config.
+    Matching,
+    /// Created by procli from a different config.
+    Stale,
+    /// Not created by procli.
+    Foreign,
+}
+
+/// How to launch a reused container: start it if it was created from the
+/// config with this `fingerprint`, otherwise `run`, first removing a kept
+/// container that no longer matches.
+///
+/// This waits on docker, so it is run in a task rather than on the way to
+/// spawning.
+pub async fn choose(container: &str, fingerprint: &str) -> Result<Launch> {
+    match existing(container, fingerprint).await? {
+        Existing::Missing => Ok(Launch::Run),
+        Existing::Matching => {
+            info!(target: "App", "Reusing container {}", container);
+            Ok(Launch::Start)
+        }
+        Existing::Stale => {
+            info!(
+                target: "App",
+                "Container {} was created from a different config, recreating it",
+                container
+            );
+            let status = Command::new("docker")
+                .args(["rm", "--force", container])
+                .output()
+                .await?
+                .status;
+            if !status.success() {
+                return Err(eyre!("docker rm failed for {}", container));
+            }
+            Ok(Launch::Run)
+        }
+        Existing::Foreign => Err(eyre!(
+            "Container {} exists but wasn't created by procli; remove it or set another container_name",
+            container
+        )),
+    }
+}
+
+async fn existing(container: &str, fingerprint: &str) -> Result<Existing> {
+    let format = format!("{{{{index .Config.Labels \"{FINGERPRINT_LABEL}\"}}}}");
+    let output = Command::new("docker")
+        .args(["container", "inspect", "--format", &format, container])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Ok(Existing::Missing);
+    }
+    Ok(compare(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        fingerprint,
+    ))
+}
+
+/// Compare a container's fingerprint label with the current one.
+fn compare(label: &str, fingerprint: &str) -> Existing {
+    match label {
+        "" | "<no value>" => Existing::Foreign,
+        label if label == fingerprint => Existing::Matching,
+        _ => Existing::Stale,
+    }
+}
+
+/// A short hash of `docker run` options (FNV-1a), to notice when a kept
+/// container no longer matches the config. Unlike the std hasher it has no
+/// random seed, so the same options give the same hash in every run.
+pub fn fingerprint<'a>(args: impl Iterator<Item = &'a OsStr>) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for arg in args {
+        for byte in arg.as_encoded_bytes().iter().chain([&0]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// The container name used for a process.
+pub fn container_name(name: &str) -> String {
+    format!("{NAME_PREFIX}{name}")
+}
+
+/// Force remove containers left behind by an earlier run that was killed
+/// before docker could clean up, for the given processes only.
+///
+/// Returns the names of the containers removed.
+pub async fn remove_orphans(names: &[String]) -> Result<Vec<String>> {
+    let output = Command::new("docker")
+        .args(["ps", "--all", "--format", "{{.Names}}"])
+        .args(["--filter", &format!("name=^{NAME_PREFIX}")])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "docker ps failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let orphans = orphans(&String::from_utf8_lossy(&output.stdout), names);
+    if orphans.is_empty() {
+        return Ok(orphans);
+    }
+    let status = Command::new("docker")
+        .args(["rm", "--force"])
+        .args(&orphans)
+        .output()
+        .await?
+        .status;
+    if !status.success() {
+        return Err(eyre!("docker rm failed for {}", orphans.join(", ")));
+    }
+    for orphan in orphans.iter() {
+        info!(target: "App", "Removed orphaned container {}", orphan);
+    }
+    Ok(orphans)
+}
+
+/// The containers in a `docker ps` name listing that belong to the given
+/// processes.
+fn orphans(listing: &str, names: &[String]) -> Vec<String> {
+    listing
+        .lines()
+        .map(str::trim)
+        .filter(|container| {
+            names
+                .iter()
+                .any(|name| container_name(name).as_str() == *container)
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kept_containers_are_matched_by_fingerprint() {
+        let current = fingerprint(["nginx", "-e", "A=1"].map(OsStr::new).into_iter());
+        let changed = fingerprint(["nginx", "-e", "A=2"].map(OsStr::new).into_iter());
+        assert_ne!(current, changed);
+        assert_eq!(compare(&current, &current), Existing::Matching);
+        assert_eq!(compare(&changed, &current), Existing::Stale);
+        assert_eq!(compare("<no value>", &current), Existing::Foreign);
+    }
+
+    #[test]
+    fn only_containers_for_the_given_processes_are_orphans() {
+        let listing = "procli-api\nprocli-web\nprocli-api-old\nsomeone-else\n";
+        let names = ["api".to_string(), "db".to_string()];
+        assert_eq!(orphans(listing, &names), ["procli-api"]);
+    }
+}
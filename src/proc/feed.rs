@@ -0,0 +1,552 @@
+//! A live feed of process events, as JSON lines, for tools connected to the
+//! management sockets. Clients of the control socket can also send commands,
+//! one JSON object per line, and get a `reply` event back.
+
+use crate::event::{AppEvent, Event};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{
+        broadcast::{self, WeakSender, error::RecvError},
+        mpsc::{self, UnboundedSender},
+    },
+    task::JoinHandle,
+};
+#[cfg(unix)]
+use {
+    color_eyre::eyre::{Result, eyre},
+    std::{os::unix::fs::PermissionsExt, path::Path, time::Duration},
+    tokio::net::{UnixListener, UnixStream},
+};
+
+/// Events buffered per subscriber before a slow one starts missing them.
+const BACKLOG: usize = 256;
+
+/// How long [`request`] waits for a process to settle after a command.
+#[cfg(unix)]
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A command from a client, e.g. `{"command":"stop","name":"api"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum FeedCommand {
+    /// Stop the process without restarting it.
+    Stop { name: String },
+    /// Start the process if it isn't running.
+    Start { name: String },
+}
+
+impl FeedCommand {
+    pub fn name(&self) -> &str {
+        match self {
+            FeedCommand::Stop { name } | FeedCommand::Start { name } => name,
+        }
+    }
+
+    /// Whether a process in this state has finished responding to the
+    /// command.
+    #[cfg(unix)]
+    fn settled(&self, state: &str) -> bool {
+        match self {
+            FeedCommand::Stop { .. } => !matches!(state, "starting" | "running" | "stopping"),
+            FeedCommand::Start { .. } => state != "starting" && state != "stopping",
+        }
+    }
+}
+
+/// One event in the feed. Serialized with an `event` tag naming the variant,
+/// e.g. `{"event":"died","name":"api","code":1}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FeedEvent {
+    /// The process moved from one state to another. `from` is missing the
+    /// first time a process is seen.
+    StateChanged {
+        name: String,
+        from: Option<&'static str>,
+        to: &'static str,
+    },
+    /// The process's child exited. `code` is missing if it was killed by a
+    /// signal.
+    Died { name: String, code: Option<i32> },
+    /// A new child was started after an earlier one.
+    Restarted { name: String, restarts: u32 },
+    /// This subscriber fell behind and missed some events.
+    Missed { count: u64 },
+    /// The answer to this client's command: the process's state just after
+    /// it was carried out, or why it couldn't be.
+    Reply {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        state: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl FeedEvent {
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Where the feed is served: a TCP `host:port` that only streams events, and
+/// a Unix socket that streams them and takes commands too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sockets {
+    pub events: Option<String>,
+    pub control: Option<PathBuf>,
+}
+
+impl Sockets {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_none() && self.control.is_none()
+    }
+}
+
+/// The sockets serving the feed. They stop listening, and disconnect every
+/// client, when dropped.
+#[derive(Debug)]
+pub struct Feed {
+    sender: broadcast::Sender<FeedEvent>,
+    listeners: Vec<JoinHandle<()>>,
+    /// The control socket's file, once bound, to remove when done.
+    control: Option<PathBuf>,
+}
+
+impl Feed {
+    /// Send an event to every connected client. Never waits on clients: one
+    /// that falls too far behind is told how many events it missed.
+    pub fn publish(&self, event: FeedEvent) {
+        // No subscribers is not an error.
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        for listener in self.listeners.iter() {
+            listener.abort();
+        }
+        if let Some(path) = &self.control {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Start listening on `sockets`, streaming published events to each client
+/// that connects. Commands from control socket clients are passed on as
+/// [`AppEvent::Control`].
+pub fn spawn(sockets: &Sockets, commands: UnboundedSender<Event>) -> Feed {
+    let (sender, _) = broadcast::channel(BACKLOG);
+    let mut feed = Feed {
+        listeners: Vec::new(),
+        control: None,
+        sender,
+    };
+    if let Some(addr) = sockets.events.clone() {
+        let subscribe = feed.sender.downgrade();
+        feed.listeners
+            .push(tokio::spawn(serve_events(addr, subscribe)));
+    }
+    if let Some(path) = &sockets.control {
+        #[cfg(unix)]
+        match bind_control(path) {
+            Ok(listener) => {
+                info!(target: "App", "Taking commands on {}", path.display());
+                feed.control = Some(path.clone());
+                let subscribe = feed.sender.downgrade();
+                feed.listeners
+                    .push(tokio::spawn(serve_control(listener, subscribe, commands)));
+            }
+            Err(err) => {
+                error!(target: "App", "Can't take commands on {}: {}", path.display(), err);
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = commands;
+            error!(
+                target: "App",
+                "Can't take commands on {}: control sockets are Unix only",
+                path.display()
+            );
+        }
+    }
+    feed
+}
+
+/// Stream events, and nothing else, to each client connecting to `addr`.
+async fn serve_events(addr: String, subscribe: WeakSender<FeedEvent>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(target: "App", "Can't serve process events on {}: {}", addr, err);
+            return;
+        }
+    };
+    info!(target: "App", "Serving process events on {}", addr);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(target: "App", "Event socket stopped accepting: {}", err);
+                break;
+            }
+        };
+        let Some(events) = subscribe.upgrade().map(|sender| sender.subscribe()) else {
+            break;
+        };
+        debug!("Event subscriber connected from {}", peer);
+        let (read, write) = stream.into_split();
+        tokio::spawn(serve_client(read, write, events, None, peer.to_string()));
+    }
+}
+
+/// Bind the control socket, where only this user may connect, replacing one
+/// left behind by a procli that is no longer running.
+#[cfg(unix)]
+fn bind_control(path: &Path) -> std::io::Result<UnixListener> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "another procli is taking commands there",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Stream events to each client of the control socket, and pass on their
+/// commands.
+#[cfg(unix)]
+async fn serve_control(
+    listener: UnixListener,
+    subscribe: WeakSender<FeedEvent>,
+    commands: UnboundedSender<Event>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!(target: "App", "Control socket stopped accepting: {}", err);
+                break;
+            }
+        };
+        let Some(events) = subscribe.upgrade().map(|sender| sender.subscribe()) else {
+            break;
+        };
+        debug!("Control client connected");
+        let (read, write) = stream.into_split();
+        let client = serve_client(
+            read,
+            write,
+            events,
+            Some(commands.clone()),
+            "on the control socket".to_string(),
+        );
+        tokio::spawn(client);
+    }
+}
+
+/// Write events to one client until it goes. With `commands`, its lines are
+/// read as commands and passed on, and the replies written back too.
+async fn serve_client(
+    read: impl AsyncRead + Unpin,
+    mut write: impl AsyncWrite + Unpin,
+    mut events: broadcast::Receiver<FeedEvent>,
+    commands: Option<UnboundedSender<Event>>,
+    peer: String,
+) {
+    let mut lines = BufReader::new(read).lines();
+    let mut reading = commands.is_some();
+    let (reply, mut replies) = mpsc::unbounded_channel();
+    loop {
+        let event = tokio::select! {
+            biased;
+            Some(event) = replies.recv() => event,
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(count)) => FeedEvent::Missed { count },
+                Err(RecvError::Closed) => break,
+            },
+            line = lines.next_line(), if reading => {
+                match (line, &commands) {
+                    (Ok(Some(line)), _) if line.trim().is_empty() => {}
+                    (Ok(Some(line)), Some(commands)) => match serde_json::from_str(&line) {
+                        Ok(command) => {
+                            let control = AppEvent::Control(command, reply.clone());
+                            if commands.send(Event::App(control)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            let _ = reply.send(FeedEvent::Reply {
+                                name: String::new(),
+                                state: None,
+                                error: Some(format!("Can't read command: {err}")),
+                            });
+                        }
+                    },
+                    // The client can still listen after it has finished
+                    // sending.
+                    _ => reading = false,
+                }
+                continue;
+            }
+        };
+        let line = event.to_line() + "\n";
+        if write.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+    debug!("Event subscriber {} gone", peer);
+}
+
+/// Send `command` to the procli taking commands on the control socket at
+/// `path` and wait for the process to settle. Returns its state then, or when
+/// giving up waiting.
+#[cfg(unix)]
+pub async fn request(path: &Path, command: &FeedCommand) -> Result<String> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| eyre!("No procli is running on {}: {}", path.display(), err))?;
+    let (read, mut write) = stream.into_split();
+    let line = serde_json::to_string(command)? + "\n";
+    write.write_all(line.as_bytes()).await?;
+    let mut lines = BufReader::new(read).lines();
+    let mut state = None;
+    let wait = async {
+        while let Some(line) = lines.next_line().await? {
+            let event: serde_json::Value = serde_json::from_str(&line)?;
+            let text = |key: &str| event[key].as_str().map(str::to_string);
+            match text("event").as_deref() {
+                Some("reply") => {
+                    if let Some(error) = text("error") {
+                        return Err(eyre!(error));
+                    }
+                    state = text("state");
+                }
+                Some("state_changed") if text("name").as_deref() == Some(command.name()) => {
+                    if state.is_some() {
+                        state = text("to");
+                    }
+                }
+                _ => continue,
+            }
+            if state.as_deref().is_some_and(|s| command.settled(s)) {
+                break;
+            }
+        }
+        Ok(())
+    };
+    match tokio::time::timeout(SETTLE_TIMEOUT, wait).await {
+        Ok(result) => result?,
+        Err(_) => warn!("Timed out waiting for {} to settle", command.name()),
+    }
+    state.ok_or_else(|| eyre!("procli on {} closed the connection", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    /// A feed on a free local port, and its address.
+    fn local_feed(commands: UnboundedSender<Event>) -> (Feed, String) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let sockets = Sockets {
+            events: Some(addr.clone()),
+            control: None,
+        };
+        (spawn(&sockets, commands), addr)
+    }
+
+    /// A feed taking commands on a control socket in the temp directory, and
+    /// the socket's path.
+    #[cfg(unix)]
+    fn control_feed(commands: UnboundedSender<Event>) -> (Feed, PathBuf) {
+        let path = std::env::temp_dir().join(format!("procli-{}.sock", uuid::Uuid::new_v4()));
+        let sockets = Sockets {
+            events: None,
+            control: Some(path.clone()),
+        };
+        (spawn(&sockets, commands), path)
+    }
+
+    async fn connect(addr: &str) -> TcpStream {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        }
+    }
+
+    #[test]
+    fn events_are_tagged_json() {
+        let events = [
+            FeedEvent::StateChanged {
+                name: "api".to_string(),
+                from: Some("starting"),
+                to: "running",
+            },
+            FeedEvent::Died {
+                name: "api".to_string(),
+                code: None,
+            },
+            FeedEvent::Restarted {
+                name: "api".to_string(),
+                restarts: 2,
+            },
+        ];
+        assert_eq!(
+            events.map(|e| e.to_line()),
+            [
+                r#"{"event":"state_changed","name":"api","from":"starting","to":"running"}"#,
+                r#"{"event":"died","name":"api","code":null}"#,
+                r#"{"event":"restarted","name":"api","restarts":2}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_events_and_laggards_are_told() {
+        let (commands, _) = mpsc::unbounded_channel();
+        let (feed, addr) = local_feed(commands);
+        let stream = connect(&addr).await;
+        let mut lines = BufReader::new(stream).lines();
+        while feed.sender.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        for restarts in 0..BACKLOG as u32 + 10 {
+            let name = "api".to_string();
+            feed.publish(FeedEvent::Restarted { name, restarts });
+        }
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"event":"missed","count":10}"#
+        );
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"event":"restarted","name":"api","restarts":10}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn event_socket_takes_no_commands() {
+        let (commands, mut received) = mpsc::unbounded_channel();
+        let (feed, addr) = local_feed(commands);
+        let (read, mut write) = connect(&addr).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write
+            .write_all(b"{\"command\":\"stop\",\"name\":\"api\"}\n")
+            .await
+            .unwrap();
+        while feed.sender.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let name = "api".to_string();
+        feed.publish(FeedEvent::Restarted { name, restarts: 1 });
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"event":"restarted","name":"api","restarts":1}"#
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn control_socket_is_private_and_removed_when_done() {
+        let (commands, _) = mpsc::unbounded_channel();
+        let (feed, path) = control_feed(commands);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(feed);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_are_passed_on_and_replied_to() {
+        let (commands, mut received) = mpsc::unbounded_channel();
+        let (_feed, path) = control_feed(commands);
+        let (read, mut write) = UnixStream::connect(&path).await.unwrap().into_split();
+        let mut lines = BufReader::new(read).lines();
+        write
+            .write_all(b"nonsense\n{\"command\":\"stop\",\"name\":\"api\"}\n")
+            .await
+            .unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.starts_with(r#"{"event":"reply","name":"","error":"Can't read command"#));
+        let Some(Event::App(AppEvent::Control(command, reply))) = received.recv().await else {
+            panic!("Expected a command");
+        };
+        assert_eq!(command.name(), "api");
+        let state = Some("stopping");
+        let name = "api".to_string();
+        reply
+            .send(FeedEvent::Reply {
+                name,
+                state,
+                error: None,
+            })
+            .unwrap();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"event":"reply","name":"api","state":"stopping"}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn requests_wait_for_the_process_to_settle() {
+        let (commands, mut received) = mpsc::unbounded_channel();
+        let (feed, path) = control_feed(commands);
+        let answer = tokio::spawn(async move {
+            let Some(Event::App(AppEvent::Control(_, reply))) = received.recv().await else {
+                panic!("Expected a command");
+            };
+            let name = "api".to_string();
+            let state = Some("stopping");
+            reply
+                .send(FeedEvent::Reply {
+                    name,
+                    state,
+                    error: None,
+                })
+                .unwrap();
+            for (from, to) in [("running", "stopping"), ("stopping", "stopped")] {
+                let name = "api".to_string();
+                let from = Some(from);
+                feed.publish(FeedEvent::StateChanged { name, from, to });
+            }
+            feed
+        });
+        let stop = FeedCommand::Stop {
+            name: "api".to_string(),
+        };
+        assert_eq!(request(&path, &stop).await.unwrap(), "stopped");
+        answer.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn requests_fail_without_a_running_procli() {
+        let path = std::env::temp_dir().join("procli-nobody-here.sock");
+        let start = FeedCommand::Start {
+            name: "api".to_string(),
+        };
+        let err = request(&path, &start).await.unwrap_err();
+        assert!(err.to_string().starts_with("No procli is running"));
+    }
+}
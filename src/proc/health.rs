@@ -0,0 +1,174 @@
+//! Health checks: a command run now and then against a live process, much
+//! like a Docker `HEALTHCHECK`.
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    process::Stdio,
+    time::Duration,
+};
+
+use log::*;
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    config::HealthCheck,
+    event::{AppEvent, Event},
+    proc::command::Hook,
+};
+
+/// What the health check last made of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// No check has passed yet, and not enough have failed.
+    Starting,
+    Healthy,
+    /// `retries` checks in a row have failed.
+    Unhealthy,
+}
+
+impl HealthStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            HealthStatus::Starting => "starting",
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// Turns check results into a status: one pass is healthy, `retries`
+/// failures in a row unhealthy.
+#[derive(Debug)]
+pub struct HealthTracker {
+    retries: u32,
+    failures: u32,
+    status: HealthStatus,
+}
+
+impl HealthTracker {
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries: retries.max(1),
+            failures: 0,
+            status: HealthStatus::Starting,
+        }
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
+
+    /// Count a check's result, ignoring failures while `starting`. Returns
+    /// the new status if it changed.
+    pub fn record(&mut self, passed: bool, starting: bool) -> Option<HealthStatus> {
+        let before = self.status;
+        if passed {
+            self.failures = 0;
+            self.status = HealthStatus::Healthy;
+        } else if !starting {
+            self.failures += 1;
+            if self.failures >= self.retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+        (self.status != before).then_some(self.status)
+    }
+}
+
+/// `interval` give or take a tenth, so checks of processes started together
+/// spread out.
+fn jittered(interval: Duration) -> Duration {
+    let random = RandomState::new().hash_one(Instant::now());
+    let spread = interval / 5;
+    let offset = spread.mul_f64((random % 1000) as f64 / 1000.0);
+    interval - spread / 2 + offset
+}
+
+/// Check the child started as `uuid` until the task is aborted, reporting
+/// each change of status.
+pub async fn run(
+    name: String,
+    uuid: Uuid,
+    check: HealthCheck,
+    hook: Hook,
+    sender: UnboundedSender<Event>,
+) {
+    let started = Instant::now();
+    let interval = check.interval();
+    // A check can't take longer than the wait between checks.
+    let timeout = check.timeout().min(interval);
+    let mut tracker = HealthTracker::new(check.retries);
+    loop {
+        tokio::time::sleep(jittered(interval)).await;
+        let passed = probe(&name, &hook, timeout).await;
+        let starting = started.elapsed() < check.start_period();
+        let Some(status) = tracker.record(passed, starting) else {
+            continue;
+        };
+        match status {
+            HealthStatus::Unhealthy => warn!(target: &name, "Unhealthy"),
+            _ => info!(target: &name, "Now {}", status.label()),
+        }
+        let event = Event::App(AppEvent::HealthChanged(uuid, status));
+        if sender.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+/// Run the check once. Whether it exited successfully within `timeout`.
+async fn probe(name: &str, hook: &Hook, timeout: Duration) -> bool {
+    let mut cmd = hook.command();
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    match tokio::time::timeout(timeout, cmd.status()).await {
+        Ok(Ok(status)) => status.success(),
+        Ok(Err(err)) => {
+            debug!(target: name, "Can't run health check: {}", err);
+            false
+        }
+        Err(_) => {
+            debug!(target: name, "Health check timed out after {:?}", timeout);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_failures_in_a_row_turn_unhealthy() {
+        let mut tracker = HealthTracker::new(3);
+        assert_eq!(tracker.record(false, false), None);
+        assert_eq!(tracker.record(false, false), None);
+        assert_eq!(tracker.record(true, false), Some(HealthStatus::Healthy));
+        assert_eq!(tracker.record(false, false), None);
+        assert_eq!(tracker.record(false, false), None);
+        assert_eq!(tracker.record(false, false), Some(HealthStatus::Unhealthy));
+        assert_eq!(tracker.record(false, false), None);
+        assert_eq!(tracker.record(true, false), Some(HealthStatus::Healthy));
+    }
+
+    #[test]
+    fn failures_during_the_start_period_dont_count() {
+        let mut tracker = HealthTracker::new(1);
+        assert_eq!(tracker.record(false, true), None);
+        assert_eq!(tracker.status(), HealthStatus::Starting);
+        assert_eq!(tracker.record(true, true), Some(HealthStatus::Healthy));
+        assert_eq!(tracker.record(false, false), Some(HealthStatus::Unhealthy));
+    }
+
+    #[test]
+    fn jitter_stays_within_a_tenth() {
+        let interval = Duration::from_secs(10);
+        for _ in 0..100 {
+            let wait = jittered(interval);
+            assert!(wait >= Duration::from_secs(9) && wait <= Duration::from_secs(11));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     process::ExitStatus,
     time::{Duration, Instant},
 };
@@ -6,45 +7,184 @@ use std::{
 use color_eyre::eyre::{OptionExt, eyre};
 use log::*;
 use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
-use tokio::{sync::mpsc::UnboundedSender, time::sleep};
+use tokio::{
+    select,
+    sync::{mpsc::UnboundedSender, watch},
+    time::sleep,
+};
 use uuid::Uuid;
 
 use crate::{
+    config::{ConfigChangeRestart, CpuMode, OnRemove},
     event::{AppEvent, Event},
     proc::{
-        process::{Named, Process, ProcessConfig, ProcessRestart, ProcessState},
-        stats::ProcessStats,
+        container::Launch,
+        feed::{self, Feed, FeedEvent},
+        health::HealthStatus,
+        process::{Expiry, Named, Process, ProcessConfig, ProcessRestart, ProcessState},
+        stats::{self, ProcessStats},
+        statsd,
     },
 };
 
+/// Overall state of a set of processes, for waiting on startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Some processes are still starting or waiting to restart.
+    Pending,
+    /// Every process is running or has completed.
+    Healthy,
+    /// A process failed to start or has stopped for good.
+    Unhealthy,
+}
+
+impl Health {
+    pub fn of<'a>(states: impl Iterator<Item = &'a ProcessState>) -> Self {
+        let mut health = Health::Healthy;
+        for state in states {
+            match state {
+                ProcessState::Running | ProcessState::Completed(_) => {}
+                ProcessState::Failed(_) | ProcessState::Stopped(ProcessRestart::NoRestart, _) => {
+                    return Health::Unhealthy;
+                }
+                _ => health = Health::Pending,
+            }
+        }
+        health
+    }
+}
+
 #[derive(Debug)]
 pub struct ProcessManager {
     pub processes: Vec<Process>,
     sender: UnboundedSender<Event>,
     sys: sysinfo::System,
+    /// Our own pid, so procli's overhead can be reported alongside its children.
+    self_pid: Option<Pid>,
+    pub self_stats: Option<ProcessStats>,
+    /// Combined stats of all live processes, one per refresh.
+    pub totals: Vec<ProcessStats>,
+    pub totals_max: ProcessStats,
+    pub cpu_mode: CpuMode,
+    /// Cores available, to normalize CPU use by.
+    pub cores: usize,
+    /// The least time between starts of a process restarted by its policy.
+    pub min_restart_interval: Duration,
+    /// Whether process output keeps its colours when logged.
+    pub color: bool,
+    /// Whether processes taken out of the config are kept once stopped.
+    pub on_remove: OnRemove,
+    /// How often the stats ticker fires; dropped with the manager to stop it.
+    stats_period: watch::Sender<Duration>,
+    /// Where metrics are pushed after each refresh, if anywhere.
+    statsd: Option<(String, UnboundedSender<Vec<String>>)>,
+    /// Restart counts already pushed, by process name.
+    statsd_restarts: HashMap<String, u32>,
+    /// Restarts waiting on dependents to stop or the dependency to come back.
+    ordered: Vec<OrderedRestart>,
+    /// Where process events are streamed, if anywhere.
+    feed: Option<(feed::Sockets, Feed)>,
+    /// States already sent to the feed, by process name.
+    published: HashMap<String, &'static str>,
+}
+
+/// A restart of a process that others depend on. Its dependents are stopped
+/// first, and started again, in dependency order, once it is running.
+#[derive(Debug)]
+struct OrderedRestart {
+    name: String,
+    /// Every dependent stopped for the restart.
+    dependents: Vec<String>,
+    /// Dependents not started again yet.
+    held: Vec<String>,
+    /// Whether `name` itself has been restarted yet.
+    restarted: bool,
 }
 
 impl ProcessManager {
     pub fn new(sender: UnboundedSender<Event>) -> Self {
-        let ticker = sender.clone();
-        tokio::spawn(async move {
-            loop {
-                sleep(Duration::from_secs(2)).await;
-                ticker
-                    .send(Event::App(AppEvent::StatsRefresh))
-                    .expect("sending process died message");
-            }
-        });
+        let (stats_period, period) = watch::channel(STATS_PERIOD);
+        tokio::spawn(stats_ticker(sender.clone(), period));
         Self {
             processes: vec![],
             sender,
             sys: System::new(),
+            self_pid: sysinfo::get_current_pid().ok(),
+            self_stats: None,
+            totals: Vec::new(),
+            totals_max: ProcessStats::default(),
+            cpu_mode: CpuMode::default(),
+            cores: stats::cores(),
+            min_restart_interval: MIN_RESTART_INTERVAL,
+            color: true,
+            on_remove: OnRemove::default(),
+            stats_period,
+            statsd: None,
+            statsd_restarts: HashMap::new(),
+            ordered: Vec::new(),
+            feed: None,
+            published: HashMap::new(),
+        }
+    }
+
+    /// Push metrics to a StatsD agent at `addr` after each refresh, or stop
+    /// pushing if `None`.
+    pub fn set_statsd(&mut self, addr: Option<&str>) {
+        if self.statsd.as_ref().map(|(current, _)| current.as_str()) == addr {
+            return;
+        }
+        self.statsd = addr.map(|addr| (addr.to_string(), statsd::spawn(addr.to_string())));
+    }
+
+    /// Stream process events to clients of the given sockets, or stop if
+    /// there are none.
+    pub fn set_feed(&mut self, sockets: feed::Sockets) {
+        let current = self.feed.as_ref().map(|(current, _)| current.clone());
+        if current.unwrap_or_default() == sockets {
+            return;
+        }
+        // Let go of the old sockets first, so the same path can be bound again.
+        self.feed = None;
+        if !sockets.is_empty() {
+            let feed = feed::spawn(&sockets, self.sender.clone());
+            self.feed = Some((sockets, feed));
+        }
+        self.published.clear();
+    }
+
+    fn publish(&self, event: FeedEvent) {
+        if let Some((_, feed)) = &self.feed {
+            feed.publish(event);
+        }
+    }
+
+    /// Send a state change for each process whose state has moved on since
+    /// the last call.
+    pub fn publish_state_changes(&mut self) {
+        let Some((_, feed)) = &self.feed else {
+            return;
+        };
+        for proc in self.processes.iter() {
+            let to = proc.state.label();
+            let from = self.published.insert(proc.name.clone(), to);
+            if from != Some(to) {
+                feed.publish(FeedEvent::StateChanged {
+                    name: proc.name.clone(),
+                    from,
+                    to,
+                });
+            }
         }
     }
 
     /// Refresh the sysinfo stats.
     fn refresh_stats(&mut self) {
-        let pids: Vec<Pid> = self.processes.iter().filter_map(|p| p.pid).collect();
+        let pids: Vec<Pid> = self
+            .processes
+            .iter()
+            .filter_map(|p| p.pid)
+            .chain(self.self_pid)
+            .collect();
         self.sys.refresh_processes_specifics(
             ProcessesToUpdate::Some(&pids),
             true,
@@ -56,10 +196,99 @@ impl ProcessManager {
     fn assign_stats(&mut self) {
         let proc_infos = self.sys.processes();
         let timestamp = Instant::now();
+        let mut current = Vec::new();
         for proc in self.processes.iter_mut().filter(|p| p.pid.is_some()) {
             if let Some(info) = proc_infos.get(&proc.pid.unwrap()) {
-                proc.push_stats(ProcessStats::new(timestamp, info));
+                let stats = ProcessStats::new(timestamp, info, self.cpu_mode, self.cores);
+                proc.push_stats(stats);
+                if !proc.removed {
+                    current.push(stats);
+                }
+            }
+        }
+        if !self.processes.is_empty() {
+            let total = ProcessStats::total(timestamp, current.iter());
+            self.totals_max.raise_to(&total);
+            self.totals.push(total);
+            stats::trim(&mut self.totals, timestamp);
+        }
+        if let Some(info) = self.self_pid.and_then(|pid| proc_infos.get(&pid)) {
+            self.self_stats = Some(ProcessStats::new(
+                timestamp,
+                info,
+                self.cpu_mode,
+                self.cores,
+            ));
+        }
+        self.push_statsd(timestamp);
+    }
+
+    /// Send the stats just assigned, and any new restarts, to StatsD.
+    fn push_statsd(&mut self, timestamp: Instant) {
+        let Some((_, sender)) = &self.statsd else {
+            return;
+        };
+        let mut samples = Vec::new();
+        for proc in self.processes.iter() {
+            let reported = self.statsd_restarts.get(&proc.name).copied();
+            let restarts = proc.restarts.saturating_sub(reported.unwrap_or(0));
+            // Dead or restarting processes have no fresh stats, but may
+            // still have restarts to count.
+            let stats = proc.stats.last().filter(|s| s.timestamp == timestamp);
+            if stats.is_none() && restarts == 0 {
+                continue;
+            }
+            samples.push(statsd::Sample {
+                service: &proc.name,
+                cpu_percent: stats.map(|s| s.cpu_percent),
+                memory_mb: stats.map(|s| s.memory_mb),
+                restarts,
+            });
+        }
+        if sender.send(statsd::format(&samples)).is_ok() {
+            for proc in self.processes.iter() {
+                self.statsd_restarts
+                    .insert(proc.name.clone(), proc.restarts);
+            }
+        }
+    }
+
+    fn check_lifetimes(&mut self) {
+        let now = Instant::now();
+        for proc in self.processes.iter_mut().filter(|p| p.has_outlived(now)) {
+            info!(target: &proc.name, "Reached its maximum lifetime, stopping");
+            proc.expire(Expiry::Lifetime);
+        }
+    }
+
+    fn check_start_timeouts(&mut self) {
+        let now = Instant::now();
+        for proc in self.processes.iter_mut().filter(|p| p.has_stalled(now)) {
+            warn!(
+                target: &proc.name,
+                "Not started after {:?}, stopping",
+                proc.start_timeout.unwrap_or_default()
+            );
+            proc.expire(Expiry::StartTimeout);
+        }
+    }
+
+    /// Flag processes that have gone quiet, and unflag those heard from
+    /// again. Either way they are left running.
+    fn check_idle(&mut self) {
+        let now = Instant::now();
+        for proc in self.processes.iter_mut() {
+            let quiet = proc.has_gone_quiet(now);
+            if quiet && !proc.idle {
+                warn!(
+                    target: &proc.name,
+                    "No output for {:?}, may have stalled",
+                    proc.idle_timeout.unwrap_or_default()
+                );
+            } else if !quiet && proc.idle && matches!(proc.state, ProcessState::Running) {
+                info!(target: &proc.name, "Output again");
             }
+            proc.idle = quiet;
         }
     }
 
@@ -87,70 +316,1184 @@ impl ProcessManager {
         self.processes.iter_mut().find(|p| p.name == name)
     }
 
+    fn get(&self, name: &str) -> Option<&Process> {
+        self.processes.iter().find(|p| p.name == name)
+    }
+
     /// Spawn an actual process for the given state.
     ///
     /// Each process gets a new UUID (PID is less reliable) and output pumping
     /// tasks as well as death handler etc.
     ///
+    /// If the command can't be executed the process is marked as failed so the
+    /// UI can show why, rather than leaving it in `Starting`.
+    ///
     fn spawn(&mut self, name: &str) -> color_eyre::Result<Uuid> {
         let sender = self.sender.clone();
+        let color = self.color;
         let proc = self.find(name).ok_or(eyre!("No such process"))?;
-        let uuid = proc.spawn(sender)?;
+        if proc.removed {
+            return Err(eyre!("`{}` has been removed from the config", name));
+        }
+        proc.strip_ansi = !color;
+        let restarted = proc.last_start.is_some();
+        let uuid = match proc.spawn(sender) {
+            Ok(uuid) => uuid,
+            Err(err) => {
+                proc.state = ProcessState::Failed(err.to_string());
+                proc.last_stop = Some(Instant::now());
+                return Err(err);
+            }
+        };
+        if restarted {
+            let restarts = proc.restarts;
+            self.publish(FeedEvent::Restarted {
+                name: name.to_string(),
+                restarts,
+            });
+        }
         self.refresh_stats();
         Ok(uuid)
     }
 
+    /// Launch a reused container once docker has said how, unless the
+    /// process has been spawned again since.
+    pub fn container_chosen(&mut self, id: Uuid, launch: Result<Launch, String>) {
+        let sender = self.sender.clone();
+        let Some(proc) = self.processes.iter_mut().find(|p| p.uuid == id) else {
+            return;
+        };
+        let launched = launch
+            .map_err(|err| eyre!(err))
+            .and_then(|launch| proc.launch(launch, sender));
+        if let Err(err) = launched {
+            error!(target: &proc.name, "Failed to start: {}", err);
+            proc.state = ProcessState::Failed(err.to_string());
+            proc.last_stop = Some(Instant::now());
+            return;
+        }
+        self.refresh_stats();
+    }
+
     /// Try to call this less frequently than once a second.
+    ///
+    /// Stats are only refreshed while some process has a child to measure;
+    /// the refresh when one is spawned means they resume on the next tick.
     pub fn tick(&mut self) {
         debug!("ProcessManager tick");
-        self.refresh_stats();
-        self.assign_stats();
+        if self.processes.iter().any(|p| p.pid.is_some()) {
+            self.refresh_stats();
+            self.assign_stats();
+        }
+        for proc in self.processes.iter_mut() {
+            proc.run_post_start();
+        }
+        self.check_start_timeouts();
+        self.check_idle();
+        self.check_lifetimes();
         self.check_restarts();
+        self.advance_ordered_restarts();
     }
 
-    /// Define a new process for the given service.
+    /// Define a new process for the given service, or update an existing one
+    /// of the same name.
     ///
-    /// If a process with the same name is already running, it is only restarted
-    /// if the config has changed. If it is in a restart cooloff period, it is
-    /// started immediately.
+    /// An existing process takes on the new definition and is restarted as its
+    /// `restart_on_config_change` says: by default only if its command,
+    /// environment or directory changed.
     ///
     pub fn upsert<T>(&mut self, svc: &T) -> color_eyre::Result<Uuid>
     where
         T: Named + ProcessConfig,
     {
         let name = svc.name();
-        // TODO: check if already running
-        // if let Some(existing) = self.find(&name) {
-        //     match &existing.state {
-        //         ProcessState::Starting => todo!(),
-        //         ProcessState::Running => todo!(),
-        //         ProcessState::Killing(process_restart) => todo!(),
-        //         ProcessState::Stopped(process_restart, exit_status) => todo!(),
-        //     }
-        // }
-        self.processes.push(Process::new(svc)?);
-        self.spawn(&name)
+        let process = Process::new(svc)?;
+        let Some(existing) = self.find(&name) else {
+            self.processes.push(process);
+            return self.spawn(&name);
+        };
+        if existing.removed {
+            info!(target: &name, "Back in the config");
+            existing.removed = false;
+            existing.reconfigure(process);
+            if !existing.is_alive() {
+                return self.spawn(&name);
+            }
+            // Still stopping, so start it again once it has.
+            existing.kill(ProcessRestart::Respawn);
+            return Ok(existing.uuid);
+        }
+        let restart = match svc.restart_on_config_change() {
+            ConfigChangeRestart::IfChanged => !existing.same_command(&process),
+            ConfigChangeRestart::Always => true,
+            ConfigChangeRestart::Never => false,
+        };
+        existing.reconfigure(process);
+        let uuid = existing.uuid;
+        if restart {
+            info!(target: &name, "Restarting for the new config");
+            self.restart(&name)?;
+        }
+        Ok(uuid)
     }
 
     pub fn process_died(&mut self, id: Uuid, status: ExitStatus) {
+        if let Some((_, feed)) = &self.feed
+            && let Some(proc) = self.processes.iter().find(|p| p.uuid == id)
+        {
+            feed.publish(FeedEvent::Died {
+                name: proc.name.clone(),
+                code: status.code(),
+            });
+        }
         if let Some(proc) = self.processes.iter_mut().find(|p| p.uuid == id) {
             let time_of_death = Instant::now();
-            if proc.restart_policy.enabled && proc.restarts < proc.restart_policy.max_restarts {
-                let restart_at = time_of_death + Duration::from_secs(proc.restart_policy.cooloff); //TODO: add jitter
-                proc.state = ProcessState::Stopped(ProcessRestart::RestartAt(restart_at), status);
+            let state = std::mem::replace(&mut proc.state, ProcessState::Starting);
+            // Stats move a process on from starting, so it died before any.
+            proc.died_before_stats = matches!(state, ProcessState::Starting);
+            // The pid may be handed to some other process now.
+            proc.pid = None;
+            proc.stopped_on_request =
+                matches!(state, ProcessState::Killing(_)) && proc.expired.is_none();
+            let by_policy = if proc.restart_policy.allows(proc.restarts) {
+                let after_cooloff =
+                    time_of_death + Duration::from_secs(proc.restart_policy.cooloff); //TODO: add jitter
+                let floor = proc.last_start.map(|t| t + self.min_restart_interval);
+                let restart_at = match floor {
+                    Some(floor) if floor > after_cooloff => {
+                        proc.quick_restarts += 1;
+                        if proc.quick_restarts == QUICK_RESTARTS_WARNING {
+                            warn!(
+                                target: &proc.name,
+                                "Dying as soon as it starts; restarts held to one every {:?}",
+                                self.min_restart_interval
+                            );
+                        }
+                        floor
+                    }
+                    _ => {
+                        proc.quick_restarts = 0;
+                        after_cooloff
+                    }
+                };
+                ProcessRestart::RestartAt(restart_at)
             } else {
-                proc.state = ProcessState::Stopped(ProcessRestart::NoRestart, status);
-            }
+                ProcessRestart::NoRestart
+            };
+            proc.state = match state {
+                ProcessState::Killing(_) if proc.expired.is_some() => {
+                    ProcessState::Stopped(by_policy, status)
+                }
+                ProcessState::Killing(then) => ProcessState::Stopped(then, status),
+                _ if proc.is_clean_exit(&status) => {
+                    info!(target: &proc.name, "Process completed");
+                    ProcessState::Completed(status)
+                }
+                _ => ProcessState::Stopped(by_policy, status),
+            };
             proc.last_stop = Some(time_of_death);
+            proc.stop_health_check();
+            if let ProcessState::Stopped(ProcessRestart::Respawn, _) = proc.state {
+                let name = proc.name.clone();
+                if let Err(err) = self.spawn(&name) {
+                    error!("Failed to restart process {}: {}", name, err);
+                }
+            }
+            self.advance_ordered_restarts();
+            self.drop_removed();
         } else {
             error!("Received process died for unknown process {}", id);
         }
     }
 
+    /// Note what a process's health check found, stopping it if it is
+    /// unhealthy and its check says to restart it. The restart policy then
+    /// decides when, and whether, it starts again.
+    pub fn health_changed(&mut self, id: Uuid, status: HealthStatus) {
+        let Some(proc) = self.processes.iter_mut().find(|p| p.uuid == id) else {
+            return;
+        };
+        if !proc.is_alive() {
+            return;
+        }
+        proc.health = Some(status);
+        proc.run_post_start();
+        if status == HealthStatus::Unhealthy && proc.restarts_when_unhealthy() {
+            warn!(target: &proc.name, "Unhealthy, stopping to restart");
+            proc.expire(Expiry::Unhealthy);
+        }
+    }
+
+    /// Stop a process taken out of the config for good. It is then kept or
+    /// dropped as `on_remove` says.
     pub fn remove(&mut self, name: &str) -> color_eyre::Result<()> {
         let proc = self.find(name).ok_or_eyre("No such process")?;
-        info!(target: name, "Killing process");
-        proc.kill();
+        proc.removed = true;
+        if proc.is_alive() {
+            info!(target: name, "Killing process");
+            proc.kill(ProcessRestart::NoRestart);
+        } else if let ProcessState::Stopped(restart, _) = &mut proc.state {
+            *restart = ProcessRestart::NoRestart;
+        }
+        self.drop_removed();
+        Ok(())
+    }
+
+    /// Forget a removed process that has stopped, taking its card away.
+    pub fn dismiss(&mut self, name: &str) -> color_eyre::Result<()> {
+        let proc = self.get(name).ok_or_eyre("No such process")?;
+        if !proc.removed || proc.is_alive() {
+            return Err(eyre!("Only stopped, removed processes can be dismissed"));
+        }
+        self.processes.retain(|p| p.name != name);
         Ok(())
     }
+
+    /// Forget removed processes that have stopped, if they aren't kept.
+    fn drop_removed(&mut self) {
+        if self.on_remove == OnRemove::Drop {
+            self.processes.retain(|p| !p.removed || p.is_alive());
+        }
+    }
+
+    /// Restart a process, killing it first if it is still alive.
+    ///
+    /// Processes that depend on it, directly or not, are stopped first and
+    /// started again once it is running, so they never see it missing.
+    pub fn restart(&mut self, name: &str) -> color_eyre::Result<()> {
+        self.find(name).ok_or_eyre("No such process")?;
+        if let Some(op) = self
+            .ordered
+            .iter()
+            .find(|op| op.name == name || op.held.iter().any(|n| n == name))
+        {
+            info!(target: name, "Already restarting with {}", op.name);
+            return Ok(());
+        }
+        let dependents: Vec<String> = self
+            .dependents_of(name)
+            .into_iter()
+            .filter(|n| self.get(n).is_some_and(Process::is_up))
+            .collect();
+        if dependents.is_empty() {
+            return self.restart_alone(name);
+        }
+        info!(
+            target: name,
+            "Stopping {} before restarting",
+            dependents.join(", ")
+        );
+        for dependent in dependents.iter() {
+            if let Some(proc) = self.find(dependent) {
+                proc.hold_for(name);
+            }
+        }
+        self.ordered.push(OrderedRestart {
+            name: name.to_string(),
+            held: dependents.clone(),
+            dependents,
+            restarted: false,
+        });
+        self.advance_ordered_restarts();
+        Ok(())
+    }
+
+    /// Everything that depends on `name`, directly or through others, nearest
+    /// first.
+    fn dependents_of(&self, name: &str) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        let mut next = vec![name.to_string()];
+        while let Some(current) = next.pop() {
+            for proc in self.processes.iter() {
+                if proc.dependencies.contains(&current)
+                    && !found.contains(&proc.name)
+                    && proc.name != name
+                {
+                    found.push(proc.name.clone());
+                    next.insert(0, proc.name.clone());
+                }
+            }
+        }
+        found
+    }
+
+    /// Move ordered restarts on: restart the dependency once its dependents
+    /// have stopped, then start each dependent once everything it depends on
+    /// in the restart is running.
+    fn advance_ordered_restarts(&mut self) {
+        let mut ordered = std::mem::take(&mut self.ordered);
+        ordered.retain_mut(|op| self.advance(op));
+        self.ordered.append(&mut ordered);
+    }
+
+    /// Advance one ordered restart, returning whether it is still going.
+    fn advance(&mut self, op: &mut OrderedRestart) -> bool {
+        if !op.restarted {
+            let stopped = op
+                .dependents
+                .iter()
+                .all(|n| self.get(n).is_none_or(|p| !p.is_alive()));
+            if !stopped {
+                return true;
+            }
+            op.restarted = true;
+            info!(target: &op.name, "Dependents stopped, restarting");
+            if let Err(err) = self.restart_alone(&op.name) {
+                error!("Failed to restart process {}: {}", op.name, err);
+            }
+        }
+        match self.get(&op.name).map(|p| &p.state) {
+            Some(ProcessState::Running) => {}
+            Some(ProcessState::Failed(_) | ProcessState::Stopped(ProcessRestart::NoRestart, _))
+            | None => {
+                warn!(
+                    target: &op.name,
+                    "Didn't come back, leaving {} stopped",
+                    op.held.join(", ")
+                );
+                for name in op.held.iter() {
+                    if let Some(proc) = self.find(name) {
+                        proc.waiting_for = None;
+                    }
+                }
+                return false;
+            }
+            Some(_) => return true,
+        }
+        let running = |name: &String| {
+            *name == op.name
+                || !op.dependents.contains(name)
+                || (!op.held.contains(name)
+                    && self
+                        .get(name)
+                        .is_some_and(|p| matches!(p.state, ProcessState::Running)))
+        };
+        let ready: Vec<String> = op
+            .held
+            .iter()
+            .filter(|n| {
+                self.get(n)
+                    .is_some_and(|p| p.dependencies.iter().all(running))
+            })
+            .cloned()
+            .collect();
+        for name in ready {
+            op.held.retain(|n| *n != name);
+            info!(target: &name, "{} is back, starting again", op.name);
+            if let Err(err) = self.spawn(&name) {
+                error!("Failed to restart process {}: {}", name, err);
+            }
+        }
+        if op.held.is_empty() {
+            info!(target: &op.name, "Restarted along with its dependents");
+        }
+        !op.held.is_empty()
+    }
+
+    /// Restart just this process.
+    fn restart_alone(&mut self, name: &str) -> color_eyre::Result<()> {
+        let proc = self.find(name).ok_or_eyre("No such process")?;
+        if proc.is_alive() {
+            info!(target: name, "Killing process for restart");
+            proc.kill(ProcessRestart::Respawn);
+            Ok(())
+        } else {
+            self.spawn(name).map(|_| ())
+        }
+    }
+
+    /// Give a process that has given up, or failed to start, a fresh set of
+    /// restarts and start it again.
+    pub fn reset_and_restart(&mut self, name: &str) -> color_eyre::Result<()> {
+        let proc = self.find(name).ok_or_eyre("No such process")?;
+        if !matches!(
+            proc.state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _) | ProcessState::Failed(_)
+        ) {
+            return Err(eyre!("Process hasn't stopped or failed"));
+        }
+        warn!(target: name, "Restarts reset by hand after {} restart(s)", proc.restarts);
+        proc.restarts = 0;
+        self.spawn(name).map(|_| ())
+    }
+
+    /// Stop a process without restarting it, calling off any restart it was
+    /// waiting for. Returns its state afterwards.
+    pub fn stop(&mut self, name: &str) -> color_eyre::Result<&'static str> {
+        let proc = self
+            .find(name)
+            .ok_or_else(|| eyre!("No such process `{}`", name))?;
+        if proc.is_alive() {
+            info!(target: name, "Killing process");
+            proc.kill(ProcessRestart::NoRestart);
+        } else if let ProcessState::Stopped(restart, _) = &mut proc.state {
+            *restart = ProcessRestart::NoRestart;
+        }
+        Ok(proc.state.label())
+    }
+
+    /// Start a process unless it is already alive. Returns its state
+    /// afterwards.
+    pub fn start(&mut self, name: &str) -> color_eyre::Result<&'static str> {
+        let proc = self
+            .find(name)
+            .ok_or_else(|| eyre!("No such process `{}`", name))?;
+        if !proc.is_alive() {
+            info!(target: name, "Starting process");
+            self.spawn(name)?;
+        }
+        let proc = self.get(name).ok_or_eyre("No such process")?;
+        Ok(proc.state.label())
+    }
+
+    /// Stop every live process without restarting it.
+    pub fn stop_all(&mut self) {
+        for proc in self.processes.iter_mut().filter(|p| p.is_alive()) {
+            info!(target: &proc.name, "Killing process");
+            proc.kill(ProcessRestart::NoRestart);
+        }
+    }
+
+    /// Start every process that has stopped for good or failed to start,
+    /// leaving live processes and those waiting to restart alone.
+    ///
+    /// Returns how many processes were started.
+    pub fn start_stopped(&mut self) -> usize {
+        let names: Vec<String> = self
+            .processes
+            .iter()
+            .filter(|p| {
+                !p.removed
+                    && matches!(
+                        p.state,
+                        ProcessState::Stopped(ProcessRestart::NoRestart, _)
+                            | ProcessState::Completed(_)
+                            | ProcessState::Failed(_)
+                    )
+            })
+            .map(|p| p.name.clone())
+            .collect();
+        let mut started = 0;
+        for name in names {
+            info!(target: &name, "Starting stopped process");
+            match self.spawn(&name) {
+                Ok(_) => started += 1,
+                Err(err) => error!("Failed to start process {}: {}", name, err),
+            }
+        }
+        info!("Started {} stopped process(es)", started);
+        started
+    }
+
+    /// Refresh stats less often while idle.
+    pub fn set_idle(&self, idle: bool) {
+        let period = if idle {
+            IDLE_STATS_PERIOD
+        } else {
+            STATS_PERIOD
+        };
+        self.stats_period.send_if_modified(|current| {
+            let changed = *current != period;
+            *current = period;
+            changed
+        });
+    }
+
+    pub fn health(&self) -> Health {
+        Health::of(self.current().map(|p| &p.state))
+    }
+
+    /// The processes still in the config.
+    pub fn current(&self) -> impl Iterator<Item = &Process> {
+        self.processes.iter().filter(|p| !p.removed)
+    }
+
+    /// Restart every process still in the config, whether or not it is
+    /// currently alive.
+    pub fn restart_all(&mut self) {
+        let names: Vec<String> = self.current().map(|p| p.name.clone()).collect();
+        for name in names {
+            if let Err(err) = self.restart_alone(&name) {
+                error!("Failed to restart process {}: {}", name, err);
+            }
+        }
+    }
+}
+
+/// The default least time between starts of a process that keeps dying.
+pub const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(1);
+/// Restarts in a row held back by the minimum interval before warning.
+const QUICK_RESTARTS_WARNING: u32 = 3;
+
+/// How often process stats are refreshed.
+const STATS_PERIOD: Duration = Duration::from_secs(2);
+/// How often process stats are refreshed when idle in power save mode.
+const IDLE_STATS_PERIOD: Duration = Duration::from_secs(10);
+
+/// Ask for a stats refresh every `period` until the manager is dropped or
+/// nobody is listening for events any more.
+async fn stats_ticker(ticker: UnboundedSender<Event>, mut period: watch::Receiver<Duration>) {
+    loop {
+        let wait = *period.borrow_and_update();
+        select! {
+            _ = sleep(wait) => {}
+            changed = period.changed() => match changed {
+                Ok(()) => continue,
+                Err(_) => break,
+            },
+        }
+        if ticker.send(Event::App(AppEvent::StatsRefresh)).is_err() {
+            break;
+        }
+    }
+    debug!("Stats ticker exiting");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::{
+        config::{HealthCheck, OnRemove, RestartPolicy, Service},
+        proc::{
+            command::command_line,
+            testing::{Harness, exit, manager_with, restarting, service, sleeper},
+        },
+    };
+
+    #[test]
+    fn health_is_pending_until_all_are_running() {
+        let states = [ProcessState::Running, ProcessState::Starting];
+        assert_eq!(Health::of(states.iter()), Health::Pending);
+        let states = [ProcessState::Running, ProcessState::Running];
+        assert_eq!(Health::of(states.iter()), Health::Healthy);
+    }
+
+    #[test]
+    fn any_failure_is_unhealthy() {
+        let states = [
+            ProcessState::Starting,
+            ProcessState::Failed("not found".to_string()),
+            ProcessState::Running,
+        ];
+        assert_eq!(Health::of(states.iter()), Health::Unhealthy);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn completed_processes_are_healthy() {
+        let states = [ProcessState::Running, ProcessState::Completed(exit(0))];
+        assert_eq!(Health::of(states.iter()), Health::Healthy);
+    }
+
+    #[test]
+    fn nothing_to_run_is_healthy() {
+        assert_eq!(Health::of([].iter()), Health::Healthy);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn containers_docker_refuses_fail_unless_spawned_again() {
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Starting);
+        manager.container_chosen(Uuid::new_v4(), Err("stale".to_string()));
+        assert!(matches!(manager.processes[0].state, ProcessState::Starting));
+
+        manager.container_chosen(uuid, Err("Container exists".to_string()));
+        assert!(matches!(
+            &manager.processes[0].state,
+            ProcessState::Failed(reason) if reason == "Container exists"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restarts_are_pushed_to_statsd_without_fresh_stats() {
+        let (mut manager, _) = manager_with(sleeper(), ProcessState::Starting);
+        manager.processes[0].restarts = 2;
+        let (sender, mut lines) = tokio::sync::mpsc::unbounded_channel();
+        manager.statsd = Some(("agent".to_string(), sender));
+
+        manager.push_statsd(Instant::now());
+        assert_eq!(
+            lines.try_recv().unwrap(),
+            ["procli.restarts:2|c|#service:sleeper"]
+        );
+        manager.push_statsd(Instant::now());
+        assert!(lines.try_recv().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn instant_exit_goes_through_the_death_path_before_stats() {
+        let mut harness = Harness::new();
+        harness.upsert(&service("blink", "true"));
+        harness.next_death().await;
+        let proc = harness.process("blink");
+        assert!(matches!(proc.state, ProcessState::Completed(_)));
+        assert!(proc.died_before_stats);
+
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Running);
+        manager.process_died(uuid, exit(1));
+        assert!(!manager.processes[0].died_before_stats);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stats_wait_for_a_live_process() {
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Starting);
+        manager.tick();
+        assert!(manager.totals.is_empty());
+        assert!(manager.self_stats.is_none());
+        manager.processes[0].pid = manager.self_pid;
+        manager.tick();
+        assert_eq!(manager.totals.len(), 1);
+
+        manager.process_died(uuid, exit(1));
+        assert_eq!(manager.processes[0].pid, None);
+        manager.tick();
+        assert_eq!(manager.totals.len(), 1, "no stats once all have died");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stopping_calls_off_a_pending_restart() {
+        let state = ProcessState::Stopped(ProcessRestart::Respawn, exit(1));
+        let (mut manager, _) = manager_with(sleeper(), state);
+        assert_eq!(manager.stop("sleeper").unwrap(), "stopped");
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+        let err = manager.start("nope").unwrap_err();
+        assert_eq!(err.to_string(), "No such process `nope`");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restarts_are_spaced_by_the_minimum_interval() {
+        let svc = restarting(sleeper(), 10);
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+        manager.min_restart_interval = Duration::from_millis(500);
+        let started = Instant::now();
+        manager.processes[0].last_start = Some(started);
+        manager.process_died(uuid, exit(1));
+        manager.check_restarts();
+        let ProcessState::Stopped(ProcessRestart::RestartAt(at), _) = manager.processes[0].state
+        else {
+            panic!("Expected a pending restart");
+        };
+        assert!(at >= started + Duration::from_millis(500));
+        assert_eq!(manager.processes[0].quick_restarts, 1);
+
+        manager.processes[0].state = ProcessState::Running;
+        manager.processes[0].last_start = Some(started - Duration::from_secs(1));
+        manager.process_died(uuid, exit(1));
+        assert_eq!(manager.processes[0].quick_restarts, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn clean_exit_completes_without_restarting() {
+        let svc = restarting(sleeper(), 3);
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+        manager.process_died(uuid, exit(0));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Completed(_)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn success_codes_complete() {
+        let svc = Service {
+            success_codes: vec![3],
+            ..sleeper()
+        };
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+        manager.process_died(uuid, exit(3));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Completed(_)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn outliving_max_lifetime_stops_then_restarts_by_policy() {
+        let svc = Service {
+            max_lifetime_secs: Some(60),
+            ..restarting(sleeper(), 3)
+        };
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(59));
+        manager.check_lifetimes();
+        assert!(matches!(manager.processes[0].state, ProcessState::Running));
+
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(61));
+        manager.check_lifetimes();
+        assert_eq!(manager.processes[0].expired, Some(Expiry::Lifetime));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Killing(_)
+        ));
+
+        manager.process_died(uuid, exit(143));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stalled_start_times_out_then_restarts_by_policy() {
+        let svc = Service {
+            start_timeout_secs: Some(30),
+            ..restarting(sleeper(), 3)
+        };
+        // Never spawned, so it has no pid and never gets stats.
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Starting);
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(29));
+        manager.tick();
+        assert!(matches!(manager.processes[0].state, ProcessState::Starting));
+
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(31));
+        manager.tick();
+        assert_eq!(manager.processes[0].expired, Some(Expiry::StartTimeout));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Killing(_)
+        ));
+
+        manager.process_died(uuid, exit(143));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn running_but_not_yet_healthy_also_times_out() {
+        let svc = Service {
+            start_timeout_secs: Some(30),
+            ..sleeper()
+        };
+        let (mut manager, _) = manager_with(svc, ProcessState::Running);
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(31));
+        manager.tick();
+        assert!(matches!(manager.processes[0].state, ProcessState::Running));
+
+        manager.processes[0].health = Some(HealthStatus::Starting);
+        manager.tick();
+        assert_eq!(manager.processes[0].expired, Some(Expiry::StartTimeout));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn silence_past_the_idle_timeout_flags_but_keeps_running() {
+        let svc = Service {
+            idle_timeout_secs: Some(30),
+            ..sleeper()
+        };
+        let (mut manager, _) = manager_with(svc, ProcessState::Running);
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(29));
+        manager.tick();
+        assert!(!manager.processes[0].idle);
+
+        manager.processes[0].last_start = Some(Instant::now() - Duration::from_secs(31));
+        manager.tick();
+        assert!(manager.processes[0].idle);
+        assert!(matches!(manager.processes[0].state, ProcessState::Running));
+
+        manager.processes[0].output.push("still here".to_string());
+        manager.tick();
+        assert!(!manager.processes[0].idle);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn zero_max_restarts_means_unlimited_unless_disabled() {
+        // (enabled, max_restarts, restarts so far, restarted again)
+        let cases = [
+            (true, 0, 0, true),
+            (true, 0, 1000, true),
+            (true, 3, 2, true),
+            (true, 3, 3, false),
+            (false, 0, 0, false),
+            (false, 3, 0, false),
+        ];
+        for (enabled, max_restarts, restarts, restarted) in cases {
+            let svc = Service {
+                restart: Some(RestartPolicy {
+                    enabled,
+                    cooloff: 0,
+                    max_restarts,
+                }),
+                ..sleeper()
+            };
+            let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+            manager.processes[0].restarts = restarts;
+            manager.process_died(uuid, exit(1));
+            assert_eq!(
+                matches!(
+                    manager.processes[0].state,
+                    ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+                ),
+                restarted,
+                "enabled = {enabled}, max_restarts = {max_restarts}, restarts = {restarts}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn crash_is_restarted_by_policy() {
+        let svc = restarting(sleeper(), 3);
+        let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+        manager.process_died(uuid, exit(1));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn crash_is_restarted_until_the_policy_runs_out() {
+        let mut harness = Harness::new();
+        let first = harness.upsert(&restarting(service("flaky", "exit 3"), 1));
+
+        let (died, status) = harness.next_death().await;
+        assert_eq!(died, first);
+        assert_eq!(status.code(), Some(3));
+        assert!(matches!(
+            harness.process("flaky").state,
+            ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+        ));
+
+        harness.manager.tick();
+        let proc = harness.process("flaky");
+        assert_eq!(proc.restarts, 1);
+        assert_ne!(proc.uuid, first);
+
+        let (died, _) = harness.next_death().await;
+        assert_ne!(died, first);
+        assert!(matches!(
+            harness.process("flaky").state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn matching_output_marks_the_process_healthy() {
+        let mut harness = Harness::new();
+        harness.upsert(&Service {
+            ready_log_pattern: Some(r"Listening on port \d+".to_string()),
+            ..service(
+                "web",
+                "echo booting; echo 'Listening on port 8080'; sleep 5",
+            )
+        });
+        assert_eq!(harness.process("web").health, Some(HealthStatus::Starting));
+
+        harness
+            .until(|m| m.processes[0].health == Some(HealthStatus::Healthy))
+            .await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unhealthy_restarts_only_if_the_check_says_to() {
+        let check = |restart| HealthCheck {
+            command: "false".to_string(),
+            interval_secs: 1,
+            timeout_secs: 1,
+            retries: 1,
+            start_period_secs: 0,
+            restart,
+        };
+        for restart in [false, true] {
+            let svc = Service {
+                health_check: Some(check(restart)),
+                ..restarting(sleeper(), 3)
+            };
+            let (mut manager, uuid) = manager_with(svc, ProcessState::Running);
+            manager.health_changed(uuid, HealthStatus::Unhealthy);
+            assert_eq!(manager.processes[0].health, Some(HealthStatus::Unhealthy));
+            assert_eq!(
+                manager.processes[0].expired == Some(Expiry::Unhealthy),
+                restart
+            );
+            assert_eq!(
+                matches!(manager.processes[0].state, ProcessState::Killing(_)),
+                restart
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unhealthy_restarts_count_against_the_policy() {
+        let svc = Service {
+            health_check: Some(HealthCheck {
+                command: "false".to_string(),
+                interval_secs: 1,
+                timeout_secs: 1,
+                retries: 1,
+                start_period_secs: 0,
+                restart: true,
+            }),
+            ..restarting(sleeper(), 1)
+        };
+        for (restarts, restarted) in [(0, true), (1, false)] {
+            let (mut manager, uuid) = manager_with(svc.clone(), ProcessState::Running);
+            manager.processes[0].restarts = restarts;
+            manager.health_changed(uuid, HealthStatus::Unhealthy);
+            manager.process_died(uuid, exit(143));
+            assert_eq!(
+                matches!(
+                    manager.processes[0].state,
+                    ProcessState::Stopped(ProcessRestart::RestartAt(_), _)
+                ),
+                restarted,
+                "restarts = {restarts}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn removed_processes_are_kept_or_dropped_once_stopped() {
+        let svc = restarting(sleeper(), 3);
+        for (on_remove, kept) in [(OnRemove::Keep, 1), (OnRemove::Drop, 0)] {
+            let (mut manager, uuid) = manager_with(svc.clone(), ProcessState::Running);
+            manager.on_remove = on_remove;
+            manager.remove("sleeper").unwrap();
+            assert_eq!(manager.processes.len(), 1);
+            assert_eq!(manager.health(), Health::Healthy);
+            manager.process_died(uuid, exit(143));
+            assert_eq!(manager.processes.len(), kept);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kept_removed_processes_stay_down_until_dismissed() {
+        let state = ProcessState::Stopped(ProcessRestart::RestartAt(Instant::now()), exit(1));
+        let (mut manager, _) = manager_with(sleeper(), state);
+        assert!(manager.dismiss("sleeper").is_err());
+        manager.remove("sleeper").unwrap();
+        manager.check_restarts();
+        assert_eq!(manager.start_stopped(), 0);
+        manager.restart_all();
+        assert!(manager.start("sleeper").is_err());
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+        manager.dismiss("sleeper").unwrap();
+        assert!(manager.processes.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reset_and_restart_only_revives_the_given_up() {
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Running);
+        assert!(manager.reset_and_restart("sleeper").is_err());
+
+        manager.processes[0].restarts = 3;
+        manager.process_died(uuid, exit(1));
+        manager.reset_and_restart("sleeper").unwrap();
+        assert_eq!(manager.processes[0].restarts, 0);
+        assert!(manager.processes[0].is_alive());
+        manager.stop_all();
+    }
+
+    #[cfg(unix)]
+    fn reloaded(restart_on_config_change: ConfigChangeRestart, command: &str) -> bool {
+        let svc = Service {
+            restart_on_config_change,
+            ..sleeper()
+        };
+        let stopped = ProcessState::Stopped(ProcessRestart::NoRestart, exit(1));
+        let (mut manager, _) = manager_with(svc.clone(), stopped);
+        manager
+            .upsert(&Service {
+                command: Some(command.to_string()),
+                ..svc
+            })
+            .unwrap();
+        assert_eq!(manager.processes.len(), 1);
+        assert_eq!(
+            command_line(&manager.processes[0].cmd),
+            command,
+            "the new command is kept either way"
+        );
+        let restarted = manager.processes[0].is_alive();
+        manager.stop_all();
+        restarted
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reload_restarts_if_changed_by_default() {
+        assert!(!reloaded(ConfigChangeRestart::IfChanged, "sleep 1"));
+        assert!(reloaded(ConfigChangeRestart::IfChanged, "sleep 2"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reload_always_restarts() {
+        assert!(reloaded(ConfigChangeRestart::Always, "sleep 1"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reload_never_restarts() {
+        assert!(!reloaded(ConfigChangeRestart::Never, "sleep 2"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dependents_stop_first_and_start_after_the_dependency() {
+        let mut harness = Harness::new();
+        for (name, dependencies) in [("db", vec![]), ("api", vec!["db"]), ("web", vec!["api"])] {
+            harness.upsert(&Service {
+                dependencies: dependencies.into_iter().map(String::from).collect(),
+                ..service(name, "sleep 10")
+            });
+        }
+        let running = |manager: &ProcessManager| {
+            manager
+                .processes
+                .iter()
+                .all(|p| matches!(p.state, ProcessState::Running))
+        };
+        harness.until(running).await;
+        let db_started = harness.process("db").last_start;
+
+        let manager = &mut harness.manager;
+        manager.restart("db").unwrap();
+        assert!(matches!(manager.processes[0].state, ProcessState::Running));
+        assert!(matches!(
+            manager.processes[2].state,
+            ProcessState::Killing(ProcessRestart::NoRestart)
+        ));
+        assert_eq!(manager.processes[2].waiting_for.as_deref(), Some("db"));
+
+        harness
+            .until(|manager| manager.processes[0].last_start != db_started && running(manager))
+            .await;
+        let manager = &mut harness.manager;
+        let [db, api, web] = &manager.processes[..] else {
+            panic!("three processes");
+        };
+        assert!(api.last_stop < db.last_stop && web.last_stop < db.last_stop);
+        assert!(db.last_start < api.last_start && api.last_start < web.last_start);
+        assert!(manager.ordered.is_empty());
+        manager.stop_all();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn feed_streams_deaths_and_state_changes_once() {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Running);
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .to_string();
+        manager.set_feed(feed::Sockets {
+            events: Some(addr.clone()),
+            control: None,
+        });
+        let stream = loop {
+            match tokio::net::TcpStream::connect(&addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let mut lines = BufReader::new(stream).lines();
+        // Let the connection subscribe before anything is published.
+        sleep(Duration::from_millis(50)).await;
+
+        manager.publish_state_changes();
+        manager.publish_state_changes();
+        manager.process_died(uuid, exit(2));
+        manager.publish_state_changes();
+        let mut next = async || lines.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            next().await,
+            r#"{"event":"state_changed","name":"sleeper","from":null,"to":"running"}"#
+        );
+        assert_eq!(
+            next().await,
+            r#"{"event":"died","name":"sleeper","code":2}"#
+        );
+        assert_eq!(
+            next().await,
+            r#"{"event":"state_changed","name":"sleeper","from":"running","to":"stopped"}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn crash_without_policy_stops() {
+        let (mut manager, uuid) = manager_with(sleeper(), ProcessState::Running);
+        manager.process_died(uuid, exit(1));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+        assert!(!manager.processes[0].stopped_on_request);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn requested_stop_is_not_a_completion() {
+        let killing = ProcessState::Killing(ProcessRestart::NoRestart);
+        let (mut manager, uuid) = manager_with(sleeper(), killing);
+        manager.process_died(uuid, exit(0));
+        assert!(matches!(
+            manager.processes[0].state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+        assert!(manager.processes[0].stopped_on_request);
+    }
+
+    #[tokio::test]
+    async fn stats_ticker_exits_when_events_are_no_longer_received() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (_keep, period) = watch::channel(Duration::from_millis(1));
+        drop(receiver);
+        let ticker = tokio::spawn(stats_ticker(sender, period));
+        assert!(ticker.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stats_ticker_picks_up_a_new_period() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (period_tx, period) = watch::channel(Duration::from_secs(60));
+        tokio::spawn(stats_ticker(sender, period));
+        period_tx.send_replace(Duration::from_millis(1));
+        let refresh = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await;
+        assert!(matches!(
+            refresh,
+            Ok(Some(Event::App(AppEvent::StatsRefresh)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stats_ticker_stops_with_the_manager() {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (stopper, period) = watch::channel(Duration::from_secs(60));
+        let ticker = tokio::spawn(stats_ticker(sender, period));
+        drop(stopper);
+        let stopped = tokio::time::timeout(Duration::from_secs(1), ticker).await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+    }
 }
@@ -1,39 +1,132 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ffi::OsString,
-    path::absolute,
+    path::{PathBuf, absolute},
     process::{ExitStatus, Stdio},
-    time::{self, Instant},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{self, Duration, Instant},
 };
 
 use color_eyre::eyre::Result;
 use log::*;
+use regex::Regex;
 use sysinfo::Pid;
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::{ChildStderr, ChildStdout, Command},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{ChildStdin, Command},
     select,
-    sync::{mpsc::UnboundedSender, oneshot},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
 };
 use uuid::Uuid;
 
 use crate::{
-    config::{RestartPolicy, Service, Stub},
+    config::{
+        ConfigChangeRestart, DEFAULT_MOUNT_TARGET, HealthCheck, RestartPolicy, Service, Stub,
+    },
     event::{AppEvent, Event},
-    proc::{command::build_command, stats::ProcessStats},
+    proc::{
+        ansi,
+        command::{Hook, build_command, build_health_check, build_post_start, build_reuse},
+        container::{self, Launch, Reuse, container_name},
+        health::{self, HealthStatus},
+        rate_limit::RateLimit,
+        stats::{self, Alert, ProcessStats, Thresholds},
+        terminate::{self, ProcessTree},
+    },
 };
 
+/// What a process was configured as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessKind {
+    Service,
+    Stub,
+    Agent,
+}
+
+impl ProcessKind {
+    /// The default title label for this kind of process.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProcessKind::Service => "SVC",
+            ProcessKind::Stub => "STUB",
+            ProcessKind::Agent => "AGENT",
+        }
+    }
+
+    /// `n` of this kind, for counts such as "2 services".
+    pub fn count(&self, n: usize) -> String {
+        let noun = match self {
+            ProcessKind::Service => "service",
+            ProcessKind::Stub => "stub",
+            ProcessKind::Agent => "agent",
+        };
+        if n == 1 {
+            format!("{n} {noun}")
+        } else {
+            format!("{n} {noun}s")
+        }
+    }
+}
+
 pub trait Named {
     fn name(&self) -> String;
     fn display(&self) -> String;
+    fn kind(&self) -> ProcessKind;
+    /// Shown before the display name in titles; may be empty.
+    fn label(&self) -> String;
+    /// What the process is for, in a few words.
+    fn note(&self) -> Option<String>;
 }
 
 pub trait ProcessConfig {
     fn image(&self) -> Option<String>;
     fn command(&self) -> Option<String>;
+    /// Arguments to pass after those in the command, as they are.
+    fn args(&self) -> Vec<String>;
     fn directory(&self) -> Result<Option<OsString>>;
+    /// Container path the directory is mounted at for image based processes.
+    fn mount_target(&self) -> String;
+    /// Whether image based processes get the directory mounted at all.
+    fn mount_directory(&self) -> bool;
+    /// Name of the container for image based processes.
+    fn container_name(&self) -> String;
+    /// Whether to keep the container and start it again on restart.
+    fn reuse(&self) -> bool;
+    /// Whether a config reload restarts the process.
+    fn restart_on_config_change(&self) -> ConfigChangeRestart;
+    /// Names of the processes this one needs running.
+    fn dependencies(&self) -> Vec<String>;
     fn environment(&self) -> HashMap<String, String>;
     fn restart_policy(&self) -> RestartPolicy;
+    fn thresholds(&self) -> Thresholds;
+    fn success_codes(&self) -> Vec<i32>;
+    fn entrypoint(&self) -> Option<String>;
+    fn shell(&self) -> bool;
+    /// How long the process may run before it is stopped.
+    fn max_lifetime(&self) -> Option<Duration>;
+    /// How long the process may take to get going before it is stopped.
+    fn start_timeout(&self) -> Option<Duration>;
+    /// How long the process may go without output before it is flagged.
+    fn idle_timeout(&self) -> Option<Duration>;
+    /// Whether to strip escape codes from the output.
+    fn ansi(&self) -> bool;
+    /// Whether to read stdout and stderr through one pipe.
+    fn merge_output(&self) -> bool;
+    /// Shell command to run each time the process starts running.
+    fn post_start(&self) -> Option<String>;
+    /// Command run now and then to check the process is healthy.
+    fn health_check(&self) -> Option<HealthCheck>;
+    /// Output that shows the process is ready.
+    fn ready_log_pattern(&self) -> Option<String>;
+    /// Most output lines to log each second.
+    fn log_rate_limit(&self) -> Option<u32>;
 }
 
 impl Named for Service {
@@ -44,6 +137,20 @@ impl Named for Service {
     fn display(&self) -> String {
         self.display.clone().unwrap_or(self.name.clone())
     }
+
+    fn kind(&self) -> ProcessKind {
+        ProcessKind::Service
+    }
+
+    fn label(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or(self.kind().label().to_string())
+    }
+
+    fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
 }
 
 impl Named for Stub {
@@ -54,6 +161,20 @@ impl Named for Stub {
     fn display(&self) -> String {
         self.display.clone().unwrap_or(self.name.clone())
     }
+
+    fn kind(&self) -> ProcessKind {
+        ProcessKind::Stub
+    }
+
+    fn label(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or(self.kind().label().to_string())
+    }
+
+    fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
 }
 
 impl ProcessConfig for Service {
@@ -65,6 +186,10 @@ impl ProcessConfig for Service {
         self.command.clone()
     }
 
+    fn args(&self) -> Vec<String> {
+        self.args.clone()
+    }
+
     fn directory(&self) -> Result<Option<OsString>> {
         let dir = match self.directory.as_ref() {
             Some(d) => Some(absolute(d)?.into_os_string()),
@@ -73,12 +198,84 @@ impl ProcessConfig for Service {
         Ok(dir)
     }
 
+    fn mount_target(&self) -> String {
+        self.mount_target
+            .clone()
+            .unwrap_or(DEFAULT_MOUNT_TARGET.to_string())
+    }
+
+    fn mount_directory(&self) -> bool {
+        self.mount_directory.unwrap_or(true)
+    }
+
+    fn container_name(&self) -> String {
+        self.container_name
+            .clone()
+            .unwrap_or_else(|| container_name(&self.name))
+    }
+
+    fn reuse(&self) -> bool {
+        self.reuse
+    }
+
+    fn restart_on_config_change(&self) -> ConfigChangeRestart {
+        self.restart_on_config_change
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.dependencies.clone()
+    }
+
     fn environment(&self) -> HashMap<String, String> {
         self.environment.clone()
     }
     fn restart_policy(&self) -> RestartPolicy {
         self.restart.unwrap_or_default()
     }
+    fn success_codes(&self) -> Vec<i32> {
+        self.success_codes.clone()
+    }
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            cpu_warn: self.cpu_warn,
+            cpu_crit: self.cpu_crit,
+            mem_warn: self.mem_warn,
+            mem_crit: self.mem_crit,
+        }
+    }
+    fn entrypoint(&self) -> Option<String> {
+        self.entrypoint.clone()
+    }
+    fn shell(&self) -> bool {
+        self.shell
+    }
+    fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime_secs.map(Duration::from_secs)
+    }
+    fn start_timeout(&self) -> Option<Duration> {
+        self.start_timeout_secs.map(Duration::from_secs)
+    }
+    fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+    fn post_start(&self) -> Option<String> {
+        self.post_start.clone()
+    }
+    fn health_check(&self) -> Option<HealthCheck> {
+        self.health_check.clone()
+    }
+    fn ready_log_pattern(&self) -> Option<String> {
+        self.ready_log_pattern.clone()
+    }
+    fn ansi(&self) -> bool {
+        self.ansi
+    }
+    fn merge_output(&self) -> bool {
+        self.merge_output
+    }
+    fn log_rate_limit(&self) -> Option<u32> {
+        self.log_rate_limit
+    }
 }
 
 impl ProcessConfig for Stub {
@@ -90,6 +287,10 @@ impl ProcessConfig for Stub {
         self.command.clone()
     }
 
+    fn args(&self) -> Vec<String> {
+        self.args.clone()
+    }
+
     fn directory(&self) -> Result<Option<OsString>> {
         let dir = match self.directory.as_ref() {
             Some(d) => Some(absolute(d)?.into_os_string()),
@@ -98,18 +299,149 @@ impl ProcessConfig for Stub {
         Ok(dir)
     }
 
+    fn mount_target(&self) -> String {
+        self.mount_target
+            .clone()
+            .unwrap_or(DEFAULT_MOUNT_TARGET.to_string())
+    }
+
+    fn mount_directory(&self) -> bool {
+        self.mount_directory.unwrap_or(true)
+    }
+
+    fn container_name(&self) -> String {
+        self.container_name
+            .clone()
+            .unwrap_or_else(|| container_name(&self.name))
+    }
+
+    fn reuse(&self) -> bool {
+        self.reuse
+    }
+
+    fn restart_on_config_change(&self) -> ConfigChangeRestart {
+        self.restart_on_config_change
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     fn environment(&self) -> HashMap<String, String> {
         self.environment.clone()
     }
     fn restart_policy(&self) -> RestartPolicy {
         self.restart.unwrap_or_default()
     }
+    fn success_codes(&self) -> Vec<i32> {
+        self.success_codes.clone()
+    }
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            cpu_warn: self.cpu_warn,
+            cpu_crit: self.cpu_crit,
+            mem_warn: self.mem_warn,
+            mem_crit: self.mem_crit,
+        }
+    }
+    fn entrypoint(&self) -> Option<String> {
+        None
+    }
+    fn shell(&self) -> bool {
+        false
+    }
+    fn max_lifetime(&self) -> Option<Duration> {
+        None
+    }
+    fn start_timeout(&self) -> Option<Duration> {
+        None
+    }
+    fn idle_timeout(&self) -> Option<Duration> {
+        None
+    }
+    fn post_start(&self) -> Option<String> {
+        None
+    }
+    fn health_check(&self) -> Option<HealthCheck> {
+        None
+    }
+    fn ready_log_pattern(&self) -> Option<String> {
+        None
+    }
+    fn ansi(&self) -> bool {
+        self.ansi
+    }
+    fn merge_output(&self) -> bool {
+        self.merge_output
+    }
+    fn log_rate_limit(&self) -> Option<u32> {
+        self.log_rate_limit
+    }
+}
+
+/// How many recent output lines each process keeps.
+pub const RECENT_OUTPUT_LINES: usize = 10;
+
+/// The last few lines a process wrote to stdout or stderr, shared with the
+/// tasks pumping its output.
+#[derive(Debug, Clone, Default)]
+pub struct RecentOutput(Arc<Mutex<OutputLines>>);
+
+#[derive(Debug, Default)]
+struct OutputLines {
+    lines: VecDeque<String>,
+    /// Every line ever pushed, including those since dropped.
+    total: u64,
+    /// When the latest line was pushed.
+    last: Option<Instant>,
+}
+
+impl RecentOutput {
+    pub fn push(&self, line: String) {
+        let mut output = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if output.lines.len() == RECENT_OUTPUT_LINES {
+            output.lines.pop_front();
+        }
+        output.lines.push_back(line);
+        output.total += 1;
+        output.last = Some(Instant::now());
+    }
+
+    /// Oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        let output = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        output.lines.iter().cloned().collect()
+    }
+
+    /// How many lines have been written in all, to spot new output.
+    pub fn total(&self) -> u64 {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).total
+    }
+
+    /// When the latest line was written, if there has been one.
+    pub fn last(&self) -> Option<Instant> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).last
+    }
+}
+
+/// Why procli stopped a process of its own accord, leaving what happens next
+/// to the restart policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// It had been running for `max_lifetime`.
+    Lifetime,
+    /// It hadn't got going within `start_timeout`.
+    StartTimeout,
+    /// Its health check found it unhealthy, and says to restart it.
+    Unhealthy,
 }
 
 #[derive(Debug)]
 pub enum ProcessRestart {
     NoRestart,
     RestartAt(time::Instant),
+    /// Respawn as soon as the process has died, outside the restart policy.
+    Respawn,
 }
 
 #[derive(Debug)]
@@ -117,24 +449,165 @@ pub enum ProcessState {
     Starting,
     Running,
     Killing(ProcessRestart),
+    /// Stopped on request, or crashed.
     Stopped(ProcessRestart, ExitStatus),
+    /// Exited by itself with a success code. Never restarted automatically.
+    Completed(ExitStatus),
+    /// The command could not be executed at all.
+    Failed(String),
+}
+
+/// Pipe a child's stdio and let it be stopped gracefully.
+fn configure(cmd: &mut Command) {
+    terminate::configure(cmd);
+    cmd.stdin(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+}
+
+/// Point the child's stdout and stderr at the same new pipe, returning its
+/// read end. Lines then arrive in the order the child wrote them, whichever
+/// stream they were written to.
+#[cfg(unix)]
+fn merged_pipe(cmd: &mut Command) -> std::io::Result<Option<tokio::net::unix::pipe::Receiver>> {
+    let (reader, writer) = std::io::pipe()?;
+    cmd.stdout(writer.try_clone()?);
+    cmd.stderr(writer);
+    tokio::net::unix::pipe::Receiver::from_owned_fd(reader.into()).map(Some)
+}
+
+/// Tokio has no way to read an anonymous pipe asynchronously here, so the
+/// streams stay separate.
+#[cfg(not(unix))]
+fn merged_pipe(_cmd: &mut Command) -> std::io::Result<Option<tokio::io::Empty>> {
+    Ok(None)
+}
+
+impl ProcessState {
+    /// A short name for the state, without its details.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProcessState::Starting => "starting",
+            ProcessState::Running => "running",
+            ProcessState::Killing(_) => "stopping",
+            ProcessState::Stopped(_, _) => "stopped",
+            ProcessState::Completed(_) => "completed",
+            ProcessState::Failed(_) => "failed",
+        }
+    }
+
+    /// Why the last child died, e.g. "exit 1" or "SIGKILL", while it is
+    /// stopped.
+    pub fn exit_reason(&self) -> Option<String> {
+        match self {
+            ProcessState::Stopped(_, status) => Some(exit_reason(status)),
+            _ => None,
+        }
+    }
+}
+
+/// A short description of how a child exited.
+pub fn exit_reason(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exit {code}");
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return match signal {
+                1 => "SIGHUP".to_string(),
+                2 => "SIGINT".to_string(),
+                3 => "SIGQUIT".to_string(),
+                6 => "SIGABRT".to_string(),
+                9 => "SIGKILL".to_string(),
+                11 => "SIGSEGV".to_string(),
+                13 => "SIGPIPE".to_string(),
+                15 => "SIGTERM".to_string(),
+                signal => format!("signal {signal}"),
+            };
+        }
+    }
+    "killed".to_string()
 }
 
 #[derive(Debug)]
 pub struct Process {
     pub name: String,
     pub display: String,
+    pub kind: ProcessKind,
+    /// Shown before the display name in titles; may be empty.
+    pub label: String,
+    pub note: Option<String>,
     pub uuid: Uuid,
     pub cmd: Command,
+    /// How to start a kept container again instead of running `cmd`.
+    reuse: Option<Reuse>,
+    /// The configured directory made absolute, whether the process runs in it
+    /// locally or has it mounted into a container.
+    pub directory: Option<PathBuf>,
     closer: Option<oneshot::Receiver<()>>,
+    /// Stdin writer and death handler of the current child.
+    tasks: Vec<JoinHandle<()>>,
+    /// Tasks copying the current child's output into `output`, one per pipe.
+    log_pumps: Vec<JoinHandle<()>>,
+    /// Bytes to write to the child's stdin, while it is open.
+    input: Option<UnboundedSender<Vec<u8>>>,
     pub state: ProcessState,
     pub restarts: u32,
     pub restart_policy: RestartPolicy,
+    /// Exit codes, besides 0, that mean the process completed successfully.
+    pub success_codes: Vec<i32>,
+    pub thresholds: Thresholds,
+    pub max_lifetime: Option<Duration>,
+    pub start_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    /// Running but silent for longer than `idle_timeout`.
+    pub idle: bool,
+    /// Stopped by procli itself rather than on request, and why.
+    pub expired: Option<Expiry>,
+    pub dependencies: Vec<String>,
+    /// Stopped while this dependency restarts, to be started after it.
+    pub waiting_for: Option<String>,
+    /// Worst alert for the latest CPU and RAM stats.
+    pub alert: Alert,
+    /// The current child's, until it has died.
     pub pid: Option<Pid>,
+    /// The last child died before the first stats refresh after it started,
+    /// so no stats were ever taken of it.
+    pub died_before_stats: bool,
+    /// The last child was stopped by a `stop`, reload or quit, rather than
+    /// dying by itself or being stopped by procli.
+    pub stopped_on_request: bool,
     pub last_start: Option<Instant>,
     pub last_stop: Option<Instant>,
+    /// Restarts in a row held back by the minimum restart interval.
+    pub quick_restarts: u32,
+    /// Taken out of the config, and so never started again, but kept to show
+    /// how it ended.
+    pub removed: bool,
     pub stats: Vec<ProcessStats>,
     pub stats_max: ProcessStats,
+    pub output: RecentOutput,
+    /// Strip escape codes from output lines.
+    ansi: bool,
+    /// Strip them whatever the config says, as colour is off.
+    pub strip_ansi: bool,
+    /// Read stdout and stderr through one pipe, in the order written.
+    merge_output: bool,
+    /// Most output lines to log each second.
+    log_rate_limit: Option<u32>,
+    post_start: Option<Hook>,
+    /// Whether `post_start` has run for the current child.
+    post_started: bool,
+    health_check: Option<(HealthCheck, Hook)>,
+    /// Output that marks the current child healthy, the first time a line
+    /// matches.
+    ready_pattern: Option<Regex>,
+    /// Checks the current child; aborted when it dies or is replaced.
+    health_task: Option<JoinHandle<()>>,
+    /// What the health check last found, if there is one.
+    pub health: Option<HealthStatus>,
 }
 
 impl Process {
@@ -143,80 +616,527 @@ impl Process {
         T: Named + ProcessConfig,
     {
         let mut cmd: Command = build_command(svc)?;
-        cmd.stderr(Stdio::piped());
-        cmd.stdout(Stdio::piped());
+        configure(&mut cmd);
+        let mut reuse = build_reuse(svc)?;
+        if let Some(reuse) = reuse.as_mut() {
+            configure(&mut reuse.start);
+        }
         Ok(Self {
             name: svc.name(),
             display: svc.display(),
+            kind: svc.kind(),
+            label: svc.label(),
+            note: svc.note(),
             cmd,
+            reuse,
+            directory: svc.directory()?.map(PathBuf::from),
             uuid: Uuid::nil(),
             state: ProcessState::Starting,
             restarts: 0,
             restart_policy: svc.restart_policy(),
+            success_codes: svc.success_codes(),
+            thresholds: svc.thresholds(),
+            max_lifetime: svc.max_lifetime(),
+            start_timeout: svc.start_timeout(),
+            idle_timeout: svc.idle_timeout(),
+            idle: false,
+            expired: None,
+            dependencies: svc.dependencies(),
+            waiting_for: None,
+            alert: Alert::Normal,
             pid: None,
             last_start: None,
             last_stop: None,
+            quick_restarts: 0,
+            removed: false,
+            died_before_stats: false,
+            stopped_on_request: false,
             stats: Vec::default(),
             stats_max: ProcessStats::default(),
+            output: RecentOutput::default(),
             closer: None,
+            tasks: Vec::new(),
+            log_pumps: Vec::new(),
+            input: None,
+            ansi: svc.ansi(),
+            strip_ansi: false,
+            merge_output: svc.merge_output(),
+            log_rate_limit: svc.log_rate_limit(),
+            post_start: build_post_start(svc)?,
+            post_started: false,
+            health_check: build_health_check(svc)?,
+            ready_pattern: svc
+                .ready_log_pattern()
+                .map(|pattern| Regex::new(&pattern))
+                .transpose()?,
+            health_task: None,
+            health: None,
         })
     }
 
+    /// Start a new child. A reused container is only launched once docker
+    /// has said whether it can be started again, which arrives as
+    /// [`AppEvent::ContainerChosen`] for [`Process::launch`].
     pub fn spawn(&mut self, sender: UnboundedSender<Event>) -> color_eyre::Result<Uuid> {
         let now = Instant::now();
         self.last_start = Some(now);
         let uuid = Uuid::new_v4();
         self.uuid = uuid;
+        self.state = ProcessState::Starting;
+        self.expired = None;
+        self.waiting_for = None;
+        self.post_started = false;
+        self.died_before_stats = false;
+        self.stopped_on_request = false;
+        self.idle = false;
         info!(target: &self.name, "Spawning process {} for {}", uuid, &self.name);
 
-        let mut child = self.cmd.spawn()?;
-        self.pid = child.id().map(Pid::from_u32);
+        // The previous child has died, but anything it left running may still
+        // hold its pipes open, so stop its pumps before starting new ones.
+        for task in self.tasks.drain(..).chain(self.log_pumps.drain(..)) {
+            task.abort();
+        }
+        self.stop_health_check();
+
+        if let Some(reuse) = &self.reuse {
+            let container = reuse.container.clone();
+            let fingerprint = reuse.fingerprint.clone();
+            self.tasks.push(tokio::spawn(async move {
+                let launch = container::choose(&container, &fingerprint)
+                    .await
+                    .map_err(|err| err.to_string());
+                let _ = sender.send(Event::App(AppEvent::ContainerChosen(uuid, launch)));
+            }));
+            return Ok(uuid);
+        }
+        self.launch(Launch::Run, sender)?;
+        Ok(uuid)
+    }
 
-        let stdout = child.stdout.take().unwrap();
-        tokio::spawn(stdout_log_pump(self.name.to_string(), stdout));
-        let stderr = child.stderr.take().unwrap();
-        tokio::spawn(stderr_log_pump(self.name.to_string(), stderr));
+    /// Start the child for the current spawn, with `docker start` rather
+    /// than the usual command if `launch` says so for a reused container.
+    pub fn launch(&mut self, launch: Launch, sender: UnboundedSender<Event>) -> Result<()> {
+        let now = Instant::now();
+        let uuid = self.uuid;
+        let cmd = match (launch, self.reuse.as_mut()) {
+            (Launch::Start, Some(reuse)) => &mut reuse.start,
+            _ => &mut self.cmd,
+        };
+        let merged = match self.merge_output {
+            true => merged_pipe(cmd)?,
+            false => None,
+        };
+        if self.merge_output && merged.is_none() {
+            warn!(target: &self.name, "merge_output is only supported on Unix");
+        }
+        let spawned = cmd.spawn();
+        if merged.is_some() {
+            // Drop our copies of the write end, so the reader sees the end of
+            // the output once the child has gone.
+            configure(cmd);
+        }
+        let mut child = spawned?;
+        self.pid = child.id().map(Pid::from_u32);
+        let tree = ProcessTree::adopt(&self.name, &child);
 
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
         let (closed, closer) = oneshot::channel();
         self.closer = Some(closer);
-        tokio::spawn(death_handler(
-            self.name.to_string(),
+        let (input, input_rx) = mpsc::unbounded_channel();
+        self.input = Some(input);
+        let limit = self.log_rate_limit.map(|n| RateLimit::new(n, now));
+        let ready = self.ready_pattern.clone().map(|pattern| ReadyWatch {
+            pattern,
+            seen: Arc::new(AtomicBool::new(false)),
             uuid,
-            closed,
-            sender,
-            child,
-        ));
-        Ok(uuid)
+            sender: sender.clone(),
+        });
+        if ready.is_some() {
+            self.health = Some(HealthStatus::Starting);
+        }
+        let pump = |stream| LogPump {
+            name: self.name.to_string(),
+            stream,
+            output: self.output.clone(),
+            ansi: self.ansi || self.strip_ansi,
+            limit: limit.clone(),
+            ready: ready.clone(),
+        };
+        let mut pumps = Vec::new();
+        if let Some(output) = merged {
+            pumps.push(tokio::spawn(pump("Output").run(output)));
+        }
+        if let Some(stdout) = stdout {
+            pumps.push(tokio::spawn(pump("Stdout").run(stdout)));
+        }
+        if let Some(stderr) = stderr {
+            pumps.push(tokio::spawn(pump("Stderr").run(stderr)));
+        }
+        self.tasks = vec![
+            tokio::spawn(stdin_pump(self.name.to_string(), stdin, input_rx)),
+            tokio::spawn(death_handler(
+                self.name.to_string(),
+                uuid,
+                closed,
+                sender.clone(),
+                child,
+                tree,
+            )),
+        ];
+        self.log_pumps = pumps;
+        if let Some((check, hook)) = self.health_check.clone() {
+            self.health = Some(HealthStatus::Starting);
+            let name = self.name.to_string();
+            self.health_task = Some(tokio::spawn(health::run(name, uuid, check, hook, sender)));
+        }
+        if matches!(self.state, ProcessState::Killing(_)) {
+            // Stopped while docker was asked how to launch it, so it dies
+            // straight away, the usual way.
+            drop(self.closer.take());
+        }
+        Ok(())
+    }
+
+    /// Whether the health check restarts the process once it is unhealthy.
+    pub fn restarts_when_unhealthy(&self) -> bool {
+        self.health_check
+            .as_ref()
+            .is_some_and(|(check, _)| check.restart)
+    }
+
+    /// Stop checking the health of a child that has gone.
+    pub fn stop_health_check(&mut self) {
+        if let Some(task) = self.health_task.take() {
+            task.abort();
+        }
+        self.health = None;
+    }
+
+    /// Whether another definition of the process would run the same thing:
+    /// the same program, arguments, environment and directory.
+    pub fn same_command(&self, other: &Process) -> bool {
+        let (cmd, other) = (self.cmd.as_std(), other.cmd.as_std());
+        cmd.get_program() == other.get_program()
+            && cmd.get_args().eq(other.get_args())
+            && cmd.get_envs().collect::<HashMap<_, _>>()
+                == other.get_envs().collect::<HashMap<_, _>>()
+            && cmd.get_current_dir() == other.get_current_dir()
+    }
+
+    /// Take on a new definition of the process, keeping the current child and
+    /// its history. The new command is used from the next spawn.
+    pub fn reconfigure(&mut self, from: Process) {
+        self.display = from.display;
+        self.kind = from.kind;
+        self.label = from.label;
+        self.note = from.note;
+        self.cmd = from.cmd;
+        self.reuse = from.reuse;
+        self.directory = from.directory;
+        self.restart_policy = from.restart_policy;
+        self.success_codes = from.success_codes;
+        self.thresholds = from.thresholds;
+        self.max_lifetime = from.max_lifetime;
+        self.start_timeout = from.start_timeout;
+        self.idle_timeout = from.idle_timeout;
+        self.dependencies = from.dependencies;
+        self.ansi = from.ansi;
+        self.merge_output = from.merge_output;
+        self.log_rate_limit = from.log_rate_limit;
+        self.post_start = from.post_start;
+        self.health_check = from.health_check;
+        self.ready_pattern = from.ready_pattern;
     }
 
-    pub fn kill(&mut self) {
+    /// Whether the process is part way through starting, stopping or
+    /// restarting.
+    pub fn is_changing(&self) -> bool {
+        matches!(
+            self.state,
+            ProcessState::Starting
+                | ProcessState::Killing(_)
+                | ProcessState::Stopped(ProcessRestart::RestartAt(_) | ProcessRestart::Respawn, _)
+        )
+    }
+
+    /// Whether there is (or is about to be) a live child for this process.
+    pub fn is_alive(&self) -> bool {
+        matches!(
+            self.state,
+            ProcessState::Starting | ProcessState::Running | ProcessState::Killing(_)
+        )
+    }
+
+    /// Write to the child's stdin. Returns false if stdin has been closed,
+    /// by us or by the child.
+    pub fn send_input(&self, bytes: Vec<u8>) -> bool {
+        self.input
+            .as_ref()
+            .is_some_and(|input| input.send(bytes).is_ok())
+    }
+
+    /// Close the child's stdin, as end of file.
+    pub fn close_input(&mut self) {
+        self.input = None;
+    }
+
+    /// Whether an exit status means the process completed rather than crashed.
+    pub fn is_clean_exit(&self, status: &ExitStatus) -> bool {
+        status.success()
+            || status
+                .code()
+                .is_some_and(|code| self.success_codes.contains(&code))
+    }
+
+    /// Kill the child, then follow `then` once it has died.
+    pub fn kill(&mut self, then: ProcessRestart) {
+        self.state = ProcessState::Killing(then);
         drop(self.closer.take());
     }
 
+    /// How close the restart policy is to giving up: a warning from half the
+    /// restarts used, critical once they all are. `None` if restarts are off,
+    /// and never more than normal if they are unlimited.
+    pub fn restart_alert(&self) -> Option<Alert> {
+        let policy = &self.restart_policy;
+        if !policy.enabled {
+            return None;
+        }
+        Some(if policy.unlimited() {
+            Alert::Normal
+        } else if self.restarts >= policy.max_restarts {
+            Alert::Critical
+        } else if self.restarts > 0 && self.restarts * 2 >= policy.max_restarts {
+            Alert::Warning
+        } else {
+            Alert::Normal
+        })
+    }
+
+    /// Whether the process has been running longer than `max_lifetime`.
+    pub fn has_outlived(&self, now: Instant) -> bool {
+        matches!(self.state, ProcessState::Running)
+            && self
+                .max_lifetime
+                .zip(self.last_start)
+                .is_some_and(|(lifetime, start)| now.duration_since(start) >= lifetime)
+    }
+
+    /// Whether the process is running or will be again without being asked.
+    pub fn is_up(&self) -> bool {
+        match self.state {
+            ProcessState::Killing(ProcessRestart::NoRestart) => self.expired.is_some(),
+            _ => self.is_alive() || self.is_changing(),
+        }
+    }
+
+    /// Stop while `dependency` restarts, calling off any restart of our own
+    /// until the manager starts us again.
+    pub fn hold_for(&mut self, dependency: &str) {
+        self.waiting_for = Some(dependency.to_string());
+        self.expired = None;
+        match &mut self.state {
+            ProcessState::Starting | ProcessState::Running => self.kill(ProcessRestart::NoRestart),
+            ProcessState::Killing(then) | ProcessState::Stopped(then, _) => {
+                *then = ProcessRestart::NoRestart
+            }
+            ProcessState::Completed(_) | ProcessState::Failed(_) => {}
+        }
+    }
+
+    /// Whether the process is still starting, or waiting for its first
+    /// passing health check, after `start_timeout`.
+    pub fn has_stalled(&self, now: Instant) -> bool {
+        let starting = match self.state {
+            ProcessState::Starting => true,
+            ProcessState::Running => self.health == Some(HealthStatus::Starting),
+            _ => false,
+        };
+        starting
+            && self
+                .start_timeout
+                .zip(self.last_start)
+                .is_some_and(|(timeout, start)| now.duration_since(start) >= timeout)
+    }
+
+    /// Whether the process is running but has written nothing, since it
+    /// started or since its latest line, for `idle_timeout`.
+    pub fn has_gone_quiet(&self, now: Instant) -> bool {
+        let heard = self.last_start.max(self.output.last());
+        matches!(self.state, ProcessState::Running)
+            && self
+                .idle_timeout
+                .zip(heard)
+                .is_some_and(|(timeout, heard)| now.duration_since(heard) >= timeout)
+    }
+
+    /// Stop a process that has outlived `max_lifetime`, stalled starting or
+    /// turned unhealthy. What happens next is up to the restart policy, as
+    /// for a crash.
+    pub fn expire(&mut self, why: Expiry) {
+        self.expired = Some(why);
+        self.kill(ProcessRestart::NoRestart);
+    }
+
+    /// Run the `post_start` hook in the background if the child has started
+    /// running, and is healthy if its health is known, and the hook hasn't
+    /// run for it yet.
+    pub fn run_post_start(&mut self) {
+        if self.post_started
+            || !matches!(self.state, ProcessState::Running)
+            || self.health.is_some_and(|h| h != HealthStatus::Healthy)
+        {
+            return;
+        }
+        let Some(hook) = &self.post_start else {
+            return;
+        };
+        self.post_started = true;
+        info!(target: &self.name, "Running post_start hook");
+        tokio::spawn(run_hook(self.name.clone(), hook.command()));
+    }
+
     pub fn push_stats(&mut self, stats: ProcessStats) {
+        self.check_thresholds(&stats);
         self.stats.push(stats);
-        self.stats_max.cpu_percent = self.stats_max.cpu_percent.max(stats.cpu_percent);
-        self.stats_max.memory_mb = self.stats_max.memory_mb.max(stats.memory_mb);
-        self.stats_max.uptime = self.stats_max.uptime.max(stats.uptime);
-        self.stats_max.timestamp = stats.timestamp;
-        self.state = ProcessState::Running;
+        stats::trim(&mut self.stats, stats.timestamp);
+        self.stats_max.raise_to(&stats);
+        if matches!(self.state, ProcessState::Starting) {
+            self.state = ProcessState::Running;
+        }
+    }
+
+    /// Log when CPU or RAM rises to a new alert level.
+    fn check_thresholds(&mut self, stats: &ProcessStats) {
+        let previous = self.stats.last();
+        let cpu = self.thresholds.cpu(stats.cpu_percent);
+        if cpu > Alert::Normal && previous.is_none_or(|p| self.thresholds.cpu(p.cpu_percent) < cpu)
+        {
+            warn!(target: &self.name, "CPU at {:.1}% ({:?})", stats.cpu_percent, cpu);
+        }
+        let memory = self.thresholds.memory(stats.memory_mb);
+        if memory > Alert::Normal
+            && previous.is_none_or(|p| self.thresholds.memory(p.memory_mb) < memory)
+        {
+            warn!(target: &self.name, "RAM at {:.1}MB ({:?})", stats.memory_mb, memory);
+        }
+        self.alert = self.thresholds.worst(stats);
+    }
+}
+
+async fn stdin_pump(name: String, mut stdin: ChildStdin, mut input: UnboundedReceiver<Vec<u8>>) {
+    while let Some(bytes) = input.recv().await {
+        let written = match stdin.write_all(&bytes).await {
+            Ok(()) => stdin.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            warn!(target: &name, "Can't write to stdin: {}", err);
+            break;
+        }
+    }
+    debug!(target: &name, "Stdin writer exiting");
+}
+
+/// Logs the lines a child writes to stdout or stderr.
+struct LogPump {
+    name: String,
+    /// "Stdout" or "Stderr".
+    stream: &'static str,
+    output: RecentOutput,
+    ansi: bool,
+    limit: Option<RateLimit>,
+    ready: Option<ReadyWatch>,
+}
+
+/// Watches a child's output for the line that shows it is ready, shared by
+/// its log pumps so only the first match counts.
+#[derive(Clone)]
+struct ReadyWatch {
+    pattern: Regex,
+    seen: Arc<AtomicBool>,
+    uuid: Uuid,
+    sender: UnboundedSender<Event>,
+}
+
+impl ReadyWatch {
+    /// Report the child healthy if `line` is the first to match.
+    fn check(&self, name: &str, line: &str) {
+        if self.pattern.is_match(line) && !self.seen.swap(true, Ordering::Relaxed) {
+            info!(target: name, "Ready, output matched `{}`", self.pattern);
+            let event = Event::App(AppEvent::HealthChanged(self.uuid, HealthStatus::Healthy));
+            let _ = self.sender.send(event);
+        }
     }
 }
 
-async fn stdout_log_pump(name: String, stdout: ChildStdout) {
-    let mut reader = BufReader::new(stdout).lines();
-    while let Some(line) = reader.next_line().await.unwrap() {
-        info!(target: &name, "{}", line);
+impl LogPump {
+    async fn run(self, pipe: impl AsyncRead + Unpin) {
+        let mut reader = BufReader::new(pipe).lines();
+        loop {
+            // Wake up now and then to report suppressed lines even if the
+            // child has gone quiet.
+            let next = tokio::time::timeout(Duration::from_secs(1), reader.next_line()).await;
+            self.report_suppressed();
+            let mut line = match next {
+                Ok(Ok(Some(line))) => line,
+                Ok(_) => break,
+                Err(_) => continue,
+            };
+            // Readiness counts even for lines the rate limit drops, and
+            // colour doesn't get in the way of a match.
+            if let Some(ready) = &self.ready {
+                ready.check(&self.name, &ansi::strip(&line));
+            }
+            if self
+                .limit
+                .as_ref()
+                .is_some_and(|l| !l.admit(Instant::now()))
+            {
+                continue;
+            }
+            if self.ansi {
+                line = ansi::strip(&line);
+            }
+            info!(target: &self.name, "{}", line);
+            self.output.push(line);
+        }
+        debug!(target: &self.name, "{} reader exiting", self.stream);
+    }
+
+    fn report_suppressed(&self) {
+        let suppressed = self
+            .limit
+            .as_ref()
+            .and_then(|l| l.take_suppressed(Instant::now()));
+        if let Some(count) = suppressed {
+            let line = format!("[{count} lines suppressed]");
+            warn!(target: &self.name, "{}", line);
+            self.output.push(line);
+        }
     }
-    debug!(target: &name, "Stdout reader exiting");
 }
 
-async fn stderr_log_pump(name: String, stderr: ChildStderr) {
-    let mut reader = BufReader::new(stderr).lines();
-    while let Some(line) = reader.next_line().await.unwrap() {
-        info!(target: &name, "{}", line);
+/// Run a hook to completion, logging its output, and warn if it fails.
+async fn run_hook(name: String, mut cmd: Command) {
+    cmd.stdin(Stdio::null());
+    let output = match cmd.output().await {
+        Ok(output) => output,
+        Err(err) => {
+            warn!(target: &name, "Can't run post_start hook: {}", err);
+            return;
+        }
+    };
+    for stream in [&output.stdout, &output.stderr] {
+        for line in String::from_utf8_lossy(stream).lines() {
+            info!(target: &name, "post_start: {}", line);
+        }
+    }
+    if !output.status.success() {
+        warn!(target: &name, "post_start hook failed: {}", exit_reason(&output.status));
     }
-    debug!(target: &name, "Stderr reader exiting");
 }
 
 async fn death_handler(
@@ -225,20 +1145,285 @@ async fn death_handler(
     mut closed: oneshot::Sender<()>,
     sender: UnboundedSender<Event>,
     mut child: tokio::process::Child,
+    tree: ProcessTree,
 ) {
-    loop {
-        select! {
-            status = child.wait() => {
-                info!(target: &name, "Process exit {:?}", status);
-                sender.send(Event::App(AppEvent::ProcessDied(uuid, status.unwrap()))).expect("sending process died message");
-                return;
+    let status = select! {
+        status = child.wait() => status,
+        _ = closed.closed() => {
+            info!(target: &name, "Process kill...");
+            tree.terminate(&name, &mut child).await;
+            child.wait().await
+        }
+    };
+    info!(target: &name, "Process exit {:?}", status);
+    // The app has already gone if nobody is receiving.
+    if sender
+        .send(Event::App(AppEvent::ProcessDied(uuid, status.unwrap())))
+        .is_err()
+    {
+        debug!(target: &name, "Nobody to tell about process exit");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_output_keeps_the_latest_lines() {
+        let output = RecentOutput::default();
+        for i in 0..RECENT_OUTPUT_LINES + 3 {
+            output.push(i.to_string());
+        }
+        let lines = output.lines();
+        assert_eq!(lines.len(), RECENT_OUTPUT_LINES);
+        assert_eq!(lines.first().unwrap(), "3");
+        assert_eq!(
+            lines.last().unwrap(),
+            &(RECENT_OUTPUT_LINES + 2).to_string()
+        );
+        assert_eq!(output.total(), RECENT_OUTPUT_LINES as u64 + 3);
+    }
+
+    #[test]
+    fn restart_alert_rises_towards_the_limit() {
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("./api".to_string()),
+            ..Default::default()
+        };
+        let mut process = Process::new(&svc).unwrap();
+        assert_eq!(process.restart_alert(), None);
+        process.restart_policy = RestartPolicy {
+            enabled: true,
+            cooloff: 0,
+            max_restarts: 4,
+        };
+        let alerts: Vec<_> = (0..=4)
+            .map(|restarts| {
+                process.restarts = restarts;
+                process.restart_alert().unwrap()
+            })
+            .collect();
+        use Alert::*;
+        assert_eq!(alerts, [Normal, Normal, Warning, Warning, Critical]);
+    }
+
+    #[test]
+    fn kinds_are_counted_in_the_plural() {
+        assert_eq!(ProcessKind::Service.count(1), "1 service");
+        assert_eq!(ProcessKind::Stub.count(0), "0 stubs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_reasons_name_codes_and_signals() {
+        use std::os::unix::process::ExitStatusExt;
+        let stopped =
+            |raw| ProcessState::Stopped(ProcessRestart::Respawn, ExitStatus::from_raw(raw));
+        assert_eq!(stopped(1 << 8).exit_reason().as_deref(), Some("exit 1"));
+        assert_eq!(stopped(9).exit_reason().as_deref(), Some("SIGKILL"));
+        assert_eq!(stopped(10).exit_reason().as_deref(), Some("signal 10"));
+        assert_eq!(ProcessState::Running.exit_reason(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn post_start_waits_until_healthy() {
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("sleep 1".to_string()),
+            post_start: Some("true".to_string()),
+            ready_log_pattern: Some("ready".to_string()),
+            ..Default::default()
+        };
+        let mut process = Process::new(&svc).unwrap();
+        let (sender, _) = mpsc::unbounded_channel();
+        process.spawn(sender).unwrap();
+        process.state = ProcessState::Running;
+        process.run_post_start();
+        assert!(!process.post_started);
+        process.health = Some(HealthStatus::Healthy);
+        process.run_post_start();
+        assert!(process.post_started);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn post_start_runs_once_per_start() {
+        let file = std::env::temp_dir().join(format!("procli-post-start-{}", Uuid::new_v4()));
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("sleep 1".to_string()),
+            post_start: Some(format!("echo ran >> {}", file.display())),
+            ..Default::default()
+        };
+        let mut process = Process::new(&svc).unwrap();
+        let (sender, _) = mpsc::unbounded_channel();
+        process.run_post_start();
+        for _ in 0..2 {
+            process.spawn(sender.clone()).unwrap();
+            process.run_post_start();
+            process.state = ProcessState::Running;
+            process.run_post_start();
+            process.run_post_start();
+        }
+        let runs = || {
+            std::fs::read_to_string(&file)
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        for _ in 0..50 {
+            if runs() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs(), 2);
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn holding_calls_off_a_pending_restart() {
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("./api".to_string()),
+            ..Default::default()
+        };
+        let mut process = Process::new(&svc).unwrap();
+        let status = std::process::Command::new("true").status().unwrap();
+        process.state = ProcessState::Stopped(ProcessRestart::RestartAt(Instant::now()), status);
+        assert!(process.is_up());
+        process.hold_for("db");
+        assert!(matches!(
+            process.state,
+            ProcessState::Stopped(ProcessRestart::NoRestart, _)
+        ));
+        assert_eq!(process.waiting_for.as_deref(), Some("db"));
+        assert!(!process.is_up());
+    }
+
+    #[test]
+    fn labels_default_by_kind() {
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("./api".to_string()),
+            ..Default::default()
+        };
+        let stub = Stub {
+            name: "payments".to_string(),
+            command: Some("./stub".to_string()),
+            label: Some(String::new()),
+            ..Default::default()
+        };
+        let svc = Process::new(&svc).unwrap();
+        let stub = Process::new(&stub).unwrap();
+        assert_eq!(
+            (svc.kind, svc.label.as_str()),
+            (ProcessKind::Service, "SVC")
+        );
+        assert_eq!((stub.kind, stub.label.as_str()), (ProcessKind::Stub, ""));
+    }
+
+    #[test]
+    fn image_processes_keep_their_directory() {
+        let svc = Service {
+            name: "web".to_string(),
+            image: Some("nginx".to_string()),
+            directory: Some("site".to_string()),
+            ..Default::default()
+        };
+        let process = Process::new(&svc).unwrap();
+        assert_eq!(process.directory, Some(absolute("site").unwrap()));
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn input_is_written_to_stdin() {
+        let svc = Service {
+            name: "cat".to_string(),
+            command: Some("cat".to_string()),
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut process = Process::new(&svc).unwrap();
+        process.spawn(sender).unwrap();
+        assert!(process.send_input(b"hello\n".to_vec()));
+        process.close_input();
+        assert!(!process.send_input(b"more\n".to_vec()));
+        while !matches!(
+            receiver.recv().await,
+            Some(Event::App(AppEvent::ProcessDied(..)))
+        ) {}
+        for _ in 0..100 {
+            if !process.output.lines().is_empty() {
+                break;
             }
-            _ = closed.closed() => {
-                info!(target: &name, "Process kill...");
-                if let Err(err) = child.start_kill() {
-                    error!("Can't kill process {}: {}", name, err);
-                }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(process.output.lines(), ["hello"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn merged_output_keeps_the_order_lines_were_written() {
+        let svc = Service {
+            name: "merged".to_string(),
+            command: Some("for i in 1 2 3 4 5; do echo out$i; echo err$i >&2; done".to_string()),
+            shell: true,
+            merge_output: true,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut process = Process::new(&svc).unwrap();
+        process.spawn(sender).unwrap();
+        while !matches!(
+            receiver.recv().await,
+            Some(Event::App(AppEvent::ProcessDied(..)))
+        ) {}
+        for _ in 0..100 {
+            if process.output.lines().len() == 10 {
+                break;
             }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
+        let expected: Vec<String> = (1..=5)
+            .flat_map(|i| [format!("out{i}"), format!("err{i}")])
+            .collect();
+        assert_eq!(process.output.lines(), expected);
+        // The pipe is closed once the child has gone, so the reader finishes.
+        let [pump] = std::mem::take(&mut process.log_pumps).try_into().unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), pump)
+            .await
+            .expect("merged output ended")
+            .unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn respawn_stops_the_previous_log_pumps() {
+        // The background sleep keeps stdout open after the shell exits.
+        let svc = Service {
+            name: "pumps".to_string(),
+            command: Some("sleep 2 & echo started".to_string()),
+            shell: true,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut process = Process::new(&svc).unwrap();
+        process.spawn(sender.clone()).unwrap();
+        let first: Vec<_> = process.log_pumps.iter().map(|t| t.abort_handle()).collect();
+        while !matches!(
+            receiver.recv().await,
+            Some(Event::App(AppEvent::ProcessDied(..)))
+        ) {}
+        assert!(!first[0].is_finished(), "stdout still held open by sleep");
+
+        process.spawn(sender).unwrap();
+        tokio::task::yield_now().await;
+        assert!(first.iter().all(|t| t.is_finished()));
     }
 }
@@ -0,0 +1,90 @@
+//! Limiting how many output lines a process may log each second.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Lets through at most `per_second` lines in each one second window and
+/// counts the rest. Shared by a process's stdout and stderr pumps.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    per_second: u32,
+    window: Arc<Mutex<Window>>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    passed: u32,
+    /// Lines dropped and not yet reported.
+    suppressed: u64,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32, now: Instant) -> Self {
+        Self {
+            per_second,
+            window: Arc::new(Mutex::new(Window {
+                start: now,
+                passed: 0,
+                suppressed: 0,
+            })),
+        }
+    }
+
+    /// Whether a line arriving `now` should be logged. Counts it as
+    /// suppressed if not.
+    pub fn admit(&self, now: Instant) -> bool {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(window.start) >= Duration::from_secs(1) {
+            window.start = now;
+            window.passed = 0;
+        }
+        if window.passed < self.per_second {
+            window.passed += 1;
+            true
+        } else {
+            window.suppressed += 1;
+            false
+        }
+    }
+
+    /// How many lines were dropped, once the window they were dropped in is
+    /// over. Each is only reported once.
+    pub fn take_suppressed(&self, now: Instant) -> Option<u64> {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.suppressed == 0 || now.duration_since(window.start) < Duration::from_secs(1) {
+            return None;
+        }
+        Some(std::mem::take(&mut window.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_over_the_limit_are_counted_and_reported_once() {
+        let start = Instant::now();
+        let limit = RateLimit::new(3, start);
+        let passed: Vec<bool> = (0..5).map(|_| limit.admit(start)).collect();
+        assert_eq!(passed, [true, true, true, false, false]);
+        assert_eq!(limit.take_suppressed(start), None);
+
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(limit.take_suppressed(later), Some(2));
+        assert_eq!(limit.take_suppressed(later), None);
+        assert!(limit.admit(later));
+    }
+
+    #[test]
+    fn each_window_starts_afresh() {
+        let start = Instant::now();
+        let limit = RateLimit::new(1, start);
+        assert!(limit.admit(start));
+        assert!(!limit.admit(start + Duration::from_millis(999)));
+        assert!(limit.admit(start + Duration::from_secs(1)));
+    }
+}
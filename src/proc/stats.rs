@@ -1,5 +1,9 @@
+use crate::config::CpuMode;
 use std::time::{Duration, Instant};
 
+/// How much stats history is kept: the widest window any chart can show.
+pub const HISTORY_KEPT: Duration = Duration::from_secs(1800);
+
 #[derive(Debug, Clone, Copy)]
 pub struct ProcessStats {
     pub timestamp: Instant,
@@ -8,10 +12,15 @@ pub struct ProcessStats {
     pub uptime: Duration,
 }
 impl ProcessStats {
-    pub(crate) fn new(timestamp: Instant, info: &sysinfo::Process) -> Self {
+    pub(crate) fn new(
+        timestamp: Instant,
+        info: &sysinfo::Process,
+        mode: CpuMode,
+        cores: usize,
+    ) -> Self {
         Self {
             timestamp,
-            cpu_percent: info.cpu_usage(),
+            cpu_percent: cpu_percent(info.cpu_usage(), mode, cores),
             memory_mb: info.memory() as f32 / 1_000_000.0,
             uptime: Duration::from_secs(info.run_time()),
         }
@@ -28,3 +37,181 @@ impl Default for ProcessStats {
         }
     }
 }
+
+impl ProcessStats {
+    /// Combined CPU and RAM of several processes; uptime is the longest.
+    pub fn total<'a>(timestamp: Instant, stats: impl Iterator<Item = &'a ProcessStats>) -> Self {
+        stats.fold(
+            Self {
+                timestamp,
+                ..Default::default()
+            },
+            |total, s| Self {
+                timestamp,
+                cpu_percent: total.cpu_percent + s.cpu_percent,
+                memory_mb: total.memory_mb + s.memory_mb,
+                uptime: total.uptime.max(s.uptime),
+            },
+        )
+    }
+
+    /// Raise each stat to at least the value in `other`, taking its timestamp.
+    pub fn raise_to(&mut self, other: &ProcessStats) {
+        self.cpu_percent = self.cpu_percent.max(other.cpu_percent);
+        self.memory_mb = self.memory_mb.max(other.memory_mb);
+        self.uptime = self.uptime.max(other.uptime);
+        self.timestamp = other.timestamp;
+    }
+}
+
+/// Drop the samples in `history` more than [`HISTORY_KEPT`] older than `now`.
+pub fn trim(history: &mut Vec<ProcessStats>, now: Instant) {
+    let stale = history.partition_point(|s| now.duration_since(s.timestamp) > HISTORY_KEPT);
+    history.drain(..stale);
+}
+
+/// CPU use as sysinfo reports it (summed across cores) counted per `mode`.
+pub fn cpu_percent(usage: f32, mode: CpuMode, cores: usize) -> f32 {
+    match mode {
+        CpuMode::Total => usage,
+        CpuMode::Normalized => usage / cores.max(1) as f32,
+    }
+}
+
+/// The number of cores processes can run on, for normalizing CPU use.
+pub fn cores() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// How far a stat is above its configured thresholds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Alert {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+/// Warning and critical levels for CPU and RAM; unset levels never alert.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub cpu_warn: Option<f32>,
+    pub cpu_crit: Option<f32>,
+    pub mem_warn: Option<f32>,
+    pub mem_crit: Option<f32>,
+}
+
+impl Thresholds {
+    pub fn cpu(&self, cpu_percent: f32) -> Alert {
+        alert(cpu_percent, self.cpu_warn, self.cpu_crit)
+    }
+
+    pub fn memory(&self, memory_mb: f32) -> Alert {
+        alert(memory_mb, self.mem_warn, self.mem_crit)
+    }
+
+    /// The worst alert for a set of stats.
+    pub fn worst(&self, stats: &ProcessStats) -> Alert {
+        self.cpu(stats.cpu_percent)
+            .max(self.memory(stats.memory_mb))
+    }
+}
+
+fn alert(value: f32, warn: Option<f32>, crit: Option<f32>) -> Alert {
+    if crit.is_some_and(|crit| value > crit) {
+        Alert::Critical
+    } else if warn.is_some_and(|warn| value > warn) {
+        Alert::Warning
+    } else {
+        Alert::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            cpu_warn: Some(50.0),
+            cpu_crit: Some(90.0),
+            mem_warn: None,
+            mem_crit: Some(512.0),
+        }
+    }
+
+    #[test]
+    fn alerts_rise_with_the_value() {
+        let t = thresholds();
+        assert_eq!(t.cpu(10.0), Alert::Normal);
+        assert_eq!(t.cpu(50.0), Alert::Normal);
+        assert_eq!(t.cpu(75.0), Alert::Warning);
+        assert_eq!(t.cpu(95.0), Alert::Critical);
+    }
+
+    #[test]
+    fn unset_thresholds_never_alert() {
+        assert_eq!(Thresholds::default().cpu(1000.0), Alert::Normal);
+        assert_eq!(thresholds().memory(500.0), Alert::Normal);
+        assert_eq!(thresholds().memory(600.0), Alert::Critical);
+    }
+
+    #[test]
+    fn totals_add_up_cpu_and_memory() {
+        let now = Instant::now();
+        let stats = [
+            ProcessStats {
+                cpu_percent: 10.0,
+                memory_mb: 100.0,
+                uptime: Duration::from_secs(5),
+                ..Default::default()
+            },
+            ProcessStats {
+                cpu_percent: 2.5,
+                memory_mb: 20.0,
+                uptime: Duration::from_secs(50),
+                ..Default::default()
+            },
+        ];
+        let total = ProcessStats::total(now, stats.iter());
+        assert_eq!(total.timestamp, now);
+        assert_eq!(total.cpu_percent, 12.5);
+        assert_eq!(total.memory_mb, 120.0);
+        assert_eq!(total.uptime, Duration::from_secs(50));
+    }
+
+    #[test]
+    fn trim_drops_samples_past_the_kept_history() {
+        let now = Instant::now() + HISTORY_KEPT * 2;
+        let at = |age: Duration| ProcessStats {
+            timestamp: now - age,
+            ..Default::default()
+        };
+        let mut history = vec![
+            at(HISTORY_KEPT * 2),
+            at(HISTORY_KEPT + Duration::from_secs(1)),
+            at(HISTORY_KEPT),
+            at(Duration::ZERO),
+        ];
+        trim(&mut history, now);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].timestamp, now - HISTORY_KEPT);
+    }
+
+    #[test]
+    fn normalized_cpu_is_divided_by_cores() {
+        assert_eq!(cpu_percent(350.0, CpuMode::Total, 4), 350.0);
+        assert_eq!(cpu_percent(350.0, CpuMode::Normalized, 4), 87.5);
+        assert_eq!(cpu_percent(50.0, CpuMode::Normalized, 0), 50.0);
+    }
+
+    #[test]
+    fn worst_alert_wins() {
+        let stats = ProcessStats {
+            cpu_percent: 60.0,
+            memory_mb: 1024.0,
+            ..Default::default()
+        };
+        assert_eq!(thresholds().worst(&stats), Alert::Critical);
+    }
+}
@@ -0,0 +1,148 @@
+//! Push process metrics to a StatsD agent, in DogStatsD format.
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use log::*;
+use tokio::{
+    net::{UdpSocket, lookup_host},
+    sync::mpsc::{self, UnboundedSender},
+};
+
+/// One process's metrics for a single stats refresh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample<'a> {
+    pub service: &'a str,
+    /// None when the process had no fresh stats, as while it is restarting.
+    pub cpu_percent: Option<f32>,
+    pub memory_mb: Option<f32>,
+    /// Restarts since the previous sample was sent.
+    pub restarts: u32,
+}
+
+/// Format samples as DogStatsD lines, tagged by service name. Gauges are left
+/// out when there are no stats, and restart counters when there is nothing to
+/// count.
+pub fn format(samples: &[Sample]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for sample in samples {
+        let tag = format!("#service:{}", sample.service);
+        if let Some(cpu_percent) = sample.cpu_percent {
+            lines.push(format!("procli.cpu:{}|g|{}", cpu_percent, tag));
+        }
+        if let Some(memory_mb) = sample.memory_mb {
+            lines.push(format!("procli.mem:{}|g|{}", memory_mb, tag));
+        }
+        if sample.restarts > 0 {
+            lines.push(format!("procli.restarts:{}|c|{}", sample.restarts, tag));
+        }
+    }
+    lines
+}
+
+/// Start a task that sends each batch of lines to `addr` as one datagram.
+/// The task stops when the returned sender is dropped.
+pub fn spawn(addr: String) -> UnboundedSender<Vec<String>> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<String>>();
+    tokio::spawn(async move {
+        let socket = match connect(&addr).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                error!(target: "App", "Can't send metrics to StatsD at {}: {}", addr, err);
+                return;
+            }
+        };
+        info!(target: "App", "Sending metrics to StatsD at {}", addr);
+        while let Some(lines) = receiver.recv().await {
+            if lines.is_empty() {
+                continue;
+            }
+            if let Err(err) = socket.send(lines.join("\n").as_bytes()).await {
+                debug!(target: "App", "Failed to send metrics to {}: {}", addr, err);
+            }
+        }
+        debug!("StatsD sender exiting");
+    });
+    sender
+}
+
+/// A socket connected to `addr`, bound to a local address of the same family.
+async fn connect(addr: &str) -> io::Result<UdpSocket> {
+    let target = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    let local = match target {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gauges_and_counters_are_tagged_by_service() {
+        let samples = [
+            Sample {
+                service: "api",
+                cpu_percent: Some(12.5),
+                memory_mb: Some(80.0),
+                restarts: 2,
+            },
+            Sample {
+                service: "web",
+                cpu_percent: Some(0.0),
+                memory_mb: Some(1.5),
+                restarts: 0,
+            },
+            Sample {
+                service: "worker",
+                cpu_percent: None,
+                memory_mb: None,
+                restarts: 1,
+            },
+        ];
+        assert_eq!(
+            format(&samples),
+            [
+                "procli.cpu:12.5|g|#service:api",
+                "procli.mem:80|g|#service:api",
+                "procli.restarts:2|c|#service:api",
+                "procli.cpu:0|g|#service:web",
+                "procli.mem:1.5|g|#service:web",
+                "procli.restarts:1|c|#service:worker",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_are_sent_as_one_datagram() {
+        let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = spawn(agent.local_addr().unwrap().to_string());
+        sender
+            .send(vec!["a:1|g".to_string(), "b:2|g".to_string()])
+            .unwrap();
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"a:1|g\nb:2|g");
+    }
+
+    #[tokio::test]
+    async fn ipv6_agents_are_reachable() {
+        let Ok(agent) = UdpSocket::bind("[::1]:0").await else {
+            return;
+        };
+        let sender = spawn(agent.local_addr().unwrap().to_string());
+        sender.send(vec!["a:1|g".to_string()]).unwrap();
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"a:1|g");
+    }
+}
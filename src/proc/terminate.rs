@@ -0,0 +1,161 @@
+//! Platform specific parts of stopping a child process and everything it started.
+//!
+//! On Unix the child is simply killed. On Windows each child is started in its
+//! own process group and placed in a job object: stopping sends `CTRL_BREAK`
+//! to the group first, then terminates the whole job if the child hasn't exited
+//! within [`GRACEFUL_STOP`]. Closing the job also kills anything left behind.
+
+use std::time::Duration;
+
+use log::*;
+use tokio::process::{Child, Command};
+
+/// How long a child gets to exit after a graceful stop request.
+pub const GRACEFUL_STOP: Duration = Duration::from_secs(5);
+
+/// Prepare a command so its process tree can be stopped later.
+pub fn configure(cmd: &mut Command) {
+    #[cfg(windows)]
+    cmd.creation_flags(windows::CREATE_NEW_PROCESS_GROUP);
+    #[cfg(not(windows))]
+    let _ = cmd;
+}
+
+/// Handle on a spawned child and the processes it starts.
+#[derive(Debug)]
+pub struct ProcessTree {
+    #[cfg(windows)]
+    job: Option<windows::Job>,
+}
+
+impl ProcessTree {
+    /// Take charge of a freshly spawned child.
+    pub fn adopt(name: &str, child: &Child) -> Self {
+        #[cfg(windows)]
+        {
+            let job = windows::Job::new().and_then(|job| job.assign(child).map(|_| job));
+            if let Err(err) = &job {
+                warn!(target: name, "Can't create job object, children may outlive the process: {}", err);
+            }
+            Self { job: job.ok() }
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = (name, child);
+            Self {}
+        }
+    }
+
+    /// Stop the child, returning once it has been asked or forced to exit.
+    pub async fn terminate(&self, name: &str, child: &mut Child) {
+        #[cfg(windows)]
+        {
+            if let Some(pid) = child.id() {
+                match windows::ctrl_break(pid) {
+                    Ok(()) => {
+                        if tokio::time::timeout(GRACEFUL_STOP, child.wait())
+                            .await
+                            .is_ok()
+                        {
+                            return;
+                        }
+                        warn!(target: name, "Process ignored CTRL_BREAK, terminating");
+                    }
+                    Err(err) => debug!(target: name, "Can't send CTRL_BREAK: {}", err),
+                }
+            }
+            if let Some(job) = &self.job {
+                match job.terminate() {
+                    Ok(()) => return,
+                    Err(err) => error!("Can't terminate job for process {}: {}", name, err),
+                }
+            }
+        }
+        if let Err(err) = child.start_kill() {
+            error!("Can't kill process {}: {}", name, err);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{ffi::c_void, io, mem, ptr};
+
+    use tokio::process::Child;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        System::{
+            Console::{CTRL_BREAK_EVENT, GenerateConsoleCtrlEvent},
+            JobObjects::{
+                AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+                JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+                SetInformationJobObject, TerminateJobObject,
+            },
+        },
+    };
+
+    pub use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+
+    /// A job object that kills its processes when the last handle closes.
+    #[derive(Debug)]
+    pub struct Job(HANDLE);
+
+    // The handle is only used through thread-safe Win32 calls.
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        pub fn new() -> io::Result<Self> {
+            let handle = unsafe { CreateJobObjectW(ptr::null(), ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Self(handle);
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { mem::zeroed() };
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let ok = unsafe {
+                SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const c_void,
+                    mem::size_of_val(&info) as u32,
+                )
+            };
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+
+        pub fn assign(&self, child: &Child) -> io::Result<()> {
+            let process = child
+                .raw_handle()
+                .ok_or_else(|| io::Error::other("process has already exited"))?;
+            if unsafe { AssignProcessToJobObject(self.0, process as HANDLE) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn terminate(&self) -> io::Result<()> {
+            if unsafe { TerminateJobObject(self.0, 1) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    /// Ask the process group led by `pid` to stop.
+    pub fn ctrl_break(pid: u32) -> io::Result<()> {
+        if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
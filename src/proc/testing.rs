@@ -0,0 +1,154 @@
+//! Helpers for driving a [`ProcessManager`] in tests.
+//!
+//! Processes run real but tiny shell commands. The events the app loop would
+//! hand the manager are pumped by the test instead, so deaths, ticks and
+//! restarts happen in the order the test says.
+
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use uuid::Uuid;
+
+use crate::{
+    config::{RestartPolicy, Service},
+    event::{AppEvent, Event},
+    proc::{
+        manager::ProcessManager,
+        process::{Process, ProcessState},
+    },
+};
+
+/// Longest a test waits for something to happen before giving up.
+const PATIENCE: Duration = Duration::from_secs(5);
+
+/// A service running `command` through the shell.
+pub fn service(name: &str, command: &str) -> Service {
+    Service {
+        name: name.to_string(),
+        command: Some(command.to_string()),
+        shell: true,
+        ..Default::default()
+    }
+}
+
+/// A service running `sleep 1` directly, long enough to be caught alive.
+pub fn sleeper() -> Service {
+    Service {
+        name: "sleeper".to_string(),
+        command: Some("sleep 1".to_string()),
+        ..Default::default()
+    }
+}
+
+/// How a child that exited with `code` is reported.
+pub fn exit(code: i32) -> ExitStatus {
+    ExitStatus::from_raw(code << 8)
+}
+
+/// A manager with one process for `svc`, not actually spawned, in the given
+/// state.
+pub fn manager_with(svc: Service, state: ProcessState) -> (ProcessManager, Uuid) {
+    let (sender, _) = mpsc::unbounded_channel();
+    let mut manager = ProcessManager::new(sender);
+    let mut process = Process::new(&svc).unwrap();
+    let uuid = Uuid::new_v4();
+    process.uuid = uuid;
+    process.state = state;
+    manager.processes.push(process);
+    (manager, uuid)
+}
+
+/// `svc`, restarted straight away up to `max_restarts` times.
+pub fn restarting(svc: Service, max_restarts: u32) -> Service {
+    Service {
+        restart: Some(RestartPolicy {
+            enabled: true,
+            cooloff: 0,
+            max_restarts,
+        }),
+        ..svc
+    }
+}
+
+/// A manager along with the events it sends, for the test to hand back.
+pub struct Harness {
+    pub manager: ProcessManager,
+    events: UnboundedReceiver<Event>,
+}
+
+impl Harness {
+    /// A manager with no processes, and no floor on how soon a process may
+    /// be restarted.
+    pub fn new() -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut manager = ProcessManager::new(sender);
+        manager.min_restart_interval = Duration::ZERO;
+        Self { manager, events }
+    }
+
+    /// Define and start a process for `svc`.
+    pub fn upsert(&mut self, svc: &Service) -> Uuid {
+        self.manager.upsert(svc).unwrap()
+    }
+
+    pub fn process(&self, name: &str) -> &Process {
+        self.manager
+            .processes
+            .iter()
+            .find(|p| p.name == name)
+            .unwrap_or_else(|| panic!("no process `{name}`"))
+    }
+
+    /// Hand the manager whatever events are already waiting, as the app
+    /// loop would.
+    pub fn pump(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+    }
+
+    /// Wait for the next child to die and hand that to the manager, along
+    /// with anything else that came first.
+    pub async fn next_death(&mut self) -> (Uuid, ExitStatus) {
+        let died = async {
+            loop {
+                match self.events.recv().await.expect("manager gone") {
+                    Event::App(AppEvent::ProcessDied(id, status)) => return (id, status),
+                    event => self.handle(event),
+                }
+            }
+        };
+        let (id, status) = tokio::time::timeout(PATIENCE, died)
+            .await
+            .expect("no process died");
+        self.manager.process_died(id, status);
+        (id, status)
+    }
+
+    /// Pump events and tick until `done` holds.
+    pub async fn until(&mut self, done: impl Fn(&ProcessManager) -> bool) {
+        let settled = async {
+            while !done(&self.manager) {
+                self.pump();
+                self.manager.tick();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(PATIENCE, settled)
+            .await
+            .expect("gave up waiting");
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::App(AppEvent::ProcessDied(id, status)) => self.manager.process_died(id, status),
+            Event::App(AppEvent::HealthChanged(id, status)) => {
+                self.manager.health_changed(id, status)
+            }
+            Event::App(AppEvent::ContainerChosen(id, launch)) => {
+                self.manager.container_chosen(id, launch)
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,5 +1,20 @@
 use std::time::Instant;
 
+/// How the samples falling in a bin are combined into a single value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleStrategy {
+    /// The largest sample in the bin.
+    #[default]
+    Max,
+    /// The arithmetic mean of the samples in the bin.
+    Mean,
+    /// The mean of the samples in the bin, each weighted by the time it covers.
+    ///
+    /// A sample covers the time since the previous sample (or since the start
+    /// of the bin if it is the first sample), clipped to the bin.
+    TimeWeightedMean,
+}
+
 /// Resample a series of samples taken at irregular time intervals into a fixed number of bins.
 /// Use a dumb 'max' strategy that simply takes the maximum value in each bin.
 pub fn resample(
@@ -9,6 +24,43 @@ pub fn resample(
     end: Instant,
     num_bins: usize,
 ) -> Vec<Option<f32>> {
+    resample_with(
+        ResampleStrategy::Max,
+        samples,
+        time_samples,
+        start,
+        end,
+        num_bins,
+    )
+}
+
+/// Resample a series of samples taken at irregular time intervals into a fixed number of bins,
+/// combining the samples in each bin with the given strategy.
+pub fn resample_with(
+    strategy: ResampleStrategy,
+    samples: &[f32],
+    time_samples: &[Instant],
+    start: Instant,
+    end: Instant,
+    num_bins: usize,
+) -> Vec<Option<f32>> {
+    resample_detailed(strategy, samples, time_samples, start, end, num_bins)
+        .into_iter()
+        .map(|bin| bin.map(|(value, _)| value))
+        .collect()
+}
+
+/// Like [`resample_with`], but alongside each bin's value gives the number of
+/// samples that fell in it, so a bin resting on a single sample can be told
+/// apart from one averaged over many.
+pub fn resample_detailed(
+    strategy: ResampleStrategy,
+    samples: &[f32],
+    time_samples: &[Instant],
+    start: Instant,
+    end: Instant,
+    num_bins: usize,
+) -> Vec<Option<(f32, usize)>> {
     if samples.is_empty() || time_samples.is_empty() || num_bins == 0 {
         return Vec::new();
     }
@@ -17,7 +69,7 @@ pub fn resample(
         panic!("samples and time_samples must have the same length");
     }
 
-    let mut result: Vec<Option<f32>> = vec![None; num_bins];
+    let mut result: Vec<Option<(f32, usize)>> = vec![None; num_bins];
     let total_duration = end.duration_since(start);
     let bin_duration = total_duration / num_bins as u32;
 
@@ -25,19 +77,67 @@ pub fn resample(
         let bin_start = start + bin_duration * i as u32;
         let bin_end = bin_start + bin_duration;
 
-        for (j, &time_sample) in time_samples.iter().enumerate() {
-            if time_sample > bin_start && time_sample <= bin_end {
-                let sample_value = samples[j];
-                *r = match *r {
-                    Some(current_max) => Some(current_max.max(sample_value)),
-                    None => Some(sample_value),
-                };
+        let in_bin: Vec<usize> = time_samples
+            .iter()
+            .enumerate()
+            .filter(|&(_, &t)| t > bin_start && t <= bin_end)
+            .map(|(j, _)| j)
+            .collect();
+        let count = in_bin.len();
+        let in_bin = in_bin.into_iter();
+        let value = match strategy {
+            ResampleStrategy::Max => in_bin.map(|j| samples[j]).reduce(f32::max),
+            ResampleStrategy::Mean => mean(in_bin.map(|j| (samples[j], 1.0))),
+            ResampleStrategy::TimeWeightedMean => {
+                let weighted: Vec<(f32, f64)> = in_bin
+                    .map(|j| {
+                        let from = match j.checked_sub(1) {
+                            Some(prev) => time_samples[prev].max(bin_start),
+                            None => bin_start,
+                        };
+                        let covered = time_samples[j].saturating_duration_since(from);
+                        (samples[j], covered.as_secs_f64())
+                    })
+                    .collect();
+                // Coincident samples cover no time, so fall back to treating them equally.
+                if weighted.iter().any(|&(_, w)| w > 0.0) {
+                    mean(weighted.into_iter())
+                } else {
+                    mean(weighted.into_iter().map(|(v, _)| (v, 1.0)))
+                }
             }
-        }
+        };
+        *r = value.map(|v| (v, count));
     }
     result
 }
 
+/// Fill runs of empty bins by linear interpolation between the known bins on
+/// either side. Leading and trailing empty bins are left empty.
+pub fn interpolate_gaps(bins: &mut [Option<f32>]) {
+    let mut last_known: Option<(usize, f32)> = None;
+    for i in 0..bins.len() {
+        let Some(value) = bins[i] else { continue };
+        if let Some((j, prev)) = last_known
+            && i - j > 1
+        {
+            let step = (value - prev) / (i - j) as f32;
+            for (k, bin) in bins.iter_mut().enumerate().take(i).skip(j + 1) {
+                *bin = Some(prev + step * (k - j) as f32);
+            }
+        }
+        last_known = Some((i, value));
+    }
+}
+
+/// Weighted mean of `(value, weight)` pairs, or `None` if there are none.
+fn mean(values: impl Iterator<Item = (f32, f64)>) -> Option<f32> {
+    let (sum, weight) = values.fold((0.0, 0.0), |(sum, weight), (v, w)| {
+        (sum + v as f64 * w, weight + w)
+    });
+    (weight > 0.0).then(|| (sum / weight) as f32)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -193,4 +293,130 @@ mod tests {
             vec![Some(15.0), Some(25.0), None, Some(45.0)],
         ),
     }
+
+    #[test]
+    fn time_weighted_mean_with_uneven_spacing() {
+        // Two quick samples followed by a long gap: the last sample covers
+        // most of the bin so it dominates the weighted mean.
+        let now = Instant::now();
+        let samples = vec![10.0, 20.0, 50.0];
+        let time_samples: Vec<Instant> = [10, 20, 100]
+            .iter()
+            .map(|&ms| now + Duration::from_millis(ms))
+            .collect();
+        let end = now + Duration::from_millis(100);
+
+        let simple = resample_with(ResampleStrategy::Mean, &samples, &time_samples, now, end, 1);
+        let weighted = resample_with(
+            ResampleStrategy::TimeWeightedMean,
+            &samples,
+            &time_samples,
+            now,
+            end,
+            1,
+        );
+
+        assert_vec_nearly_equal(&simple, &[Some(80.0 / 3.0)], "simple mean");
+        assert_vec_nearly_equal(&weighted, &[Some(43.0)], "time weighted mean");
+    }
+
+    #[test]
+    fn time_weighted_mean_clips_to_the_bin() {
+        // The second sample covers 40..120 but only 100..120 lies in its bin.
+        let now = Instant::now();
+        let samples = vec![1.0, 4.0, 10.0];
+        let time_samples: Vec<Instant> = [40, 120, 180]
+            .iter()
+            .map(|&ms| now + Duration::from_millis(ms))
+            .collect();
+        let result = resample_with(
+            ResampleStrategy::TimeWeightedMean,
+            &samples,
+            &time_samples,
+            now,
+            now + Duration::from_millis(200),
+            2,
+        );
+        assert_vec_nearly_equal(&result, &[Some(1.0), Some(8.5)], "clipped");
+    }
+
+    #[test]
+    fn detailed_counts_the_samples_in_each_bin() {
+        let now = Instant::now();
+        let samples = vec![5.0, 15.0, 25.0, 35.0, 45.0];
+        let time_samples: Vec<Instant> = [10, 50, 90, 160, 170]
+            .iter()
+            .map(|&ms| now + Duration::from_millis(ms))
+            .collect();
+        let result = resample_detailed(
+            ResampleStrategy::Mean,
+            &samples,
+            &time_samples,
+            now,
+            now + Duration::from_millis(200),
+            4,
+        );
+        assert_eq!(
+            result,
+            vec![Some((10.0, 2)), Some((25.0, 1)), None, Some((40.0, 2))]
+        );
+    }
+
+    #[test]
+    fn detailed_agrees_with_resample_with() {
+        let now = Instant::now();
+        let samples = vec![1.0, 4.0, 10.0];
+        let time_samples: Vec<Instant> = [40, 120, 180]
+            .iter()
+            .map(|&ms| now + Duration::from_millis(ms))
+            .collect();
+        let end = now + Duration::from_millis(200);
+        for strategy in [
+            ResampleStrategy::Max,
+            ResampleStrategy::Mean,
+            ResampleStrategy::TimeWeightedMean,
+        ] {
+            let detailed = resample_detailed(strategy, &samples, &time_samples, now, end, 2);
+            let plain = resample_with(strategy, &samples, &time_samples, now, end, 2);
+            let values: Vec<Option<f32>> = detailed.iter().map(|b| b.map(|(v, _)| v)).collect();
+            assert_eq!(values, plain, "{strategy:?}");
+            assert_eq!(detailed[1].map(|(_, n)| n), Some(2), "{strategy:?}");
+        }
+    }
+
+    macro_rules! interpolate_tests {
+        ($($name:ident: $value:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (mut bins, expected): (Vec<Option<f32>>, Vec<Option<f32>>) = $value;
+                    interpolate_gaps(&mut bins);
+                    assert_vec_nearly_equal(&bins, &expected, stringify!($name));
+                }
+            )*
+        }
+    }
+
+    interpolate_tests! {
+        interpolate_single_gap: (
+            vec![Some(1.0), None, Some(3.0)],
+            vec![Some(1.0), Some(2.0), Some(3.0)],
+        ),
+        interpolate_long_gap: (
+            vec![Some(4.0), None, None, None, Some(0.0)],
+            vec![Some(4.0), Some(3.0), Some(2.0), Some(1.0), Some(0.0)],
+        ),
+        interpolate_keeps_leading_and_trailing: (
+            vec![None, Some(1.0), None, Some(2.0), None],
+            vec![None, Some(1.0), Some(1.5), Some(2.0), None],
+        ),
+        interpolate_several_gaps: (
+            vec![Some(0.0), None, Some(2.0), Some(2.0), None, None, Some(8.0)],
+            vec![Some(0.0), Some(1.0), Some(2.0), Some(2.0), Some(4.0), Some(6.0), Some(8.0)],
+        ),
+        interpolate_all_empty: (
+            vec![None, None],
+            vec![None, None],
+        ),
+    }
 }
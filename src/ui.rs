@@ -4,3 +4,7 @@ pub mod process;
 pub mod stat_line;
 pub mod state;
 pub mod theme;
+pub mod title;
+
+#[cfg(test)]
+pub(crate) mod testing;
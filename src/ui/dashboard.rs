@@ -1,21 +1,118 @@
 use crate::{
     config::ProcliConfig,
-    proc::process::Process,
+    proc::{
+        process::{Process, ProcessKind},
+        stats::{ProcessStats, Thresholds},
+    },
     ui::{
         debug::DebugWidget,
-        process::ProcessWidget,
+        process::{CARD_HEIGHT, MIN_CARD_HEIGHT, ProcessWidget},
+        stat_line::split_stats,
         state::{Focussable, Mode, UiState},
+        theme::Theme,
     },
 };
-use ratatui::{buffer::Buffer, layout::Rect, macros::*, prelude::*, widgets::*};
+use ratatui::{
+    buffer::Buffer, layout::Rect, macros::line as rline, macros::*, prelude::*, widgets::*,
+};
 use tui_logger::*;
 
 pub struct DashboardWidget<'a> {
     pub ui: &'a UiState,
     pub processes: &'a [Process],
+    pub self_stats: Option<ProcessStats>,
+    /// Combined stats of all processes over time.
+    pub totals: &'a [ProcessStats],
+    pub totals_max: ProcessStats,
     pub config: &'a ProcliConfig,
 }
 
+impl DashboardWidget<'_> {
+    /// The processes as one row each, scrolled to keep the focus in view.
+    fn render_list(&self, visible: &[&Process], area: Rect, buf: &mut Buffer) {
+        let focus = match self.ui.focus {
+            Some(Focussable::Process(i)) => Some(i),
+            _ => None,
+        };
+        let header = Row::new(["", "Process", "State", "CPU", "RAM", "Up", "⟳"])
+            .style(Style::default().fg(self.ui.theme.primary));
+        let rows = visible.iter().enumerate().map(|(index, proc)| {
+            ProcessWidget {
+                process: proc,
+                focussed: focus == Some(index),
+                ui: self.ui,
+            }
+            .list_row()
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+                Constraint::Length(7),
+                Constraint::Length(9),
+                Constraint::Length(7),
+                Constraint::Length(5),
+            ],
+        )
+        .header(header)
+        .bg(self.ui.theme.surface);
+        let mut state = TableState::default().with_selected(focus);
+        StatefulWidget::render(table, area.inner(Margin::new(1, 1)), buf, &mut state);
+    }
+
+    /// Sparklines of the CPU and RAM of all processes together.
+    fn render_totals(&self, area: Rect, buf: &mut Buffer) {
+        let counts: Vec<String> = [ProcessKind::Service, ProcessKind::Stub]
+            .iter()
+            .map(|kind| {
+                (
+                    kind,
+                    self.processes
+                        .iter()
+                        .filter(|p| p.kind == *kind && !p.removed)
+                        .count(),
+                )
+            })
+            .filter(|(_, n)| *n > 0)
+            .map(|(kind, n)| kind.count(n))
+            .collect();
+        let title = if counts.is_empty() {
+            "All processes".to_string()
+        } else {
+            format!("All processes: {}", counts.join(", "))
+        };
+        let window = self.ui.totals_window.as_secs();
+        let window = if window.is_multiple_of(60) {
+            format!(" last {}m ", window / 60)
+        } else {
+            format!(" last {window}s ")
+        };
+        let focussed = matches!(self.ui.focus, Some(Focussable::Totals));
+        let block = Block::bordered()
+            .title(title)
+            .title_bottom(Line::from(window).right_aligned())
+            .border_style(Style::default().fg(Theme::border(self.ui.theme.primary, focussed)))
+            .border_type(BorderType::Rounded)
+            .bg(self.ui.theme.surface);
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if self.totals.is_empty() {
+            return;
+        }
+        let (cpu, ram) = split_stats(
+            self.ui,
+            self.totals,
+            &self.totals_max,
+            &Thresholds::default(),
+        );
+        let [top, bottom] = vertical![==1, ==1].areas(inner);
+        cpu.over(self.ui.totals_window).render(top, buf);
+        ram.over(self.ui.totals_window).render(bottom, buf);
+    }
+}
+
 impl<'a> Widget for &mut DashboardWidget<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         Clear.render(area, buf);
@@ -27,17 +124,29 @@ impl<'a> Widget for &mut DashboardWidget<'a> {
 
         let main_rect = if self.ui.debug {
             let [main_rect, panel_rect] = horizontal![>=5, >=30].areas(window_rect);
-            DebugWidget { ui: self.ui }.render(panel_rect, buf);
+            DebugWidget {
+                ui: self.ui,
+                processes: self.processes,
+                self_stats: self.self_stats,
+            }
+            .render(panel_rect, buf);
             main_rect
         } else {
             window_rect
         };
 
-        let border_color = match self.ui.focus {
-            Some(Focussable::Logs) => self.ui.theme.accent,
-            _ => self.ui.theme.foreground,
-        };
-        TuiLoggerSmartWidget::default()
+        let border_color = Theme::border(
+            self.ui.theme.foreground,
+            matches!(self.ui.focus, Some(Focussable::Logs)),
+        );
+        let mut logger = TuiLoggerSmartWidget::default();
+        if !self.ui.log_follow {
+            logger = logger.title_log(rline![
+                "Tui Log ",
+                span!("FOLLOW OFF").fg(self.ui.theme.warning).bold()
+            ]);
+        }
+        logger
             .style_error(panel_style.fg(self.ui.theme.error))
             .style_debug(panel_style)
             .style_warn(panel_style.fg(self.ui.theme.warning))
@@ -60,36 +169,75 @@ impl<'a> Widget for &mut DashboardWidget<'a> {
             .fg(self.ui.theme.foreground);
         Block::new().style(main_style).render(main_rect, buf);
 
+        let main_rect = if self.ui.show_totals {
+            let [totals_rect, rest] = vertical![==4, *=1].areas(main_rect);
+            self.render_totals(totals_rect.inner(Margin::new(1, 0)), buf);
+            rest
+        } else {
+            main_rect
+        };
+
         let col_constraints = (0..self.ui.proc_columns).map(|_| Constraint::Fill(1));
-        let row_constraints = (0..self.ui.proc_rows).map(|_| Constraint::Length(5));
+        let card_height = self
+            .ui
+            .card_height
+            .unwrap_or(CARD_HEIGHT)
+            .max(MIN_CARD_HEIGHT);
+        let row_constraints = (0..self.ui.proc_rows).map(|_| Constraint::Length(card_height));
         let horizontal = Layout::horizontal(col_constraints)
             .spacing(1)
             .horizontal_margin(1);
         let vertical = Layout::vertical(row_constraints).spacing(1).margin(1);
 
-        let rows = vertical.split(main_rect);
-        let mut cells = rows.iter().flat_map(|&row| horizontal.split(row).to_vec());
-        for (index, proc) in self.processes.iter().enumerate() {
-            if let Some(area) = cells.next() {
-                let focussed = matches!(
-                    &self.ui.focus,
-                    Some(Focussable::Process(i)) if *i == index
-                );
-                if focussed && matches!(self.ui.mode, Mode::Spotlight) {
-                    continue;
-                }
-                ProcessWidget {
-                    process: proc,
-                    focussed,
-                    ui: self.ui,
+        let visible = self.ui.visible(self.processes);
+        let filtered = !self.ui.filter.is_empty() || self.ui.kind_filter.is_some();
+        let main_rect = if self.ui.searching || filtered {
+            let [search_rect, rest] = vertical![==1, *=1].areas(main_rect);
+            let cursor = if self.ui.searching { "_" } else { "" };
+            let kind = match self.ui.kind_filter {
+                Some(kind) => format!(" {}", kind.label()),
+                None => String::new(),
+            };
+            rline![
+                kind.fg(self.ui.theme.accent),
+                " /".fg(self.ui.theme.primary),
+                format!("{}{} ", self.ui.filter, cursor),
+                format!("({} of {})", visible.len(), self.processes.len())
+                    .fg(self.ui.theme.secondary),
+            ]
+            .render(search_rect, buf);
+            rest
+        } else {
+            main_rect
+        };
+
+        if self.ui.listing() {
+            self.render_list(&visible, main_rect, buf);
+        } else {
+            let rows = vertical.split(main_rect);
+            let mut cells = rows.iter().flat_map(|&row| horizontal.split(row).to_vec());
+            for (index, proc) in visible.iter().enumerate() {
+                if let Some(area) = cells.next() {
+                    let focussed = matches!(
+                        &self.ui.focus,
+                        Some(Focussable::Process(i)) if *i == index
+                    );
+                    if focussed && matches!(self.ui.mode, Mode::Spotlight) {
+                        continue;
+                    }
+                    ProcessWidget {
+                        process: proc,
+                        focussed,
+                        ui: self.ui,
+                    }
+                    .render(area, buf);
                 }
-                .render(area, buf);
             }
         }
 
         if matches!(self.ui.mode, Mode::Spotlight)
             && let Some(Focussable::Process(i)) = &self.ui.focus
-            && let Some(proc) = self.processes.get(*i)
+            && let Some(proc) = visible.get(*i)
         {
             ProcessWidget {
                 process: proc,
@@ -100,3 +248,39 @@ impl<'a> Widget for &mut DashboardWidget<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::testing::{process, render, running};
+
+    #[test]
+    fn totals_sit_above_a_grid_of_cards() {
+        let ui = UiState::default();
+        let processes = [running("api", &ui, 30), process("web")];
+        let config = ProcliConfig::default();
+        let mut dashboard = DashboardWidget {
+            ui: &ui,
+            processes: &processes,
+            self_stats: None,
+            totals: &[],
+            totals_max: ProcessStats::default(),
+            config: &config,
+        };
+        // The log panel along the bottom belongs to tui-logger.
+        assert_eq!(
+            render(&mut dashboard, 80, 30)[..9],
+            [
+                " ╭All processes: 2 services───────────────────────────────────────────────────╮ ",
+                " │                                                                            │ ",
+                " │                                                                            │ ",
+                " ╰─────────────────────────────────────────────────────────────────── last 2m ╯ ",
+                "                                                                                ",
+                "  ╭ SVC api ─ ● ─ᔐ─────────────────────╮ ╭ SVC web ─◑────────────────────────╮  ",
+                "  │ _____________▃▄▅▆▇ CPU:    30.0%   │ │                                   │  ",
+                "  │ _____________▆▆▆▆▇ RAM:     160MB  │ │             Starting…             │  ",
+                "  ╰───────────────────────────── ↑ 60s ╯ ╰───────────────────────────────  - ╯  ",
+            ]
+        );
+    }
+}
@@ -1,31 +1,129 @@
-use ratatui::{prelude::*, widgets::*};
+use std::time::Instant;
 
-use crate::ui::state::{Focussable, UiState};
+use ratatui::{macros::*, prelude::*, widgets::*};
+
+use crate::{
+    proc::{process::Process, stats::ProcessStats},
+    ui::{
+        stat_line::{cpu_text, memory_text},
+        state::{Focussable, UiState},
+        theme::Theme,
+    },
+};
 
 pub struct DebugWidget<'a> {
     pub ui: &'a UiState,
+    pub processes: &'a [Process],
+    /// procli's own resource usage.
+    pub self_stats: Option<ProcessStats>,
+}
+
+impl DebugWidget<'_> {
+    /// One row per process of the internals useful when chasing spawn and
+    /// restart bugs.
+    fn process_table(&self) -> Table<'_> {
+        let header = Row::new([
+            "Name", "UUID", "PID", "State", "Rst", "Start", "Stop", "Stats",
+        ])
+        .style(Style::default().fg(self.ui.theme.primary));
+        let rows = self.processes.iter().map(|p| {
+            Row::new([
+                p.name.clone(),
+                p.uuid.simple().to_string()[..8].to_string(),
+                p.pid.map(|pid| pid.to_string()).unwrap_or("-".to_string()),
+                p.state.label().to_string(),
+                p.restarts.to_string(),
+                self.ago(p.last_start),
+                self.ago(p.last_stop),
+                p.stats.len().to_string(),
+            ])
+        });
+        Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(7),
+                Constraint::Length(8),
+                Constraint::Length(3),
+                Constraint::Length(5),
+                Constraint::Length(5),
+                Constraint::Length(5),
+            ],
+        )
+        .header(header)
+    }
+
+    fn ago(&self, then: Option<Instant>) -> String {
+        match then {
+            Some(then) => format!("{}s", self.ui.time.duration_since(then).as_secs()),
+            None => "-".to_string(),
+        }
+    }
 }
 
 impl Widget for DebugWidget<'_> {
     fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
         let debug = &self.ui;
-        let border_color = match self.ui.focus {
-            Some(Focussable::Debug) => self.ui.theme.accent,
-            _ => self.ui.theme.foreground,
-        };
+        let border_color = Theme::border(
+            self.ui.theme.foreground,
+            matches!(self.ui.focus, Some(Focussable::Debug)),
+        );
         let panel_style = Style::default()
             .bg(self.ui.theme.surface)
             .fg(self.ui.theme.foreground);
-        let paragraph = Paragraph::new(format!("{debug:#?}"))
-            .block(
-                Block::bordered()
-                    .title("Debug")
-                    .title_alignment(Alignment::Left)
-                    .border_style(Style::default().fg(border_color))
-                    .border_type(BorderType::Rounded),
-            )
-            .alignment(HorizontalAlignment::Left)
+        let block = Block::bordered()
+            .title("Debug")
+            .title_alignment(Alignment::Left)
+            .border_style(Style::default().fg(border_color))
+            .border_type(BorderType::Rounded)
             .style(panel_style);
-        paragraph.render(area, buf);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let table_height = self.processes.len() as u16 + 1;
+        let [table_rect, _, text_rect] = vertical![==table_height, ==1, *=1].areas(inner);
+        Widget::render(self.process_table(), table_rect, buf);
+
+        let mut text = match self.self_stats {
+            Some(stats) => Text::from(format!(
+                "procli: CPU {} RAM {}",
+                cpu_text(stats.cpu_percent),
+                memory_text(stats.memory_mb)
+            )),
+            None => Text::from("procli: no stats yet"),
+        };
+        text.extend(Text::from(format!("{debug:#?}")));
+        Paragraph::new(text)
+            .alignment(HorizontalAlignment::Left)
+            .render(text_rect, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::testing::{process, render, running};
+
+    #[test]
+    fn lists_each_process_then_procli_itself() {
+        let ui = UiState::default();
+        let processes = [running("api", &ui, 30), process("web")];
+        let widget = DebugWidget {
+            ui: &ui,
+            processes: &processes,
+            self_stats: None,
+        };
+        assert_eq!(
+            render(widget, 60, 8)[..6],
+            [
+                "╭Debug─────────────────────────────────────────────────────╮",
+                "│Name       UUID     PID     State    Rst Start Stop  Stats│",
+                "│api        00000000 -       running  0   60s   -     30   │",
+                "│web        00000000 -       starting 0   -     -     0    │",
+                "│                                                          │",
+                "│procli: no stats yet                                      │",
+            ]
+        );
     }
 }
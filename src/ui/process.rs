@@ -1,10 +1,16 @@
-use std::ffi::OsStr;
-
 use crate::{
-    proc::process::{Process, ProcessRestart, ProcessState},
+    config::CpuMode,
+    proc::{
+        command::command_line,
+        health::HealthStatus,
+        process::{
+            Expiry, Process, ProcessRestart, ProcessState, RECENT_OUTPUT_LINES, exit_reason,
+        },
+    },
     ui::{
-        stat_line::split_stats,
+        stat_line::{cpu_text, memory_text, split_stats},
         state::{Mode, UiState},
+        theme::Theme,
     },
 };
 use ratatui::{
@@ -12,6 +18,36 @@ use ratatui::{
     widgets::*,
 };
 
+/// Stat lines on a card: CPU and RAM.
+pub const CARD_STAT_LINES: u16 = 2;
+/// Height of a card, borders included, with room for its stats and note.
+pub const CARD_HEIGHT: u16 = CARD_STAT_LINES + 3;
+/// Smallest card worth drawing: one line inside the borders.
+pub const MIN_CARD_HEIGHT: u16 = 3;
+
+/// Split the inside of a card into an area for each stat line, sharing out
+/// any spare height, and the note line at the bottom if there is room.
+fn card_layout(inner: Rect) -> (Vec<Rect>, Option<Rect>) {
+    let (stats, note) = if inner.height > CARD_STAT_LINES {
+        let [stats, note] = vertical![*=1, ==1].areas(inner);
+        (stats, Some(note))
+    } else {
+        (inner, None)
+    };
+    let rows = Layout::vertical((0..CARD_STAT_LINES).map(|_| Constraint::Fill(1))).split(stats);
+    (rows.to_vec(), note)
+}
+
+/// `text` cut to `width` characters, ending in `…` if anything was cut.
+fn ellipsize(text: &str, width: usize) -> String {
+    if text.chars().count() > width {
+        let cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        cut + "…"
+    } else {
+        text.to_string()
+    }
+}
+
 pub struct ProcessWidget<'a> {
     pub process: &'a Process,
     pub focussed: bool,
@@ -32,15 +68,28 @@ impl ProcessWidget<'_> {
         let status = self.status_indicator();
         let updown = self.updown_indicator();
         let live = !self.process.stats.is_empty();
-        let border_color = match self.focussed {
-            true => self.ui.theme.accent,
-            false => self.ui.theme.primary_background,
+        let base = if self.process.removed {
+            self.ui.theme.panel
+        } else {
+            self.ui
+                .theme
+                .alert(self.process.alert, self.ui.theme.primary)
         };
-        let title = self.title_line();
+        let border_color = Theme::border(base, self.focussed);
+        // The name gets whatever the corners, the status, the throbber and
+        // the gaps between them leave.
+        let mut taken = 2 + status.width() + 1;
+        if live {
+            taken += self.signal_throbber().chars().count() + 1;
+        }
+        let title = self.title_line_within((area.width as usize).saturating_sub(taken));
         let mut border = Block::bordered()
             .title_top(title)
             .title_top(status)
             .title_bottom(rline![" ", updown, " ", self.uptime(), " "].right_aligned())
+            .title_bottom(self.restarts_used())
+            .title_bottom(self.exit_reason())
+            .title_bottom(self.dismiss_hint())
             .border_style(Style::default().bg(self.ui.theme.surface).fg(border_color))
             .bg(self.ui.theme.surface)
             .border_type(BorderType::Rounded);
@@ -50,27 +99,74 @@ impl ProcessWidget<'_> {
         }
         border.render(area, buf);
 
+        let (rows, note) = card_layout(inner);
+        if let Some(note) = note {
+            self.render_note(note, buf);
+        }
         if live {
-            let (cpu, ram) = split_stats(self.ui, &self.process.stats, &self.process.stats_max);
-            let [top, middle, _] = vertical![==1,==1, ==1].areas(inner);
-            cpu.render(top, buf);
-            ram.render(middle, buf);
+            let (cpu, ram) = split_stats(
+                self.ui,
+                &self.process.stats,
+                &self.process.stats_max,
+                &self.process.thresholds,
+            );
+            cpu.render(rows[0], buf);
+            ram.render(rows[1], buf);
         } else {
-            let text = Text::from("No Stats Yet");
+            let text = Text::from(self.placeholder());
             let area = inner.centered(
-                Constraint::Length(text.width() as u16),
+                Constraint::Length((text.width() as u16).min(inner.width)),
                 Constraint::Length(1),
             );
             text.render(area, buf);
         }
     }
 
+    /// The note on the card's last line, cut short to fit.
+    fn render_note(&self, line: Rect, buf: &mut Buffer) {
+        let Some(note) = &self.process.note else {
+            return;
+        };
+        let bottom = line.inner(Margin::new(1, 0));
+        Line::from(ellipsize(note, bottom.width as usize))
+            .fg(self.ui.theme.secondary)
+            .italic()
+            .render(bottom, buf);
+    }
+
+    /// What a card without any stats shows instead, depending on why there
+    /// are none.
+    fn placeholder(&self) -> Span<'static> {
+        let theme = &self.ui.theme;
+        if self.process.removed && !self.process.is_alive() {
+            return span!(theme.secondary; "Removed");
+        }
+        match &self.process.state {
+            ProcessState::Starting => span!(theme.warning; "Starting…"),
+            ProcessState::Running => span!(theme.secondary; "No Stats Yet"),
+            ProcessState::Killing(_) => span!(theme.warning; "Stopping…"),
+            ProcessState::Stopped(_, _) if self.process.expired == Some(Expiry::StartTimeout) => {
+                span!(theme.warning; "Start timed out")
+            }
+            ProcessState::Stopped(_, _) | ProcessState::Completed(_)
+                if self.process.died_before_stats =>
+            {
+                span!(theme.secondary; "Exited before stats could attach")
+            }
+            ProcessState::Stopped(_, _) => span!(theme.error; "Stopped"),
+            ProcessState::Completed(_) => span!(theme.success; "Completed"),
+            ProcessState::Failed(reason) => span!(theme.error; "Failed to start: {}", reason),
+        }
+    }
+
     /// Render the larger modal version of the process widget.
     ///
     /// ```"not rust"
     /// ╭ SVC Dummy Service 1 ─ ● ────────────╮
     /// │ Info                   Status       │
     /// │                                     │
+    /// │ Recent output                       │
+    /// │                                     │
     /// │ Chart                               │
     /// │                                     │
     /// ╰─────────────────────────────────────╯
@@ -79,58 +175,95 @@ impl ProcessWidget<'_> {
     fn render_modal(&self, area: Rect, buf: &mut Buffer) {
         Clear.render(area, buf);
         let live = !self.process.stats.is_empty();
+        let border_color = if self.ui.attached {
+            self.ui.theme.accent
+        } else {
+            Theme::focus_border(self.ui.theme.primary)
+        };
         let mut border = Block::bordered()
             .title(self.title_line())
-            .border_style(
-                Style::default()
-                    .bg(self.ui.theme.surface)
-                    .fg(self.ui.theme.accent),
-            )
+            .border_style(Style::default().bg(self.ui.theme.surface).fg(border_color))
             .bg(self.ui.theme.surface)
             .border_type(BorderType::Rounded);
+        if self.ui.attached {
+            border = border.title_bottom(
+                rline![" INPUT ATTACHED: Esc to detach, Ctrl-D to close stdin "]
+                    .fg(self.ui.theme.accent)
+                    .right_aligned(),
+            );
+        }
         if live {
             border = border.title_top(self.signal_throbber());
         }
         let inner = border.inner(area);
         border.render(area, buf);
         let inner = inner.inner(Margin::new(1, 1));
-        let [info, stats] = vertical![>=8, *=1].areas(inner);
+        let output_height = RECENT_OUTPUT_LINES as u16 + 2;
+        let [info, output, stats] = vertical![>=8, ==output_height, *=1].areas(inner);
+        self.render_output(output, buf);
         let [definition, _, status] = horizontal![==2/3, ==2, ==1/3].areas(info);
-        let cmd_str = self.command_string();
-        let dir = match &self.process.cmd.as_std().get_current_dir() {
+        let cmd_str = command_line(&self.process.cmd);
+        let dir = match &self.process.directory {
             Some(dir) => dir.display().to_string(),
             None => ".".to_string(),
         };
         let restart_policy = self.restart_policy_string();
 
-        let definition_text = text!(
+        let mut definition_text = text!(
             self.field_line("Name: ", &self.process.name),
             self.field_line("Command: ", &cmd_str),
             self.field_line("Directory: ", &dir),
             self.field_line("Restart Policy: ", &restart_policy),
         );
+        if let Some(note) = &self.process.note {
+            definition_text.push_line(self.field_line("Note: ", note));
+        }
         let cpu = self
             .process
             .stats
             .last()
-            .map(|s| format!("{:.1}%", s.cpu_percent))
+            .map(|s| cpu_text(s.cpu_percent))
             .unwrap_or_else(|| "-".to_string());
+        let cores = match self.ui.cpu_mode {
+            CpuMode::Total => format!(" ({} cores)", self.ui.cores),
+            CpuMode::Normalized => format!(" of {} cores", self.ui.cores),
+        };
         let ram = self
             .process
             .stats
             .last()
-            .map(|s| format!("{:.1}MB", s.memory_mb))
+            .map(|s| memory_text(s.memory_mb))
             .unwrap_or_else(|| "-".to_string());
         definition_text.render(definition, buf);
-        let status_text = text!(
+        let mut status_text = text!(
             self.field_line("State: ", self.process_state()),
             self.field_line("Restarts: ", self.process.restarts.to_string()),
-            self.field_line("CPU: ", cpu),
+            self.field_line("CPU: ", cpu + &cores),
             self.field_line("RAM: ", ram),
             self.field_line("Uptime: ", self.uptime())
         );
+        if let Some(health) = self.process.health {
+            let color = match health {
+                HealthStatus::Starting => self.ui.theme.secondary,
+                HealthStatus::Healthy => self.ui.theme.success,
+                HealthStatus::Unhealthy => self.ui.theme.error,
+            };
+            status_text.push_line(self.field_line("Health: ", health.label().fg(color)));
+        }
+        if self.process.idle
+            && let Some(heard) = self.process.last_start.max(self.process.output.last())
+        {
+            let silent = self.ui.time.saturating_duration_since(heard).as_secs();
+            let stalled = format!("stalled, silent for {silent}s").fg(self.ui.theme.warning);
+            status_text.push_line(self.field_line("Output: ", stalled));
+        }
         status_text.render(status, buf);
-        let (_cpu, ram) = split_stats(self.ui, &self.process.stats, &self.process.stats_max);
+        let (_cpu, ram) = split_stats(
+            self.ui,
+            &self.process.stats,
+            &self.process.stats_max,
+            &self.process.thresholds,
+        );
         // let cpu_data = cpu.data();
         // let cpu_dataset = Dataset::default()
         //     .name("cpu")
@@ -184,6 +317,61 @@ impl ProcessWidget<'_> {
         chart.render(stats, buf);
     }
 
+    /// The last few lines the process wrote.
+    fn render_output(&self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .title("Output")
+            .border_style(Style::default().fg(Theme::blur_border(self.ui.theme.primary)))
+            .border_type(BorderType::Rounded);
+        let lines: Vec<Line> = self
+            .process
+            .output
+            .lines()
+            .into_iter()
+            .map(Line::from)
+            .collect();
+        Paragraph::new(lines)
+            .block(block)
+            .fg(self.ui.theme.foreground)
+            .render(area, buf);
+    }
+
+    /// One row of the list view: status, name, state, CPU, RAM, uptime and
+    /// restarts.
+    pub fn list_row(&self) -> Row<'static> {
+        let (cpu, ram) = match self.process.stats.last() {
+            Some(stats) => (cpu_text(stats.cpu_percent), memory_text(stats.memory_mb)),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        let alert = self
+            .ui
+            .theme
+            .alert(self.process.alert, self.ui.theme.foreground);
+        let restarts = match self.process.restart_alert() {
+            Some(restart_alert) => Span::from(format!(
+                "{}/{}",
+                self.process.restarts,
+                self.process.restart_policy.limit()
+            ))
+            .fg(self.ui.theme.alert(restart_alert, self.ui.theme.foreground)),
+            None => Span::from(self.process.restarts.to_string()),
+        };
+        let row = Row::new([
+            Cell::from(self.status_indicator()),
+            Cell::from(self.title_line()),
+            Cell::from(self.process_state()),
+            Cell::from(Span::from(cpu).fg(alert)),
+            Cell::from(Span::from(ram).fg(alert)),
+            Cell::from(self.uptime()),
+            Cell::from(restarts),
+        ]);
+        if self.focussed {
+            row.bg(Theme::blur_border(self.ui.theme.primary))
+        } else {
+            row
+        }
+    }
+
     fn field_line<'a, T: Into<Span<'a>>>(&self, label: &'a str, value: T) -> Line<'a> {
         let mut s: Span = value.into();
         if s.style.fg.is_none() {
@@ -192,31 +380,81 @@ impl ProcessWidget<'_> {
         rline!(label.fg(self.ui.theme.primary), s)
     }
 
-    fn command_string(&self) -> String {
-        let cmd = &self.process.cmd.as_std();
-        let args = cmd.get_args().collect::<Vec<_>>().join(OsStr::new(" "));
-        format!("{} {}", cmd.get_program().display(), args.display())
+    /// Restarts used out of the policy's maximum, or nothing if restarts are
+    /// off.
+    fn restarts_used(&self) -> Line<'_> {
+        match self.process.restart_alert() {
+            Some(alert) => rline![
+                " ⟳ ",
+                format!(
+                    "{}/{} ",
+                    self.process.restarts,
+                    self.process.restart_policy.limit()
+                )
+            ]
+            .fg(self.ui.theme.alert(alert, self.ui.theme.foreground)),
+            None => Line::default(),
+        }
+    }
+
+    /// How the last child died, until the process is running again, or a
+    /// warning while it runs but has gone quiet for `idle_timeout`.
+    fn exit_reason(&self) -> Line<'static> {
+        match self.process.state.exit_reason() {
+            Some(reason) if self.process.expired.is_none() => {
+                rline![" ", reason, " "].fg(self.ui.theme.error)
+            }
+            None if self.process.idle => rline![" stalled "].fg(self.ui.theme.warning),
+            _ => Line::default(),
+        }
+    }
+
+    /// How to take away the card of a process gone from the config.
+    fn dismiss_hint(&self) -> Line<'static> {
+        if self.process.removed && !self.process.is_alive() {
+            rline![" removed, Del to dismiss "].fg(self.ui.theme.secondary)
+        } else {
+            Line::default()
+        }
     }
 
     fn restart_policy_string(&self) -> String {
         if self.process.restart_policy.enabled {
             format!(
                 "Enabled: max: {}, cooldown={}s",
-                self.process.restart_policy.max_restarts, self.process.restart_policy.cooloff
+                self.process.restart_policy.limit(),
+                self.process.restart_policy.cooloff
             )
         } else {
             "No Restart".to_string()
         }
     }
 
+    /// Said of a child that exited before any stats were taken of it.
+    fn early(&self) -> &'static str {
+        if self.process.died_before_stats {
+            " before stats could attach"
+        } else {
+            ""
+        }
+    }
+
     fn process_state<'a>(&self) -> Span<'a> {
+        if self.process.removed && !self.process.is_alive() {
+            return span!(self.ui.theme.secondary; "Removed from the config");
+        }
         match &self.process.state {
             ProcessState::Starting => span!(self.ui.theme.warning; "Starting"),
             ProcessState::Running => span!(self.ui.theme.success; "Running"),
             ProcessState::Killing(_) => span!(self.ui.theme.warning; "Killing"),
+            ProcessState::Stopped(_, _) if self.process.waiting_for.is_some() => {
+                let dependency = self.process.waiting_for.clone().unwrap_or_default();
+                span!(self.ui.theme.warning; "Waiting for {} to restart", dependency)
+            }
             ProcessState::Stopped(r, e) => {
                 let restart = match r {
                     ProcessRestart::NoRestart => "No Restart".to_string(),
+                    ProcessRestart::Respawn => "Restarting".to_string(),
                     ProcessRestart::RestartAt(target) => {
                         format!(
                             "Restart in {}",
@@ -224,32 +462,72 @@ impl ProcessWidget<'_> {
                         )
                     }
                 };
-                span!(self.ui.theme.error; "Stopped ({}), {}", e.code().unwrap_or(-1), restart)
+                if let Some(why) = self.process.expired {
+                    let why = match why {
+                        Expiry::Lifetime => "Lifetime reached",
+                        Expiry::StartTimeout => "Start timed out",
+                        Expiry::Unhealthy => "Unhealthy",
+                    };
+                    span!(self.ui.theme.warning; "{}, {}", why, restart)
+                } else {
+                    span!(self.ui.theme.error; "Stopped ({}){}, {}", exit_reason(e), self.early(), restart)
+                }
+            }
+            ProcessState::Completed(e) => {
+                span!(self.ui.theme.success; "Completed ({}){}", e.code().unwrap_or(0), self.early())
+            }
+            ProcessState::Failed(reason) => {
+                span!(self.ui.theme.error; "Failed to start: {}", reason)
             }
         }
     }
 
-    fn title_line(&self) -> Line<'_> {
-        ratatui::macros::line!(
-            " SVC ".fg(self.ui.theme.primary),
-            self.process.display.clone().fg(self.ui.theme.foreground),
-            " "
-        )
+    fn title_line(&self) -> Line<'static> {
+        self.title_line_within(usize::MAX)
     }
 
-    fn status_indicator(&self) -> Span<'_> {
+    /// The title, with the display name cut short so the whole line fits in
+    /// `width`.
+    fn title_line_within(&self, width: usize) -> Line<'static> {
+        let label = match self.process.label.as_str() {
+            "" => " ".to_string(),
+            label => format!(" {label} "),
+        };
+        let room = width.saturating_sub(label.chars().count() + 1);
+        let display = ellipsize(&self.process.display, room);
+        let display = if self.process.removed {
+            display.fg(self.ui.theme.secondary).crossed_out()
+        } else {
+            display.fg(self.ui.theme.foreground)
+        };
+        ratatui::macros::line!(label.fg(self.ui.theme.primary), display, " ")
+    }
+
+    fn status_indicator(&self) -> Span<'static> {
         match self.process.state {
             ProcessState::Starting => {
                 Span::from(self.status_progress_throbber()).fg(self.ui.theme.foreground)
             }
-            ProcessState::Running => Span::from(" ● ").fg(self.ui.theme.success),
+            ProcessState::Running => match self.process.health {
+                Some(HealthStatus::Unhealthy) => Span::from(" ♥ ").fg(self.ui.theme.error),
+                Some(HealthStatus::Starting) => Span::from(" ● ").fg(self.ui.theme.secondary),
+                _ => Span::from(" ● ").fg(self.ui.theme.success),
+            },
             ProcessState::Killing(_) => {
                 Span::from(self.status_progress_throbber()).fg(self.ui.theme.warning)
             }
+            ProcessState::Stopped(_, _) if self.process.waiting_for.is_some() => {
+                Span::from(" ⟳ ").fg(self.ui.theme.warning)
+            }
             ProcessState::Stopped(ProcessRestart::NoRestart, _) => {
                 Span::from(" ○ ").fg(self.ui.theme.error)
             }
+            ProcessState::Stopped(_, _) if self.process.expired.is_some() => {
+                Span::from(" ⟳ ").fg(self.ui.theme.warning)
+            }
             ProcessState::Stopped(_, _) => Span::from(" ⟳ ").fg(self.ui.theme.error),
+            ProcessState::Completed(_) => Span::from(" ✓ ").fg(self.ui.theme.success),
+            ProcessState::Failed(_) => Span::from(" ✗ ").fg(self.ui.theme.error),
         }
     }
 
@@ -259,6 +537,8 @@ impl ProcessWidget<'_> {
             ProcessState::Running => span!("↑"),
             ProcessState::Killing(_) => span!("↓"),
             ProcessState::Stopped(_, _) => span!("↓"),
+            ProcessState::Completed(_) => span!("✓"),
+            ProcessState::Failed(_) => span!("✗"),
         }
     }
 
@@ -271,9 +551,12 @@ impl ProcessWidget<'_> {
                     ProcessState::Running => {
                         format!("{}s", self.ui.time.duration_since(then).as_secs())
                     }
-                    ProcessState::Killing(_) | ProcessState::Stopped(_, _) => {
+                    ProcessState::Killing(_)
+                    | ProcessState::Stopped(_, _)
+                    | ProcessState::Completed(_) => {
                         format!("{}s", last_stop.duration_since(then).as_secs())
                     }
+                    ProcessState::Failed(_) => "-".to_string(),
                 }
             }
             None => "-".to_string(),
@@ -302,3 +585,156 @@ impl<'a> Widget for ProcessWidget<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::testing::{process, render, running};
+
+    fn heights(inner_height: u16) -> (Vec<u16>, Option<u16>) {
+        let (rows, note) = card_layout(Rect::new(0, 0, 40, inner_height));
+        (rows.iter().map(|r| r.height).collect(), note.map(|n| n.y))
+    }
+
+    #[test]
+    fn card_rows_share_the_height_above_the_note() {
+        assert_eq!(heights(CARD_HEIGHT - 2), (vec![1, 1], Some(2)));
+        assert_eq!(heights(7), (vec![3, 3], Some(6)));
+        assert_eq!(heights(2), (vec![1, 1], None));
+        assert_eq!(heights(MIN_CARD_HEIGHT - 2), (vec![1, 0], None));
+    }
+
+    fn card(process: &Process, ui: &UiState, width: u16) -> Vec<String> {
+        let widget = ProcessWidget {
+            process,
+            focussed: false,
+            ui,
+        };
+        render(widget, width, CARD_HEIGHT)
+    }
+
+    #[test]
+    fn running_card_shows_its_stats_and_uptime() {
+        let ui = UiState::default();
+        let api = running("api", &ui, 30);
+        assert_eq!(
+            card(&api, &ui, 40),
+            [
+                "╭ SVC api ─ ● ─ᔐ───────────────────────╮",
+                "│ _______________▄▅▅▆▇ CPU:    30.0%   │",
+                "│ _______________▆▆▆▇▇ RAM:     160MB  │",
+                "│                                      │",
+                "╰─────────────────────────────── ↑ 60s ╯",
+            ]
+        );
+    }
+
+    #[test]
+    fn card_without_stats_says_why() {
+        let ui = UiState::default();
+        assert_eq!(
+            card(&process("web"), &ui, 40),
+            [
+                "╭ SVC web ─◑───────────────────────────╮",
+                "│                                      │",
+                "│               Starting…              │",
+                "│                                      │",
+                "╰──────────────────────────────────  - ╯",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stopped_card_keeps_its_stats_and_shows_the_exit() {
+        use crate::proc::process::ProcessRestart;
+        use std::{os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+
+        let ui = UiState::default();
+        let mut api = running("api", &ui, 30);
+        api.state = ProcessState::Stopped(ProcessRestart::NoRestart, ExitStatus::from_raw(1 << 8));
+        api.last_stop = Some(ui.time - Duration::from_secs(5));
+        assert_eq!(
+            card(&api, &ui, 40),
+            [
+                "╭ SVC api ─ ○ ─ᔐ───────────────────────╮",
+                "│ _______________▄▅▅▆▇ CPU:    30.0%   │",
+                "│ _______________▆▆▆▇▇ RAM:     160MB  │",
+                "│                                      │",
+                "╰─ exit 1 ────────────────────── ↓ 55s ╯",
+            ]
+        );
+    }
+
+    #[test]
+    fn quiet_card_is_marked_stalled() {
+        let ui = UiState::default();
+        let mut api = running("api", &ui, 30);
+        api.idle = true;
+        assert_eq!(
+            card(&api, &ui, 40)[4],
+            "╰─ stalled ───────────────────── ↑ 60s ╯"
+        );
+    }
+
+    #[test]
+    fn spotlight_shows_the_details() {
+        let mut ui = UiState::default();
+        ui.mode = Mode::Spotlight;
+        let api = running("api", &ui, 30);
+        let widget = ProcessWidget {
+            process: &api,
+            focussed: true,
+            ui: &ui,
+        };
+        let screen = render(widget, 80, 24);
+        assert_eq!(
+            screen[..8],
+            [
+                "╭ SVC api ─ᔐ───────────────────────────────────────────────────────────────────╮",
+                "│                                                                              │",
+                "│ Name: api                                          State: Running            │",
+                "│ Command: ./api                                     Restarts: 0               │",
+                "│ Directory: .                                       CPU: 30.0% (1 cores)      │",
+                "│ Restart Policy: No Restart                         RAM: 160MB                │",
+                "│                                                    Uptime: 60s               │",
+                "│                                                                              │",
+            ]
+        );
+        assert_eq!(
+            screen[10],
+            "│ ╭Output────────────────────────────────────────────────────────────────────╮ │"
+        );
+        assert_eq!(
+            screen[23],
+            "╰──────────────────────────────────────────────────────────────────────────────╯"
+        );
+    }
+
+    #[test]
+    fn long_display_names_are_cut_to_fit_the_card() {
+        let ui = UiState::default();
+        let mut api = process("api");
+        api.display = "a-really-quite-remarkably-long-service-name".to_string();
+        let lines = card(&api, &ui, 30);
+        assert_eq!(lines[0], "╭ SVC a-really-quite-rema… ─◑╮");
+        assert!(
+            lines[1..CARD_HEIGHT as usize - 1]
+                .iter()
+                .all(|line| line.starts_with('│') && line.ends_with('│'))
+        );
+
+        let mut ui = UiState::default();
+        ui.mode = Mode::Spotlight;
+        let widget = ProcessWidget {
+            process: &api,
+            focussed: true,
+            ui: &ui,
+        };
+        let title = &render(widget, 80, 24)[0];
+        assert!(
+            title.contains("a-really-quite-remarkably-long-service-name"),
+            "{title}"
+        );
+    }
+}
@@ -3,7 +3,11 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{proc::stats::ProcessStats, ui::state::UiState};
+use crate::{
+    config::ChartStyle,
+    proc::stats::{Alert, ProcessStats, Thresholds},
+    ui::state::UiState,
+};
 use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Rect},
@@ -13,17 +17,110 @@ use ratatui::{
     widgets::*,
 };
 
+/// How far back the history sparklines reach.
+pub const HISTORY_WINDOW: Duration = Duration::from_secs(120);
+
+/// Sparkline bars for [`ChartStyle::Ascii`].
+const ASCII_BARS: symbols::bar::Set = symbols::bar::Set {
+    full: "#",
+    seven_eighths: "#",
+    three_quarters: "=",
+    five_eighths: "=",
+    half: "-",
+    three_eighths: "-",
+    one_quarter: ".",
+    one_eighth: ".",
+    empty: " ",
+};
+
+/// A stat with as many decimal places as suit its size: two below 1, so
+/// light use doesn't show as nothing, one below 100, and none above.
+pub fn format_value(value: f32) -> String {
+    let places = match value.abs() {
+        v if v < 1.0 => 2,
+        v if v < 100.0 => 1,
+        _ => 0,
+    };
+    format!("{value:.places$}")
+}
+
+/// A CPU percentage as a number and its unit.
+pub fn cpu_parts(percent: f32) -> (String, &'static str) {
+    (format_value(percent), "%")
+}
+
+/// Memory in megabytes as a number and its unit, switching to gigabytes from
+/// 1000MB.
+pub fn memory_parts(mb: f32) -> (String, &'static str) {
+    if mb >= 1000.0 {
+        (format_value(mb / 1000.0), "GB")
+    } else {
+        (format_value(mb), "MB")
+    }
+}
+
+pub fn cpu_text(percent: f32) -> String {
+    let (value, unit) = cpu_parts(percent);
+    value + unit
+}
+
+pub fn memory_text(mb: f32) -> String {
+    let (value, unit) = memory_parts(mb);
+    value + unit
+}
+
 #[derive(Debug)]
 pub struct SingleStat<'a> {
     name: String,
-    unit: String,
+    /// Splits the latest value into a number and unit for display.
+    format: fn(f32) -> (String, &'static str),
     history: Vec<f32>,
     max: f32,
     timestamps: Vec<Instant>,
+    /// How far back the history reaches.
+    window: Duration,
+    /// Alert level of the latest value.
+    alert: Alert,
     ui: &'a UiState,
 }
 
 impl<'a> SingleStat<'a> {
+    /// Show `window` of history rather than the usual [`HISTORY_WINDOW`].
+    pub fn over(self, window: Duration) -> Self {
+        Self { window, ..self }
+    }
+
+    /// Draw resampled history as a braille line, broken where values are
+    /// absent.
+    fn render_braille(&self, bins: &[Option<f64>], color: Color, area: Rect, buf: &mut Buffer) {
+        let points: Vec<Vec<(f64, f64)>> = bins
+            .iter()
+            .enumerate()
+            .collect::<Vec<_>>()
+            .split(|(_, bin)| bin.is_none())
+            .filter(|run| !run.is_empty())
+            .map(|run| {
+                run.iter()
+                    .filter_map(|(x, bin)| bin.map(|y| (*x as f64, y)))
+                    .collect()
+            })
+            .collect();
+        let datasets = points
+            .iter()
+            .map(|run| {
+                Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .fg(color)
+                    .data(run)
+            })
+            .collect();
+        Chart::new(datasets)
+            .x_axis(Axis::default().bounds([0.0, bins.len().saturating_sub(1) as f64]))
+            .y_axis(Axis::default().bounds([0.0, (self.max * 1.1) as f64]))
+            .render(area, buf);
+    }
+
     pub fn data(&self) -> Vec<(f64, f64)> {
         let now = Instant::now();
         std::iter::zip(&self.timestamps, &self.history)
@@ -36,22 +133,28 @@ pub fn split_stats<'a>(
     ui: &'a UiState,
     stats: &[ProcessStats],
     max_stats: &ProcessStats,
+    thresholds: &Thresholds,
 ) -> (SingleStat<'a>, SingleStat<'a>) {
     let timestamps: Vec<Instant> = stats.iter().map(|s| s.timestamp).collect();
+    let latest = stats.last();
     let cpu_history = SingleStat {
         name: "CPU".to_string(),
-        unit: "%".to_string(),
+        format: cpu_parts,
         history: stats.iter().map(|s| s.cpu_percent).collect(),
         max: max_stats.cpu_percent,
         timestamps: timestamps.clone(),
+        window: HISTORY_WINDOW,
+        alert: latest.map_or(Alert::Normal, |s| thresholds.cpu(s.cpu_percent)),
         ui,
     };
     let mem_history = SingleStat {
         name: "RAM".to_string(),
-        unit: "MB".to_string(),
+        format: memory_parts,
         history: stats.iter().map(|s| s.memory_mb).collect(),
         max: max_stats.memory_mb,
         timestamps,
+        window: HISTORY_WINDOW,
+        alert: latest.map_or(Alert::Normal, |s| thresholds.memory(s.memory_mb)),
         ui,
     };
     (cpu_history, mem_history)
@@ -62,22 +165,38 @@ impl<'a> Widget for &SingleStat<'a> {
         let [_, history, _, label, current, _] =
             horizontal![==1, *=1, ==1, ==6, ==8, ==2].areas(area);
         Text::from(self.name.clone() + ":").render(label, buf);
+        let color = self.ui.theme.alert(self.alert, self.ui.theme.primary);
+        let (value, unit) = (self.format)(*self.history.last().unwrap_or(&0.0));
+        let mut value = span![value];
+        if self.alert != Alert::Normal {
+            value = value.fg(color);
+        }
         ratatui::macros::line![
-            span![format!("{:.1}", self.history.last().unwrap_or(&0.0))],
-            span![format!("{:<2}", self.unit.clone())].fg(self.ui.theme.primary_background)
+            value,
+            span![format!("{unit:<2}")].fg(self.ui.theme.primary_background)
         ]
         .alignment(Alignment::Right)
         .render(current, buf);
-        let resampled: Vec<Option<u64>> = crate::resample::resample(
+        let mut bins = crate::resample::resample(
             &self.history,
             &self.timestamps,
-            self.ui.time - Duration::from_secs(120),
+            self.ui.time - self.window,
             self.ui.time,
             history.width as usize,
-        )
-        .iter()
-        .map(|o| o.map(|v| v.trunc() as u64))
-        .collect();
+        );
+        if self.ui.interpolate {
+            crate::resample::interpolate_gaps(&mut bins);
+        }
+        if self.ui.absent_as_zero {
+            bins.iter_mut().for_each(|bin| *bin = bin.or(Some(0.0)));
+        }
+        if self.ui.chart_style == ChartStyle::Braille {
+            let bins: Vec<Option<f64>> = bins.iter().map(|o| o.map(f64::from)).collect();
+            self.render_braille(&bins, color, history, buf);
+            return;
+        }
+        let resampled: Vec<Option<u64>> =
+            bins.iter().map(|o| o.map(|v| v.trunc() as u64)).collect();
         // if ui.tick % TICK_FPS < 1.0 {
         //     debug!(
         //         target: "App",
@@ -88,11 +207,113 @@ impl<'a> Widget for &SingleStat<'a> {
         //         resampled
         //     );
         // }
-        Sparkline::default()
+        let mut sparkline = Sparkline::default()
             .data(&resampled)
             .max((self.max * 1.1) as u64)
-            .absent_value_symbol("_")
-            .fg(self.ui.theme.primary)
-            .render(history, buf);
+            .absent_value_symbol(self.ui.absent_symbol.as_str())
+            .fg(color);
+        if self.ui.chart_style == ChartStyle::Ascii {
+            sparkline = sparkline.bar_set(ASCII_BARS);
+        }
+        sparkline.render(history, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(style: ChartStyle) -> String {
+        let mut ui = UiState::default();
+        ui.chart_style = style;
+        let stats: Vec<ProcessStats> = (0..60)
+            .map(|i| ProcessStats {
+                timestamp: ui.time - Duration::from_secs(120 - i * 2),
+                cpu_percent: (i % 10) as f32 * 10.0,
+                ..Default::default()
+            })
+            .collect();
+        rendered_cpu(&ui, &stats)
+    }
+
+    /// The CPU sparkline, with a sample only every 30 seconds.
+    fn rendered_sparse(ui: &UiState) -> String {
+        let stats: Vec<ProcessStats> = (0..4)
+            .map(|i| ProcessStats {
+                timestamp: ui.time - Duration::from_secs(i * 30),
+                cpu_percent: 50.0,
+                ..Default::default()
+            })
+            .collect();
+        rendered_cpu(ui, &stats)
+    }
+
+    fn rendered_cpu(ui: &UiState, stats: &[ProcessStats]) -> String {
+        let max = ProcessStats {
+            cpu_percent: 90.0,
+            ..Default::default()
+        };
+        let (cpu, _) = split_stats(ui, stats, &max, &Thresholds::default());
+        let area = Rect::new(0, 0, 40, 1);
+        let mut buf = Buffer::empty(area);
+        cpu.render(area, &mut buf);
+        buf.content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn precision_suits_the_magnitude() {
+        assert_eq!(cpu_text(0.0), "0.00%");
+        assert_eq!(cpu_text(0.04), "0.04%");
+        assert_eq!(cpu_text(12.345), "12.3%");
+        assert_eq!(cpu_text(350.0), "350%");
+        assert_eq!(memory_text(0.5), "0.50MB");
+        assert_eq!(memory_text(16.34), "16.3MB");
+        assert_eq!(memory_text(512.0), "512MB");
+        assert_eq!(memory_text(2345.0), "2.3GB");
+        assert_eq!(memory_text(123_456.0), "123GB");
+    }
+
+    #[test]
+    fn gaps_are_drawn_with_the_absent_symbol_or_as_zero() {
+        let mut ui = UiState::default();
+        assert!(rendered_sparse(&ui).contains('_'));
+        ui.absent_symbol = "·".to_string();
+        let dotted = rendered_sparse(&ui);
+        assert!(dotted.contains('·') && !dotted.contains('_'));
+        ui.absent_as_zero = true;
+        assert!(!rendered_sparse(&ui).contains('·'));
+    }
+
+    #[test]
+    fn chart_styles_draw_with_their_own_characters() {
+        let braille = |c: char| ('\u{2800}'..='\u{28ff}').contains(&c);
+        let blocks = |c: char| ('\u{2581}'..='\u{2588}').contains(&c);
+        let bars = rendered(ChartStyle::Bars);
+        assert!(bars.chars().any(blocks));
+        let ascii = rendered(ChartStyle::Ascii);
+        assert!(ascii.is_ascii() && ascii.contains('#'));
+        let line = rendered(ChartStyle::Braille);
+        assert!(line.chars().any(braille) && !line.chars().any(blocks));
+    }
+
+    #[test]
+    fn history_fills_the_height_with_the_label_on_top() {
+        use crate::ui::testing::{render, running};
+
+        let ui = UiState::default();
+        let api = running("api", &ui, 30);
+        let (cpu, ram) = split_stats(&ui, &api.stats, &api.stats_max, &api.thresholds);
+        assert_eq!(
+            render(&cpu, 40, 3),
+            [
+                " ________________   ▁▃▅ CPU:    30.0%   ",
+                " ________________▃▅▇███                 ",
+                " ________________██████                 ",
+            ]
+        );
+        assert_eq!(
+            render(&ram, 40, 1),
+            [" ________________▆▆▆▆▇▇ RAM:     160MB  "]
+        );
     }
 }
@@ -1,15 +1,48 @@
-use std::{fmt::Debug, time::Instant};
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
-use crate::{event::TICK_FPS, ui::theme::Theme};
+use crate::{
+    config::{ChartStyle, CpuMode, FocusOnCrash},
+    event::TICK_FPS,
+    proc::{
+        process::{Process, ProcessKind},
+        stats::HISTORY_KEPT,
+    },
+    ui::{stat_line::HISTORY_WINDOW, theme::Theme},
+};
 use tui_logger::*;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Focussable {
+    /// The combined stats of all processes, above the grid.
+    Totals,
     Process(usize),
     Logs,
     Debug,
 }
 
+/// The choices of how far back the combined stats reach.
+const TOTALS_WINDOWS: [Duration; 4] = [
+    Duration::from_secs(30),
+    HISTORY_WINDOW,
+    Duration::from_secs(600),
+    HISTORY_KEPT,
+];
+
+/// Drawn in the sparklines where there is no sample, unless configured.
+pub const DEFAULT_ABSENT_SYMBOL: &str = "_";
+
+/// A spatial move of the focus around the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusMove {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
 /// The main UI mode
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Mode {
@@ -19,10 +52,15 @@ pub enum Mode {
     Spotlight,
     /// Large log split view
     Logs,
+    /// One row per process, for stacks too big for cards
+    List,
 }
 
 pub struct UiState {
+    /// Animation phase counter, wraps every two seconds.
     pub tick: f64,
+    pub tick_fps: f64,
+    /// Wall-clock time of the latest tick.
     pub time: Instant,
     pub proc_columns: usize,
     pub proc_rows: usize,
@@ -30,7 +68,43 @@ pub struct UiState {
     pub procs: usize,
     pub focus: Option<Focussable>,
     pub mode: Mode,
+    /// The mode to go back to when the spotlight closes.
+    under_spotlight: Mode,
     pub debug: bool,
+    /// Fill gaps in the history sparklines rather than showing them as absent.
+    pub interpolate: bool,
+    pub chart_style: ChartStyle,
+    /// Drawn in the sparklines where there is no sample.
+    pub absent_symbol: String,
+    /// Draw bins without a sample as zero rather than absent.
+    pub absent_as_zero: bool,
+    /// Lines each card takes, if not just enough for its contents.
+    pub card_height: Option<u16>,
+    pub cpu_mode: CpuMode,
+    /// Cores available, shown with CPU use in the spotlight.
+    pub cores: usize,
+    /// Show the combined CPU and RAM of all processes above the cards.
+    pub show_totals: bool,
+    /// How far back the combined stats reach.
+    pub totals_window: Duration,
+    /// Only show processes whose name or display name contains this,
+    /// ignoring case.
+    pub filter: String,
+    /// Only show processes of this kind.
+    pub kind_filter: Option<ProcessKind>,
+    /// Whether to turn to a process that crashes.
+    pub focus_on_crash: FocusOnCrash,
+    /// Names of processes in the order they have been moved into. Any not
+    /// named follow in config order. Kept across reloads and restarts.
+    pub order: Vec<String>,
+    /// Keys are going to the search box rather than the usual handlers.
+    pub searching: bool,
+    /// Keys are going to the spotlit process's stdin.
+    pub attached: bool,
+    /// The log panel shows new lines as they arrive rather than staying put.
+    pub log_follow: bool,
+    /// Pages scrolled up from the newest log line.
+    log_pages_up: usize,
     pub logger_state: TuiWidgetState,
 }
 
@@ -38,12 +112,30 @@ impl Debug for UiState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("UiState")
             .field("tick", &self.tick)
+            .field("tick_fps", &self.tick_fps)
             .field("time", &self.time)
             .field("proc_columns", &self.proc_columns)
             .field("proc_rows", &self.proc_rows)
             .field("procs", &self.procs)
             .field("mode", &self.mode)
+            .field("under_spotlight", &self.under_spotlight)
             .field("focus", &self.focus)
+            .field("interpolate", &self.interpolate)
+            .field("chart_style", &self.chart_style)
+            .field("absent_symbol", &self.absent_symbol)
+            .field("absent_as_zero", &self.absent_as_zero)
+            .field("card_height", &self.card_height)
+            .field("cpu_mode", &self.cpu_mode)
+            .field("cores", &self.cores)
+            .field("show_totals", &self.show_totals)
+            .field("totals_window", &self.totals_window)
+            .field("filter", &self.filter)
+            .field("kind_filter", &self.kind_filter)
+            .field("focus_on_crash", &self.focus_on_crash)
+            .field("order", &self.order)
+            .field("searching", &self.searching)
+            .field("attached", &self.attached)
+            .field("log_follow", &self.log_follow)
             .finish()
     }
 }
@@ -53,37 +145,63 @@ impl Default for UiState {
         Self {
             logger_state: TuiWidgetState::new(),
             tick: Default::default(),
+            tick_fps: TICK_FPS,
             time: Instant::now(),
             proc_columns: 2,
             proc_rows: 3,
             procs: 0,
             theme: Theme::dark(),
             mode: Mode::Dashboard,
+            under_spotlight: Mode::Dashboard,
             focus: None,
             debug: false,
+            interpolate: false,
+            chart_style: ChartStyle::default(),
+            absent_symbol: DEFAULT_ABSENT_SYMBOL.to_string(),
+            absent_as_zero: false,
+            card_height: None,
+            cpu_mode: CpuMode::default(),
+            cores: 1,
+            show_totals: true,
+            totals_window: HISTORY_WINDOW,
+            filter: String::new(),
+            kind_filter: None,
+            focus_on_crash: FocusOnCrash::Off,
+            order: Vec::new(),
+            searching: false,
+            attached: false,
+            log_follow: true,
+            log_pages_up: 0,
         }
     }
 }
 
 impl UiState {
+    pub fn with_tick_fps(tick_fps: f64) -> Self {
+        Self {
+            tick_fps,
+            ..Default::default()
+        }
+    }
+
     pub fn tick(&mut self) {
+        self.time = Instant::now();
         self.tick += 1.0;
-        if self.tick > 2.0 * TICK_FPS {
+        if self.tick > 2.0 * self.tick_fps {
             self.tick = 0.0;
-            self.time = Instant::now();
         }
     }
 
     pub fn step_of_8_in_1_second(&self) -> usize {
-        (self.tick * 8.0 / TICK_FPS) as usize % 8
+        (self.tick * 8.0 / self.tick_fps) as usize % 8
     }
 
     pub fn step_of_4_in_1_second(&self) -> usize {
-        (self.tick * 4.0 / TICK_FPS) as usize % 4
+        (self.tick * 4.0 / self.tick_fps) as usize % 4
     }
 
     pub fn step_of_8_in_2_second(&self) -> usize {
-        (self.tick * 4.0 / TICK_FPS) as usize % 8
+        (self.tick * 4.0 / self.tick_fps) as usize % 8
     }
 
     pub fn toggle_debug(&mut self) {
@@ -95,49 +213,188 @@ impl UiState {
         }
     }
 
+    pub fn toggle_interpolate(&mut self) {
+        self.interpolate = !self.interpolate;
+    }
+
+    pub fn toggle_totals(&mut self) {
+        self.show_totals = !self.show_totals;
+        if !self.show_totals
+            && let Some(Focussable::Totals) = &self.focus
+        {
+            self.focus = Some(Focussable::Process(0));
+        }
+    }
+
+    /// Show a longer stretch of the combined stats, up to the longest window.
+    pub fn widen_totals_window(&mut self) {
+        let wider = TOTALS_WINDOWS.iter().find(|w| **w > self.totals_window);
+        self.totals_window = *wider.unwrap_or(&self.totals_window);
+    }
+
+    /// Show a shorter stretch of the combined stats, down to the shortest
+    /// window.
+    pub fn narrow_totals_window(&mut self) {
+        let narrower = TOTALS_WINDOWS
+            .iter()
+            .rev()
+            .find(|w| **w < self.totals_window);
+        self.totals_window = *narrower.unwrap_or(&self.totals_window);
+    }
+
+    /// Scroll the logs up a page, which stops following new lines.
+    pub fn log_page_up(&mut self) {
+        self.log_pages_up += 1;
+        self.log_follow = false;
+        self.logger_state.transition(TuiWidgetEvent::PrevPageKey);
+    }
+
+    /// Scroll the logs down a page, following again once back at the bottom.
+    pub fn log_page_down(&mut self) {
+        if self.log_pages_up <= 1 {
+            self.log_follow_latest();
+        } else {
+            self.log_pages_up -= 1;
+            self.logger_state.transition(TuiWidgetEvent::NextPageKey);
+        }
+    }
+
+    /// Jump to the newest log line and follow from there.
+    pub fn log_follow_latest(&mut self) {
+        self.log_pages_up = 0;
+        self.log_follow = true;
+        self.logger_state.transition(TuiWidgetEvent::EscapeKey);
+    }
+
+    /// Everything focus can cycle through, in order: the totals, the
+    /// processes, the logs and the debug panel, skipping hidden panels.
+    fn focus_ring(&self) -> Vec<Focussable> {
+        let totals = self.show_totals.then_some(Focussable::Totals);
+        let processes = (0..self.procs).map(Focussable::Process);
+        let debug = self.debug.then_some(Focussable::Debug);
+        totals
+            .into_iter()
+            .chain(processes)
+            .chain([Focussable::Logs])
+            .chain(debug)
+            .collect()
+    }
+
     pub fn focus_next(&mut self) {
-        self.focus = match &self.focus {
+        let ring = self.focus_ring();
+        self.focus = match ring.iter().position(|f| Some(f) == self.focus.as_ref()) {
+            Some(i) => Some(ring[(i + 1) % ring.len()].clone()),
             None => Some(Focussable::Process(0)),
-            Some(Focussable::Process(i)) => {
-                if i + 1 < self.procs {
-                    Some(Focussable::Process(i + 1))
-                } else {
-                    Some(Focussable::Logs)
-                }
-            }
-            Some(Focussable::Logs) => {
-                if self.debug {
-                    Some(Focussable::Debug)
-                } else {
-                    Some(Focussable::Process(0))
-                }
-            }
-            Some(Focussable::Debug) => Some(Focussable::Process(0)),
         }
     }
 
     pub fn focus_prev(&mut self) {
-        self.focus = match &self.focus {
+        let ring = self.focus_ring();
+        self.focus = match ring.iter().position(|f| Some(f) == self.focus.as_ref()) {
+            Some(i) => Some(ring[(i + ring.len() - 1) % ring.len()].clone()),
             None => Some(Focussable::Process(0)),
-            Some(Focussable::Process(i)) => {
-                if *i > 0 {
-                    Some(Focussable::Process(i - 1))
+        }
+    }
+
+    /// Move focus spatially around the process grid and the panels next to it.
+    ///
+    /// Processes fill the grid row by row. The totals sit above the grid, the
+    /// debug panel to its right and the logs along the bottom of the screen.
+    pub fn focus_move(&mut self, dir: FocusMove) {
+        if self.listing() {
+            self.focus_move_in_list(dir);
+            return;
+        }
+        let cols = self.proc_columns.max(1);
+        let visible = self.procs.min(cols * self.proc_rows);
+        let first_in_last_row = visible.saturating_sub(1) / cols * cols;
+        self.focus = match (&self.focus, dir) {
+            (None, _) if visible > 0 => Some(Focussable::Process(0)),
+            (None, _) => Some(Focussable::Logs),
+            (Some(Focussable::Process(i)), _) if *i >= visible => self.focus.clone(),
+            (Some(Focussable::Process(i)), FocusMove::Left) => {
+                Some(Focussable::Process(i - usize::from(i % cols > 0)))
+            }
+            (Some(Focussable::Process(i)), FocusMove::Right) => {
+                if i % cols + 1 < cols && i + 1 < visible {
+                    Some(Focussable::Process(i + 1))
                 } else if self.debug {
                     Some(Focussable::Debug)
                 } else {
-                    Some(Focussable::Logs)
+                    self.focus.clone()
                 }
             }
-            Some(Focussable::Logs) => {
-                if self.procs > 0 {
-                    Some(Focussable::Process(self.procs - 1))
-                } else if self.debug {
-                    Some(Focussable::Debug)
+            (Some(Focussable::Process(i)), FocusMove::Up) if *i < cols && self.show_totals => {
+                Some(Focussable::Totals)
+            }
+            (Some(Focussable::Process(i)), FocusMove::Up) => {
+                Some(Focussable::Process(i.checked_sub(cols).unwrap_or(*i)))
+            }
+            (Some(Focussable::Process(i)), FocusMove::Down) => {
+                if i + cols < visible {
+                    Some(Focussable::Process(i + cols))
+                } else if *i < first_in_last_row {
+                    Some(Focussable::Process(visible - 1))
                 } else {
                     Some(Focussable::Logs)
                 }
             }
-            Some(Focussable::Debug) => Some(Focussable::Logs),
+            (Some(Focussable::Logs), FocusMove::Up) if visible > 0 => {
+                Some(Focussable::Process(first_in_last_row))
+            }
+            (Some(Focussable::Logs), FocusMove::Right) if self.debug => Some(Focussable::Debug),
+            (Some(Focussable::Debug), FocusMove::Left) if visible > 0 => {
+                Some(Focussable::Process(cols.min(visible) - 1))
+            }
+            (Some(Focussable::Debug), FocusMove::Down) => Some(Focussable::Logs),
+            (Some(Focussable::Totals), FocusMove::Down) if visible > 0 => {
+                Some(Focussable::Process(0))
+            }
+            (Some(Focussable::Totals), FocusMove::Down) => Some(Focussable::Logs),
+            (Some(Focussable::Totals), FocusMove::Right) if self.debug => Some(Focussable::Debug),
+            (Some(_), _) => self.focus.clone(),
+        }
+    }
+
+    /// Move focus a row at a time through the list, which has the totals
+    /// above, the debug panel to its right and the logs below.
+    fn focus_move_in_list(&mut self, dir: FocusMove) {
+        self.focus = match (&self.focus, dir) {
+            (None, _) if self.procs > 0 => Some(Focussable::Process(0)),
+            (None, _) => Some(Focussable::Logs),
+            (Some(Focussable::Process(0)), FocusMove::Up) if self.show_totals => {
+                Some(Focussable::Totals)
+            }
+            (Some(Focussable::Totals), FocusMove::Down) if self.procs > 0 => {
+                Some(Focussable::Process(0))
+            }
+            (Some(Focussable::Totals), FocusMove::Down) => Some(Focussable::Logs),
+            (Some(Focussable::Process(i)), FocusMove::Up) => {
+                Some(Focussable::Process(i.saturating_sub(1)))
+            }
+            (Some(Focussable::Process(i)), FocusMove::Down) if i + 1 < self.procs => {
+                Some(Focussable::Process(i + 1))
+            }
+            (Some(Focussable::Process(_)), FocusMove::Down) => Some(Focussable::Logs),
+            (
+                Some(Focussable::Totals | Focussable::Process(_) | Focussable::Logs),
+                FocusMove::Right,
+            ) if self.debug => Some(Focussable::Debug),
+            (Some(Focussable::Logs), FocusMove::Up) if self.procs > 0 => {
+                Some(Focussable::Process(self.procs - 1))
+            }
+            (Some(Focussable::Debug), FocusMove::Left) if self.procs > 0 => {
+                Some(Focussable::Process(0))
+            }
+            (Some(Focussable::Debug), FocusMove::Down) => Some(Focussable::Logs),
+            (Some(_), _) => self.focus.clone(),
+        }
+    }
+
+    /// Focus the process at `index` if there is one.
+    pub fn focus_process(&mut self, index: usize) {
+        if index < self.procs {
+            self.focus = Some(Focussable::Process(index));
         }
     }
 
@@ -154,19 +411,380 @@ impl UiState {
         }
     }
 
+    /// Send keys to the focused process, if it is in the spotlight.
+    pub fn attach(&mut self) {
+        self.attached =
+            self.mode == Mode::Spotlight && matches!(self.focus, Some(Focussable::Process(_)));
+    }
+
+    pub fn detach(&mut self) {
+        self.attached = false;
+    }
+
+    /// Start typing a search query, keeping any current one.
+    pub fn start_search(&mut self) {
+        self.searching = true;
+    }
+
+    pub fn search_push(&mut self, c: char) {
+        self.filter.push(c);
+    }
+
+    pub fn search_pop(&mut self) {
+        self.filter.pop();
+    }
+
+    /// Stop typing but keep filtering by the query.
+    pub fn finish_search(&mut self) {
+        self.searching = false;
+    }
+
+    /// Stop typing and show every process again.
+    pub fn clear_search(&mut self) {
+        self.searching = false;
+        self.filter.clear();
+    }
+
+    /// Whether a process with this name and display name passes the filter.
+    pub fn matches(&self, name: &str, display: &str) -> bool {
+        let filter = self.filter.to_lowercase();
+        name.to_lowercase().contains(&filter) || display.to_lowercase().contains(&filter)
+    }
+
+    /// Show every process, then only services, then only stubs.
+    pub fn cycle_kind_filter(&mut self) {
+        self.kind_filter = match self.kind_filter {
+            None => Some(ProcessKind::Service),
+            Some(ProcessKind::Service) => Some(ProcessKind::Stub),
+            Some(_) => None,
+        };
+    }
+
+    /// Every process, in the order it has been moved into.
+    fn arranged<'a>(&self, processes: &'a [Process]) -> Vec<&'a Process> {
+        let mut arranged: Vec<&Process> = processes.iter().collect();
+        arranged.sort_by_key(|p| {
+            self.order
+                .iter()
+                .position(|name| *name == p.name)
+                .unwrap_or(usize::MAX)
+        });
+        arranged
+    }
+
+    /// The processes passing the filters, in grid order. Focus indexes into
+    /// this.
+    pub fn visible<'a>(&self, processes: &'a [Process]) -> Vec<&'a Process> {
+        self.arranged(processes)
+            .into_iter()
+            .filter(|p| self.kind_filter.is_none_or(|kind| p.kind == kind))
+            .filter(|p| self.matches(&p.name, &p.display))
+            .collect()
+    }
+
+    /// Swap the focused process with the one next to it in the grid, or
+    /// the list, and keep it focused. The new order is remembered by name.
+    pub fn move_focused(&mut self, processes: &[Process], dir: FocusMove) {
+        let Some(Focussable::Process(i)) = self.focus else {
+            return;
+        };
+        if self.mode == Mode::Spotlight {
+            return;
+        }
+        let cols = if self.listing() {
+            1
+        } else {
+            self.proc_columns.max(1)
+        };
+        let target = match dir {
+            FocusMove::Left if cols > 1 && i % cols > 0 => Some(i - 1),
+            FocusMove::Right if cols > 1 && i % cols + 1 < cols => Some(i + 1),
+            FocusMove::Up => i.checked_sub(cols),
+            FocusMove::Down => Some(i + cols),
+            _ => None,
+        };
+        let visible = self.visible(processes);
+        let (Some(from), Some(to)) = (visible.get(i), target.and_then(|j| visible.get(j))) else {
+            return;
+        };
+        let (from, to) = (from.name.clone(), to.name.clone());
+        let mut order: Vec<String> = self
+            .arranged(processes)
+            .iter()
+            .map(|p| p.name.clone())
+            .collect();
+        let a = order.iter().position(|name| *name == from);
+        let b = order.iter().position(|name| *name == to);
+        if let (Some(a), Some(b)) = (a, b) {
+            order.swap(a, b);
+        }
+        self.order = order;
+        self.focus = target.map(Focussable::Process);
+    }
+
+    /// Turn to `name`, which has just crashed, if `focus_on_crash` says to
+    /// and it is shown. Left alone while typing a search or into a process.
+    pub fn focus_crashed(&mut self, processes: &[Process], name: &str) {
+        if self.focus_on_crash == FocusOnCrash::Off || self.searching || self.attached {
+            return;
+        }
+        let Some(i) = self.visible(processes).iter().position(|p| p.name == name) else {
+            return;
+        };
+        self.focus = Some(Focussable::Process(i));
+        if self.focus_on_crash == FocusOnCrash::Spotlight && self.mode != Mode::Spotlight {
+            self.toggle_spotlight();
+        }
+    }
+
     pub fn toggle_spotlight(&mut self) {
         if self.mode == Mode::Spotlight {
-            self.mode = Mode::Dashboard;
+            self.mode = self.under_spotlight.clone();
+            self.attached = false;
         } else {
+            self.under_spotlight = self.mode.clone();
             self.mode = Mode::Spotlight;
         }
     }
+
+    /// Switch between the card grid and the list, even under the spotlight.
+    pub fn toggle_list(&mut self) {
+        let mode = if self.mode == Mode::Spotlight {
+            &mut self.under_spotlight
+        } else {
+            &mut self.mode
+        };
+        *mode = if *mode == Mode::List {
+            Mode::Dashboard
+        } else {
+            Mode::List
+        };
+    }
+
+    /// Whether processes are shown as a list rather than cards.
+    pub fn listing(&self) -> bool {
+        self.mode == Mode::List
+            || (self.mode == Mode::Spotlight && self.under_spotlight == Mode::List)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn scrolling_back_to_the_bottom_follows_again() {
+        let mut ui = UiState::default();
+        ui.log_page_up();
+        ui.log_page_up();
+        assert!(!ui.log_follow);
+        ui.log_page_down();
+        assert!(!ui.log_follow);
+        ui.log_page_down();
+        assert!(ui.log_follow);
+        ui.log_page_up();
+        ui.log_follow_latest();
+        assert!(ui.log_follow);
+    }
+
+    #[test]
+    fn spotlight_closes_back_to_the_list() {
+        let mut ui = UiState::default();
+        ui.toggle_list();
+        ui.toggle_spotlight();
+        assert!(ui.listing());
+        ui.toggle_spotlight();
+        assert_eq!(ui.mode, Mode::List);
+        ui.toggle_list();
+        assert_eq!(ui.mode, Mode::Dashboard);
+    }
+
+    #[test]
+    fn list_moves_row_by_row() {
+        let mut ui = grid();
+        ui.toggle_list();
+        let start = Focussable::Process(0);
+        let last = Focussable::Process(ui.procs - 1);
+        assert_eq!(
+            moves(&mut ui, start.clone(), &[FocusMove::Down, FocusMove::Down]),
+            Some(Focussable::Process(2))
+        );
+        assert_eq!(
+            moves(&mut ui, start.clone(), &[FocusMove::Left]),
+            Some(start.clone())
+        );
+        assert_eq!(
+            moves(&mut ui, start.clone(), &[FocusMove::Up]),
+            Some(Focussable::Totals)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Totals, &[FocusMove::Down]),
+            Some(start)
+        );
+        assert_eq!(
+            moves(&mut ui, last.clone(), &[FocusMove::Down]),
+            Some(Focussable::Logs)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Logs, &[FocusMove::Up]),
+            Some(last.clone())
+        );
+    }
+
+    fn names(ui: &UiState, processes: &[Process]) -> Vec<String> {
+        ui.visible(processes)
+            .iter()
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn moving_a_card_swaps_it_with_its_neighbour() {
+        use crate::ui::testing::process;
+        let processes = ["a", "b", "c", "d"].map(process);
+        let mut ui = UiState {
+            focus: Some(Focussable::Process(0)),
+            ..Default::default()
+        };
+
+        ui.move_focused(&processes, FocusMove::Left);
+        assert_eq!(names(&ui, &processes), ["a", "b", "c", "d"]);
+        ui.move_focused(&processes, FocusMove::Right);
+        assert_eq!(names(&ui, &processes), ["b", "a", "c", "d"]);
+        assert_eq!(ui.focus, Some(Focussable::Process(1)));
+        ui.move_focused(&processes, FocusMove::Down);
+        assert_eq!(names(&ui, &processes), ["b", "d", "c", "a"]);
+        assert_eq!(ui.focus, Some(Focussable::Process(3)));
+        ui.move_focused(&processes, FocusMove::Down);
+        assert_eq!(ui.focus, Some(Focussable::Process(3)));
+
+        ui.toggle_list();
+        ui.move_focused(&processes, FocusMove::Up);
+        assert_eq!(names(&ui, &processes), ["b", "d", "a", "c"]);
+        assert_eq!(ui.focus, Some(Focussable::Process(2)));
+    }
+
+    #[test]
+    fn order_is_kept_by_name_as_processes_come_and_go() {
+        use crate::ui::testing::process;
+        let mut ui = UiState {
+            focus: Some(Focussable::Process(0)),
+            ..Default::default()
+        };
+        ui.move_focused(&["api", "db", "web"].map(process), FocusMove::Right);
+
+        let reloaded = ["web", "new", "api", "db"].map(process);
+        assert_eq!(names(&ui, &reloaded), ["db", "api", "web", "new"]);
+
+        // Moves within a filtered view swap just the two shown.
+        ui.filter = "b".to_string();
+        ui.focus = Some(Focussable::Process(0));
+        ui.move_focused(&reloaded, FocusMove::Right);
+        ui.filter.clear();
+        assert_eq!(names(&ui, &reloaded), ["web", "api", "db", "new"]);
+    }
+
+    #[test]
+    fn crash_takes_focus_only_when_asked_and_shown() {
+        use crate::ui::testing::process;
+        let processes = ["api", "db", "web"].map(process);
+        let mut ui = UiState {
+            focus: Some(Focussable::Process(0)),
+            order: vec!["web".to_string()],
+            ..Default::default()
+        };
+        ui.focus_crashed(&processes, "db");
+        assert_eq!(ui.focus, Some(Focussable::Process(0)));
+
+        ui.focus_on_crash = FocusOnCrash::Focus;
+        ui.focus_crashed(&processes, "db");
+        assert_eq!(ui.focus, Some(Focussable::Process(2)));
+        assert_eq!(ui.mode, Mode::Dashboard);
+
+        ui.filter = "w".to_string();
+        ui.focus_crashed(&processes, "api");
+        assert_eq!(ui.focus, Some(Focussable::Process(2)));
+        ui.filter.clear();
+
+        ui.focus_on_crash = FocusOnCrash::Spotlight;
+        ui.focus_crashed(&processes, "web");
+        assert_eq!(ui.focus, Some(Focussable::Process(0)));
+        assert_eq!(ui.mode, Mode::Spotlight);
+        ui.attach();
+        ui.focus_crashed(&processes, "api");
+        assert_eq!(ui.focus, Some(Focussable::Process(0)));
+    }
+
+    #[test]
+    fn attach_only_in_spotlight() {
+        let mut ui = grid();
+        ui.focus = Some(Focussable::Process(1));
+        ui.attach();
+        assert!(!ui.attached);
+        ui.toggle_spotlight();
+        ui.attach();
+        assert!(ui.attached);
+        ui.toggle_spotlight();
+        assert!(!ui.attached);
+    }
+
+    #[test]
+    fn kind_filter_cycles_through_services_and_stubs() {
+        use crate::config::{Service, Stub};
+        let processes = [
+            Process::new(&Service {
+                name: "api".to_string(),
+                command: Some("./api".to_string()),
+                ..Default::default()
+            })
+            .unwrap(),
+            Process::new(&Stub {
+                name: "payments".to_string(),
+                command: Some("./stub".to_string()),
+                ..Default::default()
+            })
+            .unwrap(),
+        ];
+        let names = |ui: &UiState| -> Vec<String> {
+            ui.visible(&processes)
+                .iter()
+                .map(|p| p.name.clone())
+                .collect()
+        };
+        let mut ui = UiState::default();
+        assert_eq!(names(&ui), ["api", "payments"]);
+        ui.cycle_kind_filter();
+        assert_eq!(names(&ui), ["api"]);
+        ui.cycle_kind_filter();
+        assert_eq!(names(&ui), ["payments"]);
+        ui.cycle_kind_filter();
+        assert_eq!(ui.kind_filter, None);
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let ui = UiState::default();
+        assert!(ui.matches("api", "API server"));
+    }
+
+    #[test]
+    fn filter_matches_name_or_display_ignoring_case() {
+        let mut ui = UiState::default();
+        ui.start_search();
+        for c in "SERV".chars() {
+            ui.search_push(c);
+        }
+        assert!(ui.matches("api", "API server"));
+        assert!(ui.matches("service-1", "Dummy"));
+        assert!(!ui.matches("db", "Postgres"));
+        ui.search_pop();
+        ui.finish_search();
+        assert!(!ui.searching);
+        assert_eq!(ui.filter, "SER");
+        ui.clear_search();
+        assert!(ui.matches("db", "Postgres"));
+    }
+
     const TICKS_AND_STEPS: [(usize, usize, usize, usize); 13] = [
         (0, 0, 0, 0),
         (1, 0, 0, 0),
@@ -183,6 +801,213 @@ mod tests {
         (15, 0, 0, 0),
     ];
 
+    /// A 2x3 grid holding 5 processes, leaving the bottom right cell empty:
+    ///
+    /// ```"not rust"
+    /// 0 1 |
+    /// 2 3 | Debug
+    /// 4 _ |
+    /// Logs
+    /// ```
+    fn grid() -> UiState {
+        let mut ui = UiState::default();
+        ui.update_procs(5);
+        ui.debug = true;
+        ui
+    }
+
+    fn moves(ui: &mut UiState, start: Focussable, moves: &[FocusMove]) -> Option<Focussable> {
+        ui.focus = Some(start);
+        for m in moves {
+            ui.focus_move(*m);
+        }
+        ui.focus.clone()
+    }
+
+    #[test]
+    fn grid_moves_within_the_grid() {
+        use FocusMove::*;
+        let mut ui = grid();
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(0), &[Right]),
+            Some(Focussable::Process(1))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(0), &[Down, Down]),
+            Some(Focussable::Process(4))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(3), &[Up, Left]),
+            Some(Focussable::Process(0))
+        );
+    }
+
+    #[test]
+    fn grid_moves_clamp_at_edges() {
+        use FocusMove::*;
+        let mut ui = grid();
+        ui.show_totals = false;
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(0), &[Left, Up]),
+            Some(Focussable::Process(0))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(3), &[Down]),
+            Some(Focussable::Process(4))
+        );
+        ui.debug = false;
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(4), &[Right]),
+            Some(Focussable::Process(4))
+        );
+    }
+
+    #[test]
+    fn grid_moves_reach_the_panels() {
+        use FocusMove::*;
+        let mut ui = grid();
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(1), &[Right]),
+            Some(Focussable::Debug)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(4), &[Right]),
+            Some(Focussable::Debug)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(4), &[Down]),
+            Some(Focussable::Logs)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Logs, &[Up]),
+            Some(Focussable::Process(4))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Debug, &[Left]),
+            Some(Focussable::Process(1))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Debug, &[Down]),
+            Some(Focussable::Logs)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Process(1), &[Up]),
+            Some(Focussable::Totals)
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Totals, &[Down]),
+            Some(Focussable::Process(0))
+        );
+        assert_eq!(
+            moves(&mut ui, Focussable::Totals, &[Right]),
+            Some(Focussable::Debug)
+        );
+    }
+
+    #[test]
+    fn tab_cycles_from_the_totals_down_to_the_debug_panel() {
+        let mut ui = grid();
+        let mut order = vec![];
+        ui.focus = Some(Focussable::Totals);
+        for _ in 0..9 {
+            order.extend(ui.focus.clone());
+            ui.focus_next();
+        }
+        use Focussable::*;
+        assert_eq!(
+            order,
+            [
+                Totals,
+                Process(0),
+                Process(1),
+                Process(2),
+                Process(3),
+                Process(4),
+                Logs,
+                Debug,
+                Totals
+            ]
+        );
+        ui.focus = Some(Totals);
+        for expected in order.iter().rev().skip(1) {
+            ui.focus_prev();
+            assert_eq!(ui.focus.as_ref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn hidden_panels_drop_out_of_the_cycle() {
+        let mut ui = grid();
+        ui.debug = false;
+        ui.focus = Some(Focussable::Logs);
+        ui.focus_next();
+        assert_eq!(ui.focus, Some(Focussable::Totals));
+        ui.toggle_totals();
+        assert_eq!(ui.focus, Some(Focussable::Process(0)));
+        ui.focus_prev();
+        assert_eq!(ui.focus, Some(Focussable::Logs));
+    }
+
+    #[test]
+    fn totals_window_steps_between_limits() {
+        let mut ui = UiState::default();
+        ui.narrow_totals_window();
+        ui.narrow_totals_window();
+        assert_eq!(ui.totals_window, Duration::from_secs(30));
+        for _ in 0..5 {
+            ui.widen_totals_window();
+        }
+        assert_eq!(ui.totals_window, Duration::from_secs(1800));
+    }
+
+    #[test]
+    fn focus_process_ignores_out_of_range() {
+        let mut ui = UiState::default();
+        ui.update_procs(3);
+        ui.focus_process(2);
+        assert_eq!(ui.focus, Some(Focussable::Process(2)));
+        ui.focus_process(3);
+        assert_eq!(ui.focus, Some(Focussable::Process(2)));
+    }
+
+    #[test]
+    fn throbs_follow_the_tick_rate() {
+        let mut t = UiState::with_tick_fps(10.0);
+        // (ticks, 4/1, 8/1, 8/2) at 10 ticks a second
+        for (ticks, s4i1, s8i1, s8i2) in [
+            (2, 0, 1, 0),
+            (1, 1, 2, 1),
+            (2, 2, 4, 2),
+            (5, 0, 0, 4),
+            (10, 0, 0, 0),
+            (1, 0, 0, 0),
+        ] {
+            for _ in 0..ticks {
+                t.tick();
+            }
+            assert_eq!(
+                (
+                    t.step_of_4_in_1_second(),
+                    t.step_of_8_in_1_second(),
+                    t.step_of_8_in_2_second()
+                ),
+                (s4i1, s8i1, s8i2),
+                "At tick {}",
+                t.tick
+            );
+        }
+    }
+
+    #[test]
+    fn time_advances_every_tick() {
+        let mut t = UiState::default();
+        let before = t.time;
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        t.tick();
+        assert!(t.time > before);
+        assert_eq!(t.tick, 1.0);
+    }
+
     #[test]
     fn all_the_throbs() {
         let mut t = UiState::default();
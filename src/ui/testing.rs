@@ -0,0 +1,58 @@
+//! Helpers for rendering widgets in tests.
+//!
+//! Widgets are drawn into a [`TestBackend`] and read back as plain text, one
+//! string per line, so tests can compare them with what the screen should
+//! show. Colours and other styles are left out.
+
+use std::time::Duration;
+
+use ratatui::{Terminal, backend::TestBackend, widgets::Widget};
+
+use crate::{
+    config::Service,
+    proc::{
+        process::{Process, ProcessState},
+        stats::ProcessStats,
+    },
+    ui::state::UiState,
+};
+
+/// `widget` drawn into a `width` by `height` screen, as lines of text.
+pub fn render(widget: impl Widget, width: u16, height: u16) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|frame| frame.render_widget(widget, frame.area()))
+        .unwrap();
+    let buf = terminal.backend().buffer();
+    (0..height)
+        .map(|y| (0..width).map(|x| buf[(x, y)].symbol()).collect())
+        .collect()
+}
+
+/// A process for `name` that hasn't been started.
+pub fn process(name: &str) -> Process {
+    Process::new(&Service {
+        name: name.to_string(),
+        command: Some(format!("./{name}")),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+/// A process for `name`, up for a minute as of `ui.time`, with a stats
+/// sample every second for the last `samples` seconds.
+pub fn running(name: &str, ui: &UiState, samples: u64) -> Process {
+    let mut process = process(name);
+    process.state = ProcessState::Running;
+    process.last_start = Some(ui.time - Duration::from_secs(60));
+    for ago in (0..samples).rev() {
+        let at = 60 - ago;
+        process.push_stats(ProcessStats {
+            timestamp: ui.time - Duration::from_secs(ago),
+            cpu_percent: at as f32 / 2.0,
+            memory_mb: 100.0 + at as f32,
+            uptime: Duration::from_secs(at),
+        });
+    }
+    process
+}
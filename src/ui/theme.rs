@@ -6,6 +6,8 @@
 
 use ratatui::style::Color;
 
+use crate::proc::stats::Alert;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Theme {
     pub primary: Color,
@@ -42,37 +44,170 @@ impl Theme {
         }
     }
 
+    /// No colours at all, leaving the terminal's own, for `--color never`
+    /// and `NO_COLOR`.
+    pub const fn monochrome() -> Self {
+        Self {
+            primary: Color::Reset,
+            secondary: Color::Reset,
+            primary_background: Color::Reset,
+            secondary_background: Color::Reset,
+            accent: Color::Reset,
+            warning: Color::Reset,
+            error: Color::Reset,
+            success: Color::Reset,
+            foreground: Color::Reset,
+            background: Color::Reset,
+            surface: Color::Reset,
+            panel: Color::Reset,
+            boost: Color::Reset,
+        }
+    }
+
+    /// Border color for a focused widget whose border is normally `base`.
+    pub fn focus_border(base: Color) -> Color {
+        Self::lighten(base, 0.4)
+    }
+
+    /// Border color for an unfocused widget whose border is normally `base`.
+    pub fn blur_border(base: Color) -> Color {
+        Self::darken(base, 0.5)
+    }
+
+    /// Pick the focused or unfocused shade of `base`.
+    pub fn border(base: Color, focussed: bool) -> Color {
+        if focussed {
+            Self::focus_border(base)
+        } else {
+            Self::blur_border(base)
+        }
+    }
+
+    /// Colour for something normally drawn in `normal` at the given alert level.
+    pub fn alert(&self, alert: Alert, normal: Color) -> Color {
+        match alert {
+            Alert::Normal => normal,
+            Alert::Warning => self.warning,
+            Alert::Critical => self.error,
+        }
+    }
+
     /// Lighten a color by blending with white
     /// factor should be between 0.0 (no change) and 1.0 (white)
     pub fn lighten(color: Color, factor: f32) -> Color {
         let factor = factor.clamp(0.0, 1.0);
-        match color {
-            Color::Rgb(r, g, b) => {
-                let r = r as f32 + (255.0 - r as f32) * factor;
-                let g = g as f32 + (255.0 - g as f32) * factor;
-                let b = b as f32 + (255.0 - b as f32) * factor;
-                Color::Rgb(r as u8, g as u8, b as u8)
-            }
-            _ => color,
-        }
+        Self::blend(color, |c| c as f32 + (255.0 - c as f32) * factor)
     }
 
     /// Darken a color by blending with black
     /// factor should be between 0.0 (no change) and 1.0 (black)
     pub fn darken(color: Color, factor: f32) -> Color {
         let factor = factor.clamp(0.0, 1.0);
-        match color {
-            Color::Rgb(r, g, b) => {
-                let r = r as f32 * (1.0 - factor);
-                let g = g as f32 * (1.0 - factor);
-                let b = b as f32 * (1.0 - factor);
-                Color::Rgb(r as u8, g as u8, b as u8)
+        Self::blend(color, |c| c as f32 * (1.0 - factor))
+    }
+
+    /// Apply `f` to each RGB channel of `color`.
+    ///
+    /// Named and indexed colors are resolved to RGB first and the result is
+    /// mapped back to the nearest entry of the 256 color palette, so a theme
+    /// built for a 256 color terminal stays within it. `Reset` has no known
+    /// value and is returned unchanged.
+    fn blend(color: Color, f: impl Fn(u8) -> f32) -> Color {
+        match Self::to_rgb(color) {
+            Some((r, g, b)) => {
+                let (r, g, b) = (f(r) as u8, f(g) as u8, f(b) as u8);
+                match color {
+                    Color::Rgb(..) => Color::Rgb(r, g, b),
+                    _ => Color::Indexed(Self::nearest_indexed(r, g, b)),
+                }
             }
-            _ => color,
+            None => color,
+        }
+    }
+
+    /// Resolve a color to RGB using the xterm default palette.
+    pub fn to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+        const ANSI: [(u8, u8, u8); 16] = [
+            (0, 0, 0),
+            (128, 0, 0),
+            (0, 128, 0),
+            (128, 128, 0),
+            (0, 0, 128),
+            (128, 0, 128),
+            (0, 128, 128),
+            (192, 192, 192),
+            (128, 128, 128),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (0, 0, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        let index = match color {
+            Color::Reset => return None,
+            Color::Rgb(r, g, b) => return Some((r, g, b)),
+            Color::Indexed(i) => i,
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::Gray => 7,
+            Color::DarkGray => 8,
+            Color::LightRed => 9,
+            Color::LightGreen => 10,
+            Color::LightYellow => 11,
+            Color::LightBlue => 12,
+            Color::LightMagenta => 13,
+            Color::LightCyan => 14,
+            Color::White => 15,
+        };
+        Some(match index {
+            0..=15 => ANSI[index as usize],
+            16..=231 => {
+                let i = index - 16;
+                let level = |n: u8| CUBE_LEVELS[n as usize];
+                (level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            232..=255 => {
+                let v = 8 + 10 * (index - 232);
+                (v, v, v)
+            }
+        })
+    }
+
+    /// Find the closest color in the 6x6x6 cube or grayscale ramp of the 256
+    /// color palette.
+    pub fn nearest_indexed(r: u8, g: u8, b: u8) -> u8 {
+        let nearest_level = |c: u8| {
+            (0..CUBE_LEVELS.len())
+                .min_by_key(|&i| CUBE_LEVELS[i].abs_diff(c))
+                .unwrap_or_default() as u8
+        };
+        let distance = |(r2, g2, b2): (u8, u8, u8)| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(r, r2) + d(g, g2) + d(b, b2)
+        };
+        let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+        let cube = 16 + 36 * ri + 6 * gi + bi;
+        let average = (r as u32 + g as u32 + b as u32) / 3;
+        let gray = 232 + ((average.saturating_sub(3)) / 10).min(23) as u8;
+        let rgb = |i| Self::to_rgb(Color::Indexed(i)).unwrap_or_default();
+        if distance(rgb(gray)) < distance(rgb(cube)) {
+            gray
+        } else {
+            cube
         }
     }
 }
 
+/// Channel intensities of the 6x6x6 color cube in the 256 color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,4 +225,38 @@ mod tests {
         let darkened = Theme::darken(white, 0.5);
         assert_eq!(darkened, Color::Rgb(127, 127, 127));
     }
+
+    #[test]
+    fn test_border() {
+        let base = Color::Rgb(0, 200, 100);
+        assert_eq!(Theme::border(base, true), Color::Rgb(102, 222, 162));
+        assert_eq!(Theme::border(base, false), Color::Rgb(0, 100, 50));
+    }
+
+    #[test]
+    fn test_to_rgb() {
+        assert_eq!(Theme::to_rgb(Color::Red), Some((128, 0, 0)));
+        assert_eq!(Theme::to_rgb(Color::Indexed(9)), Some((255, 0, 0)));
+        assert_eq!(Theme::to_rgb(Color::Indexed(16)), Some((0, 0, 0)));
+        assert_eq!(Theme::to_rgb(Color::Indexed(231)), Some((255, 255, 255)));
+        assert_eq!(Theme::to_rgb(Color::Indexed(196)), Some((255, 0, 0)));
+        assert_eq!(Theme::to_rgb(Color::Indexed(244)), Some((128, 128, 128)));
+        assert_eq!(Theme::to_rgb(Color::Reset), None);
+    }
+
+    #[test]
+    fn test_nearest_indexed() {
+        assert_eq!(Theme::nearest_indexed(255, 0, 0), 196);
+        assert_eq!(Theme::nearest_indexed(0, 0, 0), 16);
+        assert_eq!(Theme::nearest_indexed(128, 128, 128), 244);
+        assert_eq!(Theme::nearest_indexed(100, 140, 170), 67);
+    }
+
+    #[test]
+    fn test_blend_named_and_indexed() {
+        assert_eq!(Theme::lighten(Color::Black, 0.5), Color::Indexed(244));
+        assert_eq!(Theme::darken(Color::Indexed(231), 0.5), Color::Indexed(244));
+        assert_eq!(Theme::darken(Color::LightRed, 1.0), Color::Indexed(16));
+        assert_eq!(Theme::lighten(Color::Reset, 0.5), Color::Reset);
+    }
 }
@@ -0,0 +1,150 @@
+//! The terminal window title, summarising process states for tabs and taskbars.
+
+use std::io::{self, stdout};
+
+use ratatui::crossterm::{execute, terminal::SetTitle};
+
+use crate::proc::process::{Process, ProcessRestart, ProcessState};
+
+/// Title text such as `procli: 4↑ 1✗`.
+///
+/// Failures are the states drawn in the error colour on the cards: failed to
+/// start, died and waiting to be restarted, or died with no restart to come.
+/// Processes stopped on request don't count.
+pub fn title<'a>(processes: impl Iterator<Item = &'a Process>) -> String {
+    let (mut up, mut failed) = (0, 0);
+    for proc in processes {
+        match &proc.state {
+            ProcessState::Running => up += 1,
+            ProcessState::Failed(_) | ProcessState::Stopped(ProcessRestart::RestartAt(_), _) => {
+                failed += 1
+            }
+            ProcessState::Stopped(ProcessRestart::NoRestart, status)
+                if !proc.stopped_on_request && !proc.is_clean_exit(status) =>
+            {
+                failed += 1
+            }
+            _ => {}
+        }
+    }
+    if failed > 0 {
+        format!("procli: {up}↑ {failed}✗")
+    } else {
+        format!("procli: {up}↑")
+    }
+}
+
+/// Keeps the terminal title in step with the processes, restoring the
+/// previous title when dropped.
+#[derive(Debug, Default)]
+pub struct TerminalTitle {
+    current: Option<String>,
+}
+
+impl TerminalTitle {
+    /// Set the title if it differs from the last one set.
+    pub fn update(&mut self, title: String) -> io::Result<()> {
+        if self.current.as_ref() == Some(&title) {
+            return Ok(());
+        }
+        if self.current.is_none() {
+            save_title()?;
+        }
+        execute!(stdout(), SetTitle(&title))?;
+        self.current = Some(title);
+        Ok(())
+    }
+
+    /// Put back the title from before the first update, if there was one.
+    pub fn reset(&mut self) -> io::Result<()> {
+        if self.current.take().is_some() {
+            restore_title()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TerminalTitle {
+    fn drop(&mut self) {
+        let _ = self.reset();
+    }
+}
+
+/// Push the current title onto the xterm title stack.
+fn save_title() -> io::Result<()> {
+    #[cfg(not(windows))]
+    {
+        use std::io::Write;
+        let mut out = stdout();
+        out.write_all(b"\x1b[22;0t")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Pop the title saved by [`save_title`]. Terminals without a title stack
+/// keep the procli title.
+fn restore_title() -> io::Result<()> {
+    #[cfg(not(windows))]
+    {
+        use std::io::Write;
+        let mut out = stdout();
+        out.write_all(b"\x1b[23;0t")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Service;
+
+    fn processes(states: impl IntoIterator<Item = ProcessState>) -> Vec<Process> {
+        let svc = Service {
+            name: "api".to_string(),
+            command: Some("./api".to_string()),
+            ..Default::default()
+        };
+        states
+            .into_iter()
+            .map(|state| {
+                let mut proc = Process::new(&svc).unwrap();
+                proc.state = state;
+                proc
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_running_processes() {
+        let procs = processes([
+            ProcessState::Running,
+            ProcessState::Starting,
+            ProcessState::Running,
+        ]);
+        assert_eq!(title(procs.iter()), "procli: 2↑");
+    }
+
+    #[test]
+    fn counts_failed_processes() {
+        let procs = processes([
+            ProcessState::Running,
+            ProcessState::Failed("not found".to_string()),
+        ]);
+        assert_eq!(title(procs.iter()), "procli: 1↑ 1✗");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_crashes_with_no_restart_but_not_stops() {
+        use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+        let stopped =
+            |code| ProcessState::Stopped(ProcessRestart::NoRestart, ExitStatus::from_raw(code));
+        let mut procs = processes([stopped(1 << 8), stopped(15), stopped(0)]);
+        assert_eq!(title(procs.iter()), "procli: 0↑ 2✗");
+        procs[1].stopped_on_request = true;
+        assert_eq!(title(procs.iter()), "procli: 0↑ 1✗");
+    }
+}
@@ -0,0 +1,95 @@
+//! A check, at startup, for a newer procli release.
+
+use log::*;
+use semver::Version;
+use std::{process::Stdio, time::Duration};
+use tokio::process::Command;
+
+/// Where releases are looked up when the config doesn't say.
+pub const DEFAULT_RELEASE_URL: &str =
+    "https://api.github.com/repos/stackfull/procli/releases/latest";
+
+/// How long to wait for the release URL before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The version named in a release URL's response: either a JSON object with a
+/// `tag_name` (as GitHub serves) or `version` field, or just the version as
+/// text. A leading `v` is allowed.
+pub fn published_version(body: &str) -> Option<Version> {
+    let text = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json
+            .get("tag_name")
+            .or(json.get("version"))?
+            .as_str()?
+            .to_string(),
+        Err(_) => body.to_string(),
+    };
+    let text = text.trim();
+    Version::parse(text.strip_prefix('v').unwrap_or(text)).ok()
+}
+
+/// Whether `published` is newer than the `current` version. Anything that
+/// doesn't parse is never newer.
+pub fn is_newer(current: &str, published: &Version) -> bool {
+    Version::parse(current).is_ok_and(|current| *published > current)
+}
+
+/// Look up the latest release at `url` in the background and mention it once
+/// if it's newer than this build. Never holds up startup, and says nothing if
+/// the lookup fails.
+pub fn spawn(url: String) {
+    tokio::spawn(async move {
+        match fetch(&url).await {
+            Some(published) if is_newer(env!("CARGO_PKG_VERSION"), &published) => {
+                info!(target: "App", "procli {} is available (this is {})", published, env!("CARGO_PKG_VERSION"));
+            }
+            Some(_) => debug!("procli is up to date"),
+            None => debug!("Couldn't check {} for a newer procli", url),
+        }
+    });
+}
+
+async fn fetch(url: &str) -> Option<Version> {
+    let output = Command::new("curl")
+        .args(["--silent", "--fail", "--location", "--max-time"])
+        .arg(TIMEOUT.as_secs().to_string())
+        .arg(url)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(TIMEOUT * 2, output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    published_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_read_from_json_or_text() {
+        let v = |s: &str| Some(Version::parse(s).unwrap());
+        assert_eq!(published_version(r#"{"tag_name":"v0.2.0"}"#), v("0.2.0"));
+        assert_eq!(
+            published_version(r#"{"version":"1.0.0-rc.1"}"#),
+            v("1.0.0-rc.1")
+        );
+        assert_eq!(published_version("0.3.1\n"), v("0.3.1"));
+        assert_eq!(published_version(r#"{"name":"latest"}"#), None);
+        assert_eq!(published_version("<html>"), None);
+    }
+
+    #[test]
+    fn only_later_versions_are_newer() {
+        let newer = |current, published| is_newer(current, &Version::parse(published).unwrap());
+        assert!(newer("0.1.1", "0.1.2"));
+        assert!(newer("0.1.1", "0.10.0"));
+        assert!(!newer("0.1.1", "0.1.1"));
+        assert!(!newer("0.2.0", "0.1.9"));
+        assert!(!newer("0.2.0", "0.2.0-beta.1"));
+        assert!(!newer("not a version", "9.9.9"));
+    }
+}